@@ -2,5 +2,12 @@
 
 pub mod prelude;
 
+pub mod audit_events;
+pub mod lists;
+pub mod preference_tokens;
+pub mod subscriber_tags;
+pub mod subscription_lists;
 pub mod subscription_tokens;
 pub mod subscriptions;
+pub mod tenants;
+pub mod users;
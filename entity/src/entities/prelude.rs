@@ -1,4 +1,11 @@
 //! `SeaORM` Entity, @generated by sea-orm-codegen 1.1.3
 
+pub use super::audit_events::Entity as AuditEvents;
+pub use super::lists::Entity as Lists;
+pub use super::preference_tokens::Entity as PreferenceTokens;
+pub use super::subscriber_tags::Entity as SubscriberTags;
+pub use super::subscription_lists::Entity as SubscriptionLists;
 pub use super::subscription_tokens::Entity as SubscriptionTokens;
 pub use super::subscriptions::Entity as Subscriptions;
+pub use super::tenants::Entity as Tenants;
+pub use super::users::Entity as Users;
@@ -8,20 +8,35 @@ pub struct Model {
     #[sea_orm(primary_key, auto_increment = false, column_type = "Text")]
     pub subscription_token: String,
     pub subscriber_id: Uuid,
+    pub list_id: Uuid,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::lists::Entity",
+        from = "Column::ListId",
+        to = "super::lists::Column::Id",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Lists,
     #[sea_orm(
         belongs_to = "super::subscriptions::Entity",
         from = "Column::SubscriberId",
         to = "super::subscriptions::Column::Id",
         on_update = "NoAction",
-        on_delete = "NoAction"
+        on_delete = "Cascade"
     )]
     Subscriptions,
 }
 
+impl Related<super::lists::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Lists.def()
+    }
+}
+
 impl Related<super::subscriptions::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Subscriptions.def()
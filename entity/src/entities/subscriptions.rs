@@ -7,19 +7,56 @@ use sea_orm::entity::prelude::*;
 pub struct Model {
     #[sea_orm(primary_key, auto_increment = false)]
     pub id: Uuid,
-    #[sea_orm(column_type = "Text", unique)]
+    #[sea_orm(column_type = "Text")]
     pub email: String,
     #[sea_orm(column_type = "Text")]
+    pub ascii_email: String,
+    #[sea_orm(column_type = "Text")]
+    pub normalized_email: String,
+    #[sea_orm(column_type = "Text")]
     pub name: String,
     pub subscribed_at: DateTimeWithTimeZone,
     #[sea_orm(column_type = "Text")]
     pub status: String,
+    pub tenant_id: Uuid,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_one = "super::preference_tokens::Entity")]
+    PreferenceTokens,
+    #[sea_orm(has_many = "super::subscriber_tags::Entity")]
+    SubscriberTags,
+    #[sea_orm(has_many = "super::subscription_lists::Entity")]
+    SubscriptionLists,
     #[sea_orm(has_many = "super::subscription_tokens::Entity")]
     SubscriptionTokens,
+    #[sea_orm(
+        belongs_to = "super::tenants::Entity",
+        from = "Column::TenantId",
+        to = "super::tenants::Column::Id",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Tenants,
+}
+
+impl Related<super::preference_tokens::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::PreferenceTokens.def()
+    }
+}
+
+impl Related<super::subscriber_tags::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::SubscriberTags.def()
+    }
+}
+
+impl Related<super::subscription_lists::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::SubscriptionLists.def()
+    }
 }
 
 impl Related<super::subscription_tokens::Entity> for Entity {
@@ -28,4 +65,23 @@ impl Related<super::subscription_tokens::Entity> for Entity {
     }
 }
 
+impl Related<super::tenants::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Tenants.def()
+    }
+}
+
+impl Related<super::lists::Entity> for Entity {
+    fn to() -> RelationDef {
+        super::subscription_lists::Relation::Lists.def()
+    }
+    fn via() -> Option<RelationDef> {
+        Some(
+            super::subscription_lists::Relation::Subscriptions
+                .def()
+                .rev(),
+        )
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
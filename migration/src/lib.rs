@@ -1,6 +1,18 @@
 mod m20250107_122803_create_subscriptions_table;
 mod m20250112_124700_create_subscription_tokens_table;
+mod m20250420_093000_create_lists_table;
+mod m20250503_181500_create_preference_tokens_table;
+mod m20250511_140000_create_subscriber_tags_table;
+mod m20250518_101500_create_users_table;
+mod m20250525_120000_cascade_subscription_tokens;
+mod m20250601_090000_create_audit_events_table;
+mod m20250608_100000_add_normalized_email;
+mod m20250615_090000_add_ascii_email;
+mod m20250622_090000_create_tenants_table;
+mod sqlite;
 
+pub use m20250420_093000_create_lists_table::{DEFAULT_LIST_ID, DEFAULT_LIST_NAME};
+pub use m20250622_090000_create_tenants_table::{DEFAULT_TENANT_ID, DEFAULT_TENANT_SLUG};
 pub use sea_orm_migration::prelude::*;
 
 pub struct Migrator;
@@ -11,6 +23,15 @@ impl MigratorTrait for Migrator {
         vec![
             Box::new(m20250107_122803_create_subscriptions_table::Migration),
             Box::new(m20250112_124700_create_subscription_tokens_table::Migration),
+            Box::new(m20250420_093000_create_lists_table::Migration),
+            Box::new(m20250503_181500_create_preference_tokens_table::Migration),
+            Box::new(m20250511_140000_create_subscriber_tags_table::Migration),
+            Box::new(m20250518_101500_create_users_table::Migration),
+            Box::new(m20250525_120000_cascade_subscription_tokens::Migration),
+            Box::new(m20250601_090000_create_audit_events_table::Migration),
+            Box::new(m20250608_100000_add_normalized_email::Migration),
+            Box::new(m20250615_090000_add_ascii_email::Migration),
+            Box::new(m20250622_090000_create_tenants_table::Migration),
         ]
     }
 }
@@ -1,3 +1,4 @@
+use sea_orm_migration::sea_orm::DatabaseBackend;
 use sea_orm_migration::{prelude::*, schema::*};
 
 use crate::m20250107_122803_create_subscriptions_table::Subscriptions;
@@ -9,25 +10,27 @@ pub struct Migration;
 impl MigrationTrait for Migration {
     async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
         // Replace the sample below with your own migration scripts
-        manager
-            .create_table(
-                Table::create()
-                    .table(SubscriptionTokens::Table)
-                    .if_not_exists()
-                    .col(text(SubscriptionTokens::SubscriptionToken).primary_key())
-                    .col(uuid(SubscriptionTokens::SubscriberId))
-                    .to_owned(),
-            )
-            .await?;
+        let mut table = Table::create()
+            .table(SubscriptionTokens::Table)
+            .if_not_exists()
+            .col(text(SubscriptionTokens::SubscriptionToken).primary_key())
+            .col(uuid(SubscriptionTokens::SubscriberId))
+            .to_owned();
+        let mut foreign_key = ForeignKey::create()
+            .from(SubscriptionTokens::Table, SubscriptionTokens::SubscriberId)
+            .to(Subscriptions::Table, Subscriptions::Id)
+            .to_owned();
 
-        manager
-            .create_foreign_key(
-                ForeignKey::create()
-                    .from(SubscriptionTokens::Table, SubscriptionTokens::SubscriberId)
-                    .to(Subscriptions::Table, Subscriptions::Id)
-                    .to_owned(),
-            )
-            .await
+        // SQLite can only declare foreign keys along with the table
+        if manager.get_database_backend() == DatabaseBackend::Sqlite {
+            return manager
+                .create_table(table.foreign_key(&mut foreign_key).to_owned())
+                .await;
+        }
+
+        manager.create_table(table).await?;
+
+        manager.create_foreign_key(foreign_key).await
     }
 
     async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
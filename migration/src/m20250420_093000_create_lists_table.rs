@@ -0,0 +1,217 @@
+use sea_orm_migration::sea_orm::prelude::Uuid;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::m20250107_122803_create_subscriptions_table::Subscriptions;
+use crate::sqlite;
+
+/// Identifier of the list every pre-existing subscriber is migrated into.
+pub const DEFAULT_LIST_ID: Uuid = Uuid::from_u128(0x0196_5167_ca4b_7c1e_9b1c_3f43_9a5d_0001);
+pub const DEFAULT_LIST_NAME: &str = "default";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Lists::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Lists::Id))
+                    .col(text_uniq(Lists::Name))
+                    .col(
+                        timestamp_with_time_zone(Lists::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .exec_stmt(
+                Query::insert()
+                    .into_table(Lists::Table)
+                    .columns([Lists::Id, Lists::Name])
+                    .values_panic([DEFAULT_LIST_ID.into(), DEFAULT_LIST_NAME.into()])
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(SubscriptionLists::Table)
+                    .if_not_exists()
+                    .col(uuid(SubscriptionLists::SubscriberId))
+                    .col(uuid(SubscriptionLists::ListId))
+                    .col(text(SubscriptionLists::Status).default("pending_confirmation"))
+                    .col(timestamp_with_time_zone(SubscriptionLists::SubscribedAt))
+                    .primary_key(
+                        Index::create()
+                            .col(SubscriptionLists::SubscriberId)
+                            .col(SubscriptionLists::ListId),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(SubscriptionLists::Table, SubscriptionLists::SubscriberId)
+                            .to(Subscriptions::Table, Subscriptions::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(SubscriptionLists::Table, SubscriptionLists::ListId)
+                            .to(Lists::Table, Lists::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Move every existing subscriber into the default list, keeping their confirmation status
+        manager
+            .exec_stmt(
+                Query::insert()
+                    .into_table(SubscriptionLists::Table)
+                    .columns([
+                        SubscriptionLists::SubscriberId,
+                        SubscriptionLists::ListId,
+                        SubscriptionLists::Status,
+                        SubscriptionLists::SubscribedAt,
+                    ])
+                    .select_from(
+                        Query::select()
+                            .column(Subscriptions::Id)
+                            .expr(Expr::val(DEFAULT_LIST_ID))
+                            .column(Subscriptions::Status)
+                            .column(Subscriptions::SubscribedAt)
+                            .from(Subscriptions::Table)
+                            .to_owned(),
+                    )
+                    .map_err(|e| DbErr::Migration(e.to_string()))?
+                    .to_owned(),
+            )
+            .await?;
+
+        // Tokens issued before lists existed confirm the default list
+        if manager.get_database_backend() == DatabaseBackend::Sqlite {
+            return sqlite::rebuild_table(
+                manager,
+                SubscriptionTokens::Table,
+                |table| {
+                    subscription_tokens(table.clone())
+                        .col(uuid(SubscriptionTokens::ListId))
+                        .foreign_key(
+                            ForeignKey::create()
+                                .from(table, SubscriptionTokens::ListId)
+                                .to(Lists::Table, Lists::Id)
+                                .on_delete(ForeignKeyAction::Cascade),
+                        )
+                        .to_owned()
+                },
+                vec![
+                    sqlite::keep(SubscriptionTokens::SubscriptionToken),
+                    sqlite::keep(SubscriptionTokens::SubscriberId),
+                    (
+                        SubscriptionTokens::ListId.into_iden(),
+                        Expr::val(DEFAULT_LIST_ID).into(),
+                    ),
+                ],
+            )
+            .await;
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(SubscriptionTokens::Table)
+                    .add_column(uuid(SubscriptionTokens::ListId).default(DEFAULT_LIST_ID))
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name("fk_subscription_tokens_list_id")
+                    .from(SubscriptionTokens::Table, SubscriptionTokens::ListId)
+                    .to(Lists::Table, Lists::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() == DatabaseBackend::Sqlite {
+            sqlite::rebuild_table(
+                manager,
+                SubscriptionTokens::Table,
+                |table| subscription_tokens(table).to_owned(),
+                vec![
+                    sqlite::keep(SubscriptionTokens::SubscriptionToken),
+                    sqlite::keep(SubscriptionTokens::SubscriberId),
+                ],
+            )
+            .await?;
+        } else {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(SubscriptionTokens::Table)
+                        .drop_column(SubscriptionTokens::ListId)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        manager
+            .drop_table(Table::drop().table(SubscriptionLists::Table).to_owned())
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(Lists::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+pub(crate) enum Lists {
+    Table,
+    Id,
+    Name,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+pub(crate) enum SubscriptionLists {
+    Table,
+    SubscriberId,
+    ListId,
+    Status,
+    SubscribedAt,
+}
+
+/// `subscription_tokens` as created before lists existed, for SQLite to rebuild it from.
+fn subscription_tokens(table: DynIden) -> TableCreateStatement {
+    Table::create()
+        .table(table.clone())
+        .col(text(SubscriptionTokens::SubscriptionToken).primary_key())
+        .col(uuid(SubscriptionTokens::SubscriberId))
+        .foreign_key(
+            ForeignKey::create()
+                .from(table, SubscriptionTokens::SubscriberId)
+                .to(Subscriptions::Table, Subscriptions::Id),
+        )
+        .to_owned()
+}
+
+#[derive(DeriveIden)]
+enum SubscriptionTokens {
+    Table,
+    SubscriptionToken,
+    SubscriberId,
+    ListId,
+}
@@ -0,0 +1,41 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::m20250107_122803_create_subscriptions_table::Subscriptions;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(PreferenceTokens::Table)
+                    .if_not_exists()
+                    .col(text(PreferenceTokens::PreferenceToken).primary_key())
+                    .col(uuid_uniq(PreferenceTokens::SubscriberId))
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(PreferenceTokens::Table, PreferenceTokens::SubscriberId)
+                            .to(Subscriptions::Table, Subscriptions::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(PreferenceTokens::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum PreferenceTokens {
+    Table,
+    PreferenceToken,
+    SubscriberId,
+}
@@ -0,0 +1,57 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::m20250107_122803_create_subscriptions_table::Subscriptions;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SubscriberTags::Table)
+                    .if_not_exists()
+                    .col(uuid(SubscriberTags::SubscriberId))
+                    .col(text(SubscriberTags::Tag))
+                    .primary_key(
+                        Index::create()
+                            .col(SubscriberTags::SubscriberId)
+                            .col(SubscriberTags::Tag),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from(SubscriberTags::Table, SubscriberTags::SubscriberId)
+                            .to(Subscriptions::Table, Subscriptions::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Segment queries look subscribers up by tag
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_subscriber_tags_tag")
+                    .table(SubscriberTags::Table)
+                    .col(SubscriberTags::Tag)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(SubscriberTags::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum SubscriberTags {
+    Table,
+    SubscriberId,
+    Tag,
+}
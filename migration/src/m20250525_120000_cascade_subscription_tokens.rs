@@ -0,0 +1,113 @@
+use sea_orm_migration::sea_orm::DatabaseBackend;
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::m20250107_122803_create_subscriptions_table::Subscriptions;
+use crate::m20250420_093000_create_lists_table::Lists;
+use crate::sqlite;
+
+/// Name Postgres gave the unnamed foreign key created alongside `subscription_tokens`.
+const LEGACY_FOREIGN_KEY: &str = "subscription_tokens_subscriber_id_fkey";
+const FOREIGN_KEY: &str = "fk_subscription_tokens_subscriber_id";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Erasing a subscriber must take their confirmation tokens with them
+        if manager.get_database_backend() == DatabaseBackend::Sqlite {
+            return rebuild_subscription_tokens(manager, ForeignKeyAction::Cascade).await;
+        }
+
+        manager
+            .drop_foreign_key(
+                ForeignKey::drop()
+                    .name(LEGACY_FOREIGN_KEY)
+                    .table(SubscriptionTokens::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name(FOREIGN_KEY)
+                    .from(SubscriptionTokens::Table, SubscriptionTokens::SubscriberId)
+                    .to(Subscriptions::Table, Subscriptions::Id)
+                    .on_delete(ForeignKeyAction::Cascade)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() == DatabaseBackend::Sqlite {
+            return rebuild_subscription_tokens(manager, ForeignKeyAction::NoAction).await;
+        }
+
+        manager
+            .drop_foreign_key(
+                ForeignKey::drop()
+                    .name(FOREIGN_KEY)
+                    .table(SubscriptionTokens::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_foreign_key(
+                ForeignKey::create()
+                    .name(LEGACY_FOREIGN_KEY)
+                    .from(SubscriptionTokens::Table, SubscriptionTokens::SubscriberId)
+                    .to(Subscriptions::Table, Subscriptions::Id)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// SQLite cannot swap the foreign key in place, so the whole table is rebuilt around it.
+async fn rebuild_subscription_tokens(
+    manager: &SchemaManager<'_>,
+    on_subscriber_delete: ForeignKeyAction,
+) -> Result<(), DbErr> {
+    sqlite::rebuild_table(
+        manager,
+        SubscriptionTokens::Table,
+        |table| {
+            Table::create()
+                .table(table.clone())
+                .col(text(SubscriptionTokens::SubscriptionToken).primary_key())
+                .col(uuid(SubscriptionTokens::SubscriberId))
+                .col(uuid(SubscriptionTokens::ListId))
+                .foreign_key(
+                    ForeignKey::create()
+                        .from(table.clone(), SubscriptionTokens::SubscriberId)
+                        .to(Subscriptions::Table, Subscriptions::Id)
+                        .on_delete(on_subscriber_delete),
+                )
+                .foreign_key(
+                    ForeignKey::create()
+                        .from(table, SubscriptionTokens::ListId)
+                        .to(Lists::Table, Lists::Id)
+                        .on_delete(ForeignKeyAction::Cascade),
+                )
+                .to_owned()
+        },
+        vec![
+            sqlite::keep(SubscriptionTokens::SubscriptionToken),
+            sqlite::keep(SubscriptionTokens::SubscriberId),
+            sqlite::keep(SubscriptionTokens::ListId),
+        ],
+    )
+    .await
+}
+
+#[derive(DeriveIden)]
+enum SubscriptionTokens {
+    Table,
+    SubscriptionToken,
+    SubscriberId,
+    ListId,
+}
@@ -0,0 +1,109 @@
+use sea_orm_migration::sea_orm::DatabaseBackend;
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(AuditEvents::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(AuditEvents::Id))
+                    .col(
+                        timestamp_with_time_zone(AuditEvents::OccurredAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .col(uuid(AuditEvents::ActorId))
+                    .col(text(AuditEvents::Actor))
+                    .col(text(AuditEvents::Action))
+                    .col(text_null(AuditEvents::Target))
+                    .col(text(AuditEvents::RequestId))
+                    .col(json_binary_null(AuditEvents::Diff))
+                    .to_owned(),
+            )
+            .await?;
+
+        // The query endpoint lists the newest events first
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_audit_events_occurred_at")
+                    .table(AuditEvents::Table)
+                    .col(AuditEvents::OccurredAt)
+                    .col(AuditEvents::Id)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Recorded events can never be rewritten, not even by the application itself
+        let db = manager.get_connection();
+        if manager.get_database_backend() == DatabaseBackend::Sqlite {
+            for (trigger, event) in [
+                ("audit_events_no_update", "UPDATE"),
+                ("audit_events_no_delete", "DELETE"),
+            ] {
+                db.execute_unprepared(&format!(
+                    "CREATE TRIGGER {} BEFORE {} ON audit_events
+                    BEGIN
+                        SELECT RAISE(ABORT, 'audit_events is append-only');
+                    END",
+                    trigger, event
+                ))
+                .await?;
+            }
+
+            return Ok(());
+        }
+        db.execute_unprepared(
+            "CREATE FUNCTION audit_events_append_only() RETURNS trigger AS $$
+            BEGIN
+                RAISE EXCEPTION 'audit_events is append-only';
+            END;
+            $$ LANGUAGE plpgsql",
+        )
+        .await?;
+        db.execute_unprepared(
+            "CREATE TRIGGER audit_events_append_only
+            BEFORE UPDATE OR DELETE ON audit_events
+            FOR EACH ROW EXECUTE FUNCTION audit_events_append_only()",
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(AuditEvents::Table).to_owned())
+            .await?;
+
+        // SQLite triggers go away with their table
+        if manager.get_database_backend() == DatabaseBackend::Sqlite {
+            return Ok(());
+        }
+
+        manager
+            .get_connection()
+            .execute_unprepared("DROP FUNCTION audit_events_append_only()")
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(DeriveIden)]
+enum AuditEvents {
+    Table,
+    Id,
+    OccurredAt,
+    ActorId,
+    Actor,
+    Action,
+    Target,
+    RequestId,
+    Diff,
+}
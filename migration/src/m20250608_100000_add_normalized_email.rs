@@ -0,0 +1,172 @@
+use std::collections::BTreeMap;
+
+use sea_orm_migration::sea_orm::prelude::Uuid;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::m20250107_122803_create_subscriptions_table::Subscriptions;
+
+/// Name Postgres gave the unique constraint created by `text_uniq` on `subscriptions.email`.
+const EMAIL_UNIQUE_CONSTRAINT: &str = "subscriptions_email_key";
+const NORMALIZED_EMAIL_INDEX: &str = "idx_subscriptions_normalized_email";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let backend = manager.get_database_backend();
+
+        let rows = db
+            .query_all(
+                backend.build(
+                    Query::select()
+                        .columns([Subscriptions::Id, Subscriptions::Email])
+                        .from(Subscriptions::Table),
+                ),
+            )
+            .await?;
+
+        let mut subscribers: BTreeMap<String, Vec<(Uuid, String)>> = BTreeMap::new();
+        for row in rows {
+            let id: Uuid = row.try_get("", "id")?;
+            let email: String = row.try_get("", "email")?;
+            subscribers
+                .entry(normalize_email(&email))
+                .or_default()
+                .push((id, email));
+        }
+
+        // Merging subscribers means picking whose lists, tags and status win, which is not
+        // a call a migration should make
+        let duplicates: Vec<String> = subscribers
+            .values()
+            .filter(|group| group.len() > 1)
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|(id, email)| format!("{} ({})", email, id))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .collect();
+        if !duplicates.is_empty() {
+            return Err(DbErr::Migration(format!(
+                "Found {} groups of subscribers that only differ by the case or encoding of their \
+                email, merge or delete them before migrating: {}",
+                duplicates.len(),
+                duplicates.join("; ")
+            )));
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Subscriptions::Table)
+                    .add_column(text_null(SubscriptionEmails::NormalizedEmail))
+                    .to_owned(),
+            )
+            .await?;
+
+        for (normalized_email, group) in subscribers {
+            let (id, _) = &group[0];
+            manager
+                .exec_stmt(
+                    Query::update()
+                        .table(Subscriptions::Table)
+                        .value(SubscriptionEmails::NormalizedEmail, normalized_email)
+                        .and_where(Expr::col(Subscriptions::Id).eq(*id))
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        // SQLite cannot alter a column, the application always sets it anyway
+        if backend != DatabaseBackend::Sqlite {
+            manager
+                .alter_table(
+                    Table::alter()
+                        .table(Subscriptions::Table)
+                        .modify_column(text(SubscriptionEmails::NormalizedEmail))
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        manager
+            .create_index(
+                Index::create()
+                    .name(NORMALIZED_EMAIL_INDEX)
+                    .table(Subscriptions::Table)
+                    .col(SubscriptionEmails::NormalizedEmail)
+                    .unique()
+                    .to_owned(),
+            )
+            .await?;
+
+        // Uniqueness is now enforced on the normalized address. SQLite cannot drop the
+        // constraint without rebuilding a table half the schema references, and it is implied
+        // by the one on the normalized address anyway
+        if backend == DatabaseBackend::Sqlite {
+            return Ok(());
+        }
+        db.execute_unprepared(&format!(
+            "ALTER TABLE subscriptions DROP CONSTRAINT {}",
+            EMAIL_UNIQUE_CONSTRAINT
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        if manager.get_database_backend() != DatabaseBackend::Sqlite {
+            manager
+                .get_connection()
+                .execute_unprepared(&format!(
+                    "ALTER TABLE subscriptions ADD CONSTRAINT {} UNIQUE (email)",
+                    EMAIL_UNIQUE_CONSTRAINT
+                ))
+                .await?;
+        }
+
+        // SQLite refuses to drop an indexed column
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(NORMALIZED_EMAIL_INDEX)
+                    .table(Subscriptions::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Subscriptions::Table)
+                    .drop_column(SubscriptionEmails::NormalizedEmail)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Mirrors `SubscriberEmail::normalized` in the application, as of this migration.
+fn normalize_email(email: &str) -> String {
+    let email = email.trim();
+
+    match email.rsplit_once('@') {
+        Some((local_part, domain)) => {
+            let domain = idna::domain_to_ascii(domain).unwrap_or_else(|_| domain.to_string());
+            format!("{}@{}", local_part, domain).to_lowercase()
+        }
+        None => email.to_lowercase(),
+    }
+}
+
+#[derive(DeriveIden)]
+enum SubscriptionEmails {
+    NormalizedEmail,
+}
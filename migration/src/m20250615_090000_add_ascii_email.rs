@@ -0,0 +1,161 @@
+use std::collections::BTreeMap;
+
+use sea_orm_migration::sea_orm::prelude::Uuid;
+use sea_orm_migration::sea_orm::DatabaseBackend;
+use sea_orm_migration::{prelude::*, schema::*};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::m20250107_122803_create_subscriptions_table::Subscriptions;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let backend = manager.get_database_backend();
+
+        let rows = db
+            .query_all(
+                backend.build(
+                    Query::select()
+                        .columns([Subscriptions::Id, Subscriptions::Email])
+                        .from(Subscriptions::Table),
+                ),
+            )
+            .await?;
+
+        let mut subscribers: BTreeMap<String, Vec<(Uuid, String, EmailForms)>> = BTreeMap::new();
+        for row in rows {
+            let id: Uuid = row.try_get("", "id")?;
+            let email: String = row.try_get("", "email")?;
+            let forms = email_forms(&email);
+            subscribers
+                .entry(forms.normalized.clone())
+                .or_default()
+                .push((id, email, forms));
+        }
+
+        // The normalized addresses backfilled so far kept the local part as typed, addresses
+        // only differing by its Unicode encoding collide once it is brought to NFC
+        let duplicates: Vec<String> = subscribers
+            .values()
+            .filter(|group| group.len() > 1)
+            .map(|group| {
+                group
+                    .iter()
+                    .map(|(id, email, _)| format!("{} ({})", email, id))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .collect();
+        if !duplicates.is_empty() {
+            return Err(DbErr::Migration(format!(
+                "Found {} groups of subscribers that only differ by the case or encoding of their \
+                email, merge or delete them before migrating: {}",
+                duplicates.len(),
+                duplicates.join("; ")
+            )));
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Subscriptions::Table)
+                    .add_column(text_null(SubscriptionEmails::AsciiEmail))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Emails were stored with a punycode domain until now, `email` keeps the Unicode form
+        for (id, _, forms) in subscribers.into_values().flatten() {
+            manager
+                .exec_stmt(
+                    Query::update()
+                        .table(Subscriptions::Table)
+                        .value(Subscriptions::Email, forms.unicode)
+                        .value(SubscriptionEmails::AsciiEmail, forms.ascii)
+                        .value(SubscriptionEmails::NormalizedEmail, forms.normalized)
+                        .and_where(Expr::col(Subscriptions::Id).eq(id))
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        // SQLite cannot alter a column, the application always sets it anyway
+        if backend == DatabaseBackend::Sqlite {
+            return Ok(());
+        }
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Subscriptions::Table)
+                    .modify_column(text(SubscriptionEmails::AsciiEmail))
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .exec_stmt(
+                Query::update()
+                    .table(Subscriptions::Table)
+                    .value(
+                        Subscriptions::Email,
+                        Expr::col(SubscriptionEmails::AsciiEmail),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Subscriptions::Table)
+                    .drop_column(SubscriptionEmails::AsciiEmail)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// The forms of an address kept by `SubscriberEmail`.
+struct EmailForms {
+    unicode: String,
+    ascii: String,
+    normalized: String,
+}
+
+/// Mirrors the forms of `SubscriberEmail`, as of this migration, which brings the local part
+/// to NFC.
+fn email_forms(email: &str) -> EmailForms {
+    let email = email.trim();
+
+    match email.rsplit_once('@') {
+        Some((local_part, domain)) => {
+            let local_part: String = local_part.nfc().collect();
+            let ascii_domain = idna::domain_to_ascii(domain).unwrap_or_else(|_| domain.to_string());
+            let (unicode_domain, _) = idna::domain_to_unicode(&ascii_domain);
+            let ascii = format!("{}@{}", local_part, ascii_domain);
+            EmailForms {
+                unicode: format!("{}@{}", local_part, unicode_domain),
+                normalized: ascii.to_lowercase(),
+                ascii,
+            }
+        }
+        None => EmailForms {
+            unicode: email.to_string(),
+            ascii: email.to_string(),
+            normalized: email.to_lowercase(),
+        },
+    }
+}
+
+#[derive(DeriveIden)]
+enum SubscriptionEmails {
+    AsciiEmail,
+    NormalizedEmail,
+}
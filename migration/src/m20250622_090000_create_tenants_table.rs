@@ -0,0 +1,462 @@
+use sea_orm_migration::sea_orm::prelude::Uuid;
+use sea_orm_migration::sea_orm::{ConnectionTrait, DatabaseBackend};
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::m20250107_122803_create_subscriptions_table::Subscriptions;
+use crate::m20250420_093000_create_lists_table::Lists;
+use crate::m20250518_101500_create_users_table::Users;
+use crate::sqlite;
+
+/// Identifier of the tenant every pre-existing list, subscriber, user and audit event is
+/// migrated into.
+pub const DEFAULT_TENANT_ID: Uuid = Uuid::from_u128(0x0197_9a3e_5c00_7a4e_8f2d_6b1e_4c7a_0001);
+pub const DEFAULT_TENANT_SLUG: &str = "default";
+
+/// Name Postgres gave the unique constraint created by `text_uniq` on `lists.name`.
+const LIST_NAME_UNIQUE_CONSTRAINT: &str = "lists_name_key";
+const LIST_NAME_INDEX: &str = "idx_lists_tenant_id_name";
+const LEGACY_NORMALIZED_EMAIL_INDEX: &str = "idx_subscriptions_normalized_email";
+const NORMALIZED_EMAIL_INDEX: &str = "idx_subscriptions_tenant_id_normalized_email";
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // A NULL sender, base URL or email account falls back to the one of the configuration
+        manager
+            .create_table(
+                Table::create()
+                    .table(Tenants::Table)
+                    .if_not_exists()
+                    .col(pk_uuid(Tenants::Id))
+                    .col(text_uniq(Tenants::Slug))
+                    .col(text_null(Tenants::Host).unique_key())
+                    .col(text_null(Tenants::BaseUrl))
+                    .col(text_null(Tenants::SenderEmail))
+                    .col(text_null(Tenants::EmailAccount))
+                    .col(
+                        timestamp_with_time_zone(Tenants::CreatedAt)
+                            .default(Expr::current_timestamp()),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .exec_stmt(
+                Query::insert()
+                    .into_table(Tenants::Table)
+                    .columns([Tenants::Id, Tenants::Slug])
+                    .values_panic([DEFAULT_TENANT_ID.into(), DEFAULT_TENANT_SLUG.into()])
+                    .to_owned(),
+            )
+            .await?;
+
+        if manager.get_database_backend() == DatabaseBackend::Sqlite {
+            return sqlite_up(manager).await;
+        }
+
+        for table in [
+            Lists::Table.into_iden(),
+            Subscriptions::Table.into_iden(),
+            Users::Table.into_iden(),
+        ] {
+            add_tenant_id(manager, table.clone()).await?;
+            manager
+                .create_foreign_key(
+                    ForeignKey::create()
+                        .name(format!("fk_{}_tenant_id", table.to_string()))
+                        .from(table, TenantScoped::TenantId)
+                        .to(Tenants::Table, Tenants::Id)
+                        .on_delete(ForeignKeyAction::Cascade)
+                        .to_owned(),
+                )
+                .await?;
+        }
+
+        // Audit events outlive whatever they are about, tenants included
+        add_tenant_id(manager, AuditEvents::Table.into_iden()).await?;
+
+        // List names and subscriber addresses only need to be unique within a tenant
+        db.execute_unprepared(&format!(
+            "ALTER TABLE lists DROP CONSTRAINT {}",
+            LIST_NAME_UNIQUE_CONSTRAINT
+        ))
+        .await?;
+        manager.create_index(list_name_index()).await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name(LEGACY_NORMALIZED_EMAIL_INDEX)
+                    .table(Subscriptions::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager.create_index(normalized_email_index()).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let backend = manager.get_database_backend();
+
+        // Folding tenants together means picking whose lists and subscribers win, which is not
+        // a call a migration should make
+        let tenants = db
+            .query_all(
+                backend.build(
+                    Query::select()
+                        .column(Tenants::Slug)
+                        .from(Tenants::Table)
+                        .and_where(Expr::col(Tenants::Id).ne(DEFAULT_TENANT_ID)),
+                ),
+            )
+            .await?
+            .into_iter()
+            .map(|row| row.try_get::<String>("", "slug"))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !tenants.is_empty() {
+            return Err(DbErr::Migration(format!(
+                "Found {} tenants besides the default one, delete them before migrating: {}",
+                tenants.len(),
+                tenants.join(", ")
+            )));
+        }
+
+        if backend == DatabaseBackend::Sqlite {
+            sqlite_down(manager).await?;
+        } else {
+            manager
+                .drop_index(
+                    Index::drop()
+                        .name(NORMALIZED_EMAIL_INDEX)
+                        .table(Subscriptions::Table)
+                        .to_owned(),
+                )
+                .await?;
+            manager
+                .create_index(legacy_normalized_email_index())
+                .await?;
+
+            manager
+                .drop_index(
+                    Index::drop()
+                        .name(LIST_NAME_INDEX)
+                        .table(Lists::Table)
+                        .to_owned(),
+                )
+                .await?;
+            db.execute_unprepared(&format!(
+                "ALTER TABLE lists ADD CONSTRAINT {} UNIQUE (name)",
+                LIST_NAME_UNIQUE_CONSTRAINT
+            ))
+            .await?;
+
+            for table in [
+                Lists::Table.into_iden(),
+                Subscriptions::Table.into_iden(),
+                Users::Table.into_iden(),
+                AuditEvents::Table.into_iden(),
+            ] {
+                manager
+                    .alter_table(
+                        Table::alter()
+                            .table(table)
+                            .drop_column(TenantScoped::TenantId)
+                            .to_owned(),
+                    )
+                    .await?;
+            }
+        }
+
+        manager
+            .drop_table(Table::drop().table(Tenants::Table).to_owned())
+            .await
+    }
+}
+
+/// Adds a `tenant_id` column, pointing existing rows at the default tenant.
+async fn add_tenant_id(manager: &SchemaManager<'_>, table: DynIden) -> Result<(), DbErr> {
+    manager
+        .alter_table(
+            Table::alter()
+                .table(table.clone())
+                .add_column(uuid(TenantScoped::TenantId).default(DEFAULT_TENANT_ID))
+                .to_owned(),
+        )
+        .await?;
+
+    // New rows must say which tenant they belong to
+    manager
+        .get_connection()
+        .execute_unprepared(&format!(
+            r#"ALTER TABLE "{}" ALTER COLUMN tenant_id DROP DEFAULT"#,
+            table.to_string()
+        ))
+        .await?;
+
+    Ok(())
+}
+
+/// SQLite can neither add a column referencing another table nor change the unique
+/// constraints of one, so the tables are rebuilt instead.
+async fn sqlite_up(manager: &SchemaManager<'_>) -> Result<(), DbErr> {
+    let tenant_id = || -> (DynIden, SimpleExpr) {
+        (
+            TenantScoped::TenantId.into_iden(),
+            Expr::val(DEFAULT_TENANT_ID).into(),
+        )
+    };
+
+    sqlite::rebuild_table(
+        manager,
+        Lists::Table,
+        |table| {
+            Table::create()
+                .table(table.clone())
+                .col(pk_uuid(Lists::Id))
+                .col(uuid(TenantScoped::TenantId))
+                .col(text(Lists::Name))
+                .col(timestamp_with_time_zone(Lists::CreatedAt).default(Expr::current_timestamp()))
+                .foreign_key(&mut tenant_foreign_key(table.clone()))
+                .index(
+                    Index::create()
+                        .name(LIST_NAME_INDEX)
+                        .table(table)
+                        .col(TenantScoped::TenantId)
+                        .col(Lists::Name)
+                        .unique(),
+                )
+                .to_owned()
+        },
+        vec![
+            sqlite::keep(Lists::Id),
+            tenant_id(),
+            sqlite::keep(Lists::Name),
+            sqlite::keep(Lists::CreatedAt),
+        ],
+    )
+    .await?;
+
+    // Also drops the constraint on `email` that earlier migrations had to leave behind, and
+    // makes the columns they could not alter mandatory
+    sqlite::rebuild_table(
+        manager,
+        Subscriptions::Table,
+        |table| {
+            subscriptions(table.clone(), text(Subscriptions::Email))
+                .col(uuid(TenantScoped::TenantId))
+                .col(text(SubscriptionColumns::NormalizedEmail))
+                .col(text(SubscriptionColumns::AsciiEmail))
+                .foreign_key(&mut tenant_foreign_key(table.clone()))
+                .index(
+                    Index::create()
+                        .name(NORMALIZED_EMAIL_INDEX)
+                        .table(table)
+                        .col(TenantScoped::TenantId)
+                        .col(SubscriptionColumns::NormalizedEmail)
+                        .unique(),
+                )
+                .to_owned()
+        },
+        subscription_columns(Some(tenant_id())),
+    )
+    .await?;
+
+    sqlite::rebuild_table(
+        manager,
+        Users::Table,
+        |table| {
+            users(table.clone())
+                .col(uuid(TenantScoped::TenantId))
+                .foreign_key(&mut tenant_foreign_key(table))
+                .to_owned()
+        },
+        user_columns(Some(tenant_id())),
+    )
+    .await?;
+
+    // Adding a column keeps the triggers guarding the table, its default is the blob sqlx
+    // stores a UUID as
+    manager
+        .alter_table(
+            Table::alter()
+                .table(AuditEvents::Table)
+                .add_column(
+                    uuid(TenantScoped::TenantId)
+                        .default(Expr::cust(format!("X'{}'", DEFAULT_TENANT_ID.simple()))),
+                )
+                .to_owned(),
+        )
+        .await
+}
+
+async fn sqlite_down(manager: &SchemaManager<'_>) -> Result<(), DbErr> {
+    manager
+        .alter_table(
+            Table::alter()
+                .table(AuditEvents::Table)
+                .drop_column(TenantScoped::TenantId)
+                .to_owned(),
+        )
+        .await?;
+
+    sqlite::rebuild_table(
+        manager,
+        Users::Table,
+        |table| users(table).to_owned(),
+        user_columns(None),
+    )
+    .await?;
+
+    sqlite::rebuild_table(
+        manager,
+        Subscriptions::Table,
+        |table| {
+            subscriptions(table, text_uniq(Subscriptions::Email))
+                .col(text_null(SubscriptionColumns::NormalizedEmail))
+                .col(text_null(SubscriptionColumns::AsciiEmail))
+                .to_owned()
+        },
+        subscription_columns(None),
+    )
+    .await?;
+    manager
+        .create_index(legacy_normalized_email_index())
+        .await?;
+
+    sqlite::rebuild_table(
+        manager,
+        Lists::Table,
+        |table| {
+            Table::create()
+                .table(table)
+                .col(pk_uuid(Lists::Id))
+                .col(text_uniq(Lists::Name))
+                .col(timestamp_with_time_zone(Lists::CreatedAt).default(Expr::current_timestamp()))
+                .to_owned()
+        },
+        vec![
+            sqlite::keep(Lists::Id),
+            sqlite::keep(Lists::Name),
+            sqlite::keep(Lists::CreatedAt),
+        ],
+    )
+    .await
+}
+
+fn tenant_foreign_key(table: DynIden) -> ForeignKeyCreateStatement {
+    ForeignKey::create()
+        .from(table, TenantScoped::TenantId)
+        .to(Tenants::Table, Tenants::Id)
+        .on_delete(ForeignKeyAction::Cascade)
+        .to_owned()
+}
+
+/// The columns `subscriptions` had before tenants existed, apart from the derived forms of
+/// the email address.
+fn subscriptions(table: DynIden, email: ColumnDef) -> TableCreateStatement {
+    Table::create()
+        .table(table)
+        .col(pk_uuid(Subscriptions::Id))
+        .col(email)
+        .col(text(Subscriptions::Name))
+        .col(timestamp_with_time_zone(Subscriptions::SubscribedAt))
+        .col(text(Subscriptions::Status).default("pending_confirmation"))
+        .to_owned()
+}
+
+fn subscription_columns(tenant_id: Option<(DynIden, SimpleExpr)>) -> Vec<(DynIden, SimpleExpr)> {
+    let mut columns = vec![
+        sqlite::keep(Subscriptions::Id),
+        sqlite::keep(Subscriptions::Email),
+        sqlite::keep(Subscriptions::Name),
+        sqlite::keep(Subscriptions::SubscribedAt),
+        sqlite::keep(Subscriptions::Status),
+        sqlite::keep(SubscriptionColumns::NormalizedEmail),
+        sqlite::keep(SubscriptionColumns::AsciiEmail),
+    ];
+    columns.extend(tenant_id);
+    columns
+}
+
+/// `users` as created before tenants existed.
+fn users(table: DynIden) -> TableCreateStatement {
+    Table::create()
+        .table(table)
+        .col(pk_uuid(Users::UserId))
+        .col(text_uniq(Users::Username))
+        .col(text(Users::PasswordHash))
+        .to_owned()
+}
+
+fn user_columns(tenant_id: Option<(DynIden, SimpleExpr)>) -> Vec<(DynIden, SimpleExpr)> {
+    let mut columns = vec![
+        sqlite::keep(Users::UserId),
+        sqlite::keep(Users::Username),
+        sqlite::keep(Users::PasswordHash),
+    ];
+    columns.extend(tenant_id);
+    columns
+}
+
+fn list_name_index() -> IndexCreateStatement {
+    Index::create()
+        .name(LIST_NAME_INDEX)
+        .table(Lists::Table)
+        .col(TenantScoped::TenantId)
+        .col(Lists::Name)
+        .unique()
+        .to_owned()
+}
+
+fn normalized_email_index() -> IndexCreateStatement {
+    Index::create()
+        .name(NORMALIZED_EMAIL_INDEX)
+        .table(Subscriptions::Table)
+        .col(TenantScoped::TenantId)
+        .col(SubscriptionColumns::NormalizedEmail)
+        .unique()
+        .to_owned()
+}
+
+fn legacy_normalized_email_index() -> IndexCreateStatement {
+    Index::create()
+        .name(LEGACY_NORMALIZED_EMAIL_INDEX)
+        .table(Subscriptions::Table)
+        .col(SubscriptionColumns::NormalizedEmail)
+        .unique()
+        .to_owned()
+}
+
+#[derive(DeriveIden)]
+pub(crate) enum Tenants {
+    Table,
+    Id,
+    Slug,
+    Host,
+    BaseUrl,
+    SenderEmail,
+    EmailAccount,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum TenantScoped {
+    TenantId,
+}
+
+#[derive(DeriveIden)]
+enum SubscriptionColumns {
+    NormalizedEmail,
+    AsciiEmail,
+}
+
+#[derive(DeriveIden)]
+enum AuditEvents {
+    Table,
+}
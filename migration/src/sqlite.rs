@@ -0,0 +1,157 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::{
+    ConnectionTrait, DatabaseTransaction, Statement, TransactionTrait,
+};
+
+/// Recreates `table` with the definition returned by `create`, copying every row over with
+/// the given column values, since SQLite cannot add or alter the constraints of an existing
+/// table. Indexes of `table` that are not part of its definition are dropped along with it.
+///
+/// Every step runs on the same connection, the others of the pool may not have caught up with
+/// the new schema yet.
+pub(crate) async fn rebuild_table<F>(
+    manager: &SchemaManager<'_>,
+    table: impl IntoIden,
+    create: F,
+    columns: Vec<(DynIden, SimpleExpr)>,
+) -> Result<(), DbErr>
+where
+    F: Fn(DynIden) -> TableCreateStatement,
+{
+    let transaction = manager.get_connection().begin().await?;
+    let manager = SchemaManager::new(&transaction);
+    let table = table.into_iden();
+    let rebuilt: DynIden = Alias::new(format!("{}_rebuilt", table.to_string())).into_iden();
+
+    // Dropping `table` would cascade to the rows referencing it, and renaming it would point
+    // their foreign keys at the renamed table, so tables referencing it are set aside meanwhile
+    let dependents = set_aside_dependents(&transaction, &table.to_string()).await?;
+
+    manager.create_table(create(rebuilt.clone())).await?;
+
+    let (columns, values): (Vec<_>, Vec<_>) = columns.into_iter().unzip();
+    manager
+        .exec_stmt(
+            Query::insert()
+                .into_table(rebuilt.clone())
+                .columns(columns)
+                .select_from(Query::select().exprs(values).from(table.clone()).to_owned())
+                .map_err(|e| DbErr::Migration(e.to_string()))?
+                .to_owned(),
+        )
+        .await?;
+
+    manager
+        .drop_table(Table::drop().table(table.clone()).to_owned())
+        .await?;
+
+    manager
+        .rename_table(Table::rename().table(rebuilt, table).to_owned())
+        .await?;
+
+    restore_dependents(&transaction, dependents).await?;
+
+    transaction.commit().await
+}
+
+/// Copies `column` over as is.
+pub(crate) fn keep(column: impl IntoIden) -> (DynIden, SimpleExpr) {
+    let column = column.into_iden();
+    (column.clone(), Expr::col(column).into())
+}
+
+/// A table referencing the table being rebuilt, with what it takes to recreate it.
+struct Dependent {
+    name: String,
+    /// `CREATE` statements of the table, then of its indexes and triggers
+    schema: Vec<String>,
+}
+
+/// Moves the rows of the tables referencing `table` to temporary tables, then drops them.
+///
+/// Those tables must not be referenced themselves.
+async fn set_aside_dependents(
+    transaction: &DatabaseTransaction,
+    table: &str,
+) -> Result<Vec<Dependent>, DbErr> {
+    let names: Vec<String> = query_strings(
+        transaction,
+        "SELECT DISTINCT m.name FROM sqlite_master m, pragma_foreign_key_list(m.name) f \
+        WHERE m.type = 'table' AND m.name != ? AND f.\"table\" = ?",
+        vec![table.into(), table.into()],
+    )
+    .await?;
+
+    let mut dependents = Vec::with_capacity(names.len());
+    for name in names {
+        let schema = query_strings(
+            transaction,
+            "SELECT sql FROM sqlite_master WHERE tbl_name = ? AND sql IS NOT NULL \
+            ORDER BY type = 'table' DESC, type = 'index' DESC",
+            vec![name.clone().into()],
+        )
+        .await?;
+
+        transaction
+            .execute_unprepared(&format!(
+                r#"CREATE TEMP TABLE "{0}_set_aside" AS SELECT * FROM "{0}""#,
+                name
+            ))
+            .await?;
+        transaction
+            .execute_unprepared(&format!(r#"DROP TABLE "{}""#, name))
+            .await?;
+
+        dependents.push(Dependent { name, schema });
+    }
+
+    Ok(dependents)
+}
+
+async fn restore_dependents(
+    transaction: &DatabaseTransaction,
+    dependents: Vec<Dependent>,
+) -> Result<(), DbErr> {
+    for dependent in dependents {
+        let mut schema = dependent.schema.into_iter();
+        if let Some(create_table) = schema.next() {
+            transaction.execute_unprepared(&create_table).await?;
+        }
+        transaction
+            .execute_unprepared(&format!(
+                r#"INSERT INTO "{0}" SELECT * FROM temp."{0}_set_aside""#,
+                dependent.name
+            ))
+            .await?;
+        transaction
+            .execute_unprepared(&format!(
+                r#"DROP TABLE temp."{}_set_aside""#,
+                dependent.name
+            ))
+            .await?;
+        for create_index_or_trigger in schema {
+            transaction
+                .execute_unprepared(&create_index_or_trigger)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn query_strings(
+    transaction: &DatabaseTransaction,
+    sql: &str,
+    values: Vec<Value>,
+) -> Result<Vec<String>, DbErr> {
+    transaction
+        .query_all(Statement::from_sql_and_values(
+            transaction.get_database_backend(),
+            sql,
+            values,
+        ))
+        .await?
+        .into_iter()
+        .map(|row| row.try_get_by_index(0))
+        .collect()
+}
@@ -0,0 +1,142 @@
+use chrono::Utc;
+use entity::audit_events;
+use sea_orm::ActiveValue::Set;
+use sea_orm::{ConnectionTrait, DbErr, EntityTrait};
+use serde_json::{Map, Value, json};
+use tower_request_id::RequestId;
+use uuid::Uuid;
+
+use crate::authentication::AuthenticatedUser;
+
+/// Something an administrator did, ready to be appended to `audit_events`.
+pub struct AuditEvent {
+    /// Dotted name of the action, e.g. `subscriber.update`
+    pub action: &'static str,
+    pub target: Option<String>,
+    pub diff: Option<Value>,
+}
+
+#[tracing::instrument(
+    name = "Record audit event",
+    skip(db_connection, user, request_id, event),
+    fields(action = event.action)
+)]
+pub async fn record_audit_event(
+    db_connection: &impl ConnectionTrait,
+    user: &AuthenticatedUser,
+    request_id: &RequestId,
+    event: AuditEvent,
+) -> Result<(), DbErr> {
+    audit_events::Entity::insert(audit_events::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        actor_id: Set(user.user_id),
+        actor: Set(user.username.clone()),
+        action: Set(event.action.to_string()),
+        target: Set(event.target),
+        request_id: Set(request_id.to_string()),
+        diff: Set(event.diff),
+        tenant_id: Set(user.tenant_id),
+        // Rather than the database clock, which SQLite only keeps to the second
+        occurred_at: Set(Utc::now().into()),
+    })
+    .exec_without_returning(db_connection)
+    .await?;
+
+    Ok(())
+}
+
+/// Lists the top-level fields that differ between two JSON objects as `{"field": {"from", "to"}}`.
+///
+/// A `null` side stands for a record that did not exist yet, or no longer exists.
+pub fn diff(before: &Value, after: &Value) -> Value {
+    let empty = Map::new();
+    let before = before.as_object().unwrap_or(&empty);
+    let after = after.as_object().unwrap_or(&empty);
+
+    let changes: Map<String, Value> = before
+        .keys()
+        .chain(after.keys().filter(|key| !before.contains_key(*key)))
+        .filter(|key| before.get(*key) != after.get(*key))
+        .map(|key| {
+            let change = json!({
+                "from": before.get(key).unwrap_or(&Value::Null),
+                "to": after.get(key).unwrap_or(&Value::Null),
+            });
+            (key.clone(), change)
+        })
+        .collect();
+
+    Value::Object(changes)
+}
+
+/// Names the top-level fields that differ between two JSON objects, leaving their values out.
+///
+/// Audit events are append-only and outlive the erasure of a subscriber, so changes to
+/// personal data are recorded through this rather than `diff`.
+pub fn changed_fields(before: &Value, after: &Value) -> Value {
+    match diff(before, after) {
+        Value::Object(changes) => changes.keys().cloned().map(Value::String).collect(),
+        _ => Value::Array(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::assert_none;
+    use serde_json::json;
+
+    use crate::audit::{changed_fields, diff};
+
+    #[test]
+    fn only_changed_fields_are_reported() {
+        let before = json!({ "name": "ursula", "status": "confirmed" });
+        let after = json!({ "name": "le guin", "status": "confirmed" });
+
+        let changes = diff(&before, &after);
+
+        assert_eq!(
+            changes,
+            json!({ "name": { "from": "ursula", "to": "le guin" } })
+        );
+        assert_none!(changes.get("status"));
+    }
+
+    #[test]
+    fn a_created_record_reports_every_field_from_null() {
+        let after = json!({ "name": "ursula" });
+
+        assert_eq!(
+            diff(&json!(null), &after),
+            json!({ "name": { "from": null, "to": "ursula" } })
+        );
+    }
+
+    #[test]
+    fn a_deleted_record_reports_every_field_to_null() {
+        let before = json!({ "name": "ursula" });
+
+        assert_eq!(
+            diff(&before, &json!(null)),
+            json!({ "name": { "from": "ursula", "to": null } })
+        );
+    }
+
+    #[test]
+    fn identical_records_have_an_empty_diff() {
+        let record = json!({ "name": "ursula" });
+
+        assert_eq!(diff(&record, &record), json!({}));
+    }
+
+    #[test]
+    fn changed_fields_are_named_without_their_values() {
+        let before = json!({ "email": "ursula@example.com", "name": "ursula", "tags": [] });
+        let after = json!({ "email": "ursula@example.com", "name": "le guin", "tags": [] });
+
+        assert_eq!(changed_fields(&before, &after), json!(["name"]));
+        assert_eq!(
+            changed_fields(&before, &json!(null)),
+            json!(["email", "name", "tags"])
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use argon2::password_hash::SaltString;
+use argon2::{Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version};
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use entity::prelude::Users;
+use entity::users;
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use secrecy::{ExposeSecret, SecretString};
+use uuid::Uuid;
+
+use crate::problem::Problem;
+use crate::routes::error_chain_fmt;
+use crate::startup::ApplicationState;
+use crate::telemetry::spawn_blocking_with_tracing;
+use crate::tenant::{Tenant, TenantError};
+
+#[derive(thiserror::Error)]
+pub enum AuthError {
+    #[error("Invalid credentials.")]
+    InvalidCredentials(#[source] anyhow::Error),
+    #[error(transparent)]
+    TenantError(#[from] TenantError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for AuthError {
+    fn into_response(self) -> Response {
+        tracing::error!("{:?}", self);
+
+        match self {
+            AuthError::InvalidCredentials(_) => {
+                Problem::from_error(StatusCode::UNAUTHORIZED, &self).with_header(
+                    header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="admin""#),
+                )
+            }
+            AuthError::TenantError(TenantError::UnknownTenant(_)) => {
+                Problem::from_error(StatusCode::NOT_FOUND, &self)
+            }
+            AuthError::TenantError(TenantError::UnexpectedError(_))
+            | AuthError::UnexpectedError(_) => {
+                Problem::from_error(StatusCode::INTERNAL_SERVER_ERROR, &self)
+            }
+        }
+        .into_response()
+    }
+}
+
+pub struct Credentials {
+    pub username: String,
+    pub password: SecretString,
+}
+
+/// A newsletter publisher that has passed HTTP Basic authentication, as a user of the tenant
+/// the request is for.
+pub struct AuthenticatedUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub tenant_id: Uuid,
+}
+
+impl FromRequestParts<Arc<ApplicationState>> for AuthenticatedUser {
+    type Rejection = AuthError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ApplicationState>,
+    ) -> Result<Self, Self::Rejection> {
+        let tenant = Tenant::from_request_parts(parts, state).await?;
+        let credentials =
+            basic_authentication(&parts.headers).map_err(AuthError::InvalidCredentials)?;
+        let username = credentials.username.clone();
+
+        let user_id = validate_credentials(credentials, tenant.id, &state.db_connection).await?;
+
+        Ok(Self {
+            user_id,
+            username,
+            tenant_id: tenant.id,
+        })
+    }
+}
+
+fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::Error> {
+    let header_value = headers
+        .get(header::AUTHORIZATION)
+        .context("The 'Authorization' header was missing")?
+        .to_str()
+        .context("The 'Authorization' header was not a valid UTF8 string")?;
+    let base64encoded_segment = header_value
+        .strip_prefix("Basic ")
+        .context("The authorization scheme was not 'Basic'")?;
+    let decoded_bytes = base64::engine::general_purpose::STANDARD
+        .decode(base64encoded_segment)
+        .context("Failed to base64-decode 'Basic' credentials")?;
+    let decoded_credentials = String::from_utf8(decoded_bytes)
+        .context("The decoded credential string is not valid UTF8")?;
+
+    let (username, password) = decoded_credentials
+        .split_once(':')
+        .context("A password must be provided in 'Basic' auth")?;
+
+    Ok(Credentials {
+        username: username.to_string(),
+        password: SecretString::from(password),
+    })
+}
+
+/// Users of other tenants are as unknown as usernames nobody has.
+#[tracing::instrument(name = "Validate credentials", skip(credentials, db_connection))]
+pub async fn validate_credentials(
+    credentials: Credentials,
+    tenant_id: Uuid,
+    db_connection: &DatabaseConnection,
+) -> Result<Uuid, AuthError> {
+    let mut user_id = None;
+    // Verify against a dummy hash when the user is unknown, so that response times
+    // do not reveal which usernames exist
+    let mut expected_password_hash = SecretString::from(
+        "$argon2id$v=19$m=15000,t=2,p=1$\
+        gZiV/M1gPc22ElAH/Jh1Hw$\
+        CWOrkoo7oJBQ/iyh7uJ0LO2aLEfrHwTWllSAxT0zRno",
+    );
+
+    if let Some((stored_user_id, stored_password_hash)) =
+        get_stored_credentials(&credentials.username, tenant_id, db_connection)
+            .await
+            .context("Failed to retrieve stored credentials")?
+    {
+        user_id = Some(stored_user_id);
+        expected_password_hash = stored_password_hash;
+    }
+
+    spawn_blocking_with_tracing(move || {
+        verify_password_hash(expected_password_hash, credentials.password)
+    })
+    .await
+    .context("Failed to spawn blocking task")??;
+
+    user_id
+        .ok_or_else(|| anyhow::anyhow!("Unknown username"))
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(
+    name = "Verify password hash",
+    skip(expected_password_hash, password_candidate)
+)]
+fn verify_password_hash(
+    expected_password_hash: SecretString,
+    password_candidate: SecretString,
+) -> Result<(), AuthError> {
+    let expected_password_hash = PasswordHash::new(expected_password_hash.expose_secret())
+        .context("Failed to parse hash in PHC string format")?;
+
+    Argon2::default()
+        .verify_password(
+            password_candidate.expose_secret().as_bytes(),
+            &expected_password_hash,
+        )
+        .context("Invalid password")
+        .map_err(AuthError::InvalidCredentials)
+}
+
+#[tracing::instrument(name = "Get stored credentials", skip(username, db_connection))]
+async fn get_stored_credentials(
+    username: &str,
+    tenant_id: Uuid,
+    db_connection: &DatabaseConnection,
+) -> Result<Option<(Uuid, SecretString)>, anyhow::Error> {
+    let user = Users::find()
+        .filter(users::Column::Username.eq(username))
+        .filter(users::Column::TenantId.eq(tenant_id))
+        .one(db_connection)
+        .await
+        .context("Failed to perform a query to retrieve stored credentials")?;
+
+    Ok(user.map(|user| (user.user_id, SecretString::from(user.password_hash))))
+}
+
+/// Hashes a password into a PHC string, ready to be stored in `users.password_hash`.
+pub fn compute_password_hash(password: SecretString) -> Result<SecretString, anyhow::Error> {
+    let salt = SaltString::generate(&mut rand::thread_rng());
+    let password_hash = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(15000, 2, 1, None).unwrap(),
+    )
+    .hash_password(password.expose_secret().as_bytes(), &salt)?
+    .to_string();
+
+    Ok(SecretString::from(password_hash))
+}
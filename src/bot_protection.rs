@@ -0,0 +1,310 @@
+use std::net::IpAddr;
+use std::time::Duration;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+
+use crate::configuration::{BotProtectionSettings, CaptchaSettings};
+use crate::routes::error_chain_fmt;
+
+#[derive(thiserror::Error)]
+pub enum BotCheckError {
+    #[error("{0}")]
+    Rejected(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for BotCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// The fields of a signup form that only exist to tell people and scripts apart.
+pub struct FormSubmission<'a> {
+    /// Hidden from people, so any value was filled in by a script
+    pub honeypot: Option<&'a str>,
+    /// When the form was rendered, as signed by `BotProtection::issue_form_token`
+    pub form_token: Option<&'a str>,
+    pub captcha_response: Option<&'a str>,
+}
+
+pub struct BotProtection {
+    min_form_fill_time: Duration,
+    max_form_age: Duration,
+    form_token_secret: SecretString,
+    captcha: Option<CaptchaVerifier>,
+}
+
+impl BotProtection {
+    pub fn new(settings: &BotProtectionSettings) -> Self {
+        Self {
+            min_form_fill_time: Duration::from_secs(settings.min_form_fill_seconds),
+            max_form_age: Duration::from_secs(settings.max_form_age_seconds),
+            form_token_secret: settings.form_token_secret.clone(),
+            captcha: settings.captcha.as_ref().map(CaptchaVerifier::new),
+        }
+    }
+
+    /// Signs the current time, for a signup form to send back when it is submitted.
+    pub fn issue_form_token(&self) -> String {
+        self.form_token_at(Utc::now())
+    }
+
+    fn form_token_at(&self, started_at: DateTime<Utc>) -> String {
+        let timestamp = started_at.timestamp().to_string();
+        let signature = self.form_token_mac(&timestamp).finalize().into_bytes();
+
+        format!("{}.{}", timestamp, hex::encode(signature))
+    }
+
+    /// Reads the start time back from a form token, as long as we signed it.
+    fn verify_form_token(&self, token: &str) -> Option<DateTime<Utc>> {
+        let (timestamp, signature) = token.split_once('.')?;
+        let signature = hex::decode(signature).ok()?;
+        self.form_token_mac(timestamp)
+            .verify_slice(&signature)
+            .ok()?;
+
+        DateTime::from_timestamp(timestamp.parse().ok()?, 0)
+    }
+
+    fn form_token_mac(&self, timestamp: &str) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(self.form_token_secret.expose_secret().as_bytes())
+                .expect("HMAC accepts keys of any size");
+        mac.update(timestamp.as_bytes());
+        mac
+    }
+
+    #[tracing::instrument(name = "Check signup for bots", skip(self, submission))]
+    pub async fn check(
+        &self,
+        submission: &FormSubmission<'_>,
+        remote_ip: IpAddr,
+    ) -> Result<(), BotCheckError> {
+        self.check_form(submission, Utc::now())
+            .map_err(BotCheckError::Rejected)?;
+
+        if let Some(captcha) = &self.captcha {
+            let response = submission
+                .captcha_response
+                .filter(|response| !response.is_empty())
+                .ok_or_else(|| BotCheckError::Rejected("The CAPTCHA response is missing".into()))?;
+
+            if !captcha.verify(response, remote_ip).await? {
+                return Err(BotCheckError::Rejected(
+                    "The CAPTCHA response was rejected".into(),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_form(
+        &self,
+        submission: &FormSubmission<'_>,
+        now: DateTime<Utc>,
+    ) -> Result<(), String> {
+        if submission.honeypot.is_some_and(|value| !value.is_empty()) {
+            return Err("The honeypot field was filled in".into());
+        }
+
+        if self.min_form_fill_time.is_zero() {
+            return Ok(());
+        }
+
+        let form_token = submission
+            .form_token
+            .filter(|token| !token.is_empty())
+            .ok_or("The form token is missing")?;
+        let started_at = self
+            .verify_form_token(form_token)
+            .ok_or("The form token is invalid")?;
+        let fill_time = (now - started_at).to_std().unwrap_or_default();
+
+        if fill_time < self.min_form_fill_time {
+            return Err(format!("The form was filled in {:?}", fill_time));
+        }
+        if fill_time > self.max_form_age {
+            return Err("The form token has expired".into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Verifies CAPTCHA responses with the provider, using the protocol shared by reCAPTCHA,
+/// hCaptcha and Turnstile.
+struct CaptchaVerifier {
+    http_client: Client,
+    verification_url: String,
+    secret: SecretString,
+}
+
+#[derive(serde::Deserialize)]
+struct VerificationResponse {
+    success: bool,
+}
+
+impl CaptchaVerifier {
+    fn new(settings: &CaptchaSettings) -> Self {
+        let http_client = Client::builder()
+            .timeout(Duration::from_millis(settings.timeout_milliseconds))
+            .build()
+            .unwrap();
+
+        Self {
+            http_client,
+            verification_url: settings.verification_url.clone(),
+            secret: settings.secret.clone(),
+        }
+    }
+
+    async fn verify(&self, response: &str, remote_ip: IpAddr) -> Result<bool, anyhow::Error> {
+        let verification: VerificationResponse = self
+            .http_client
+            .post(&self.verification_url)
+            .form(&[
+                ("secret", self.secret.expose_secret()),
+                ("response", response),
+                ("remoteip", &remote_ip.to_string()),
+            ])
+            .send()
+            .await
+            .context("Failed to reach the CAPTCHA provider")?
+            .error_for_status()
+            .context("The CAPTCHA provider returned an error")?
+            .json()
+            .await
+            .context("Failed to parse the CAPTCHA verification")?;
+
+        Ok(verification.success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeDelta, Utc};
+    use claims::{assert_err, assert_ok};
+
+    use crate::bot_protection::{BotProtection, FormSubmission};
+    use crate::configuration::BotProtectionSettings;
+
+    fn bot_protection(min_form_fill_seconds: u64) -> BotProtection {
+        BotProtection::new(&BotProtectionSettings {
+            min_form_fill_seconds,
+            max_form_age_seconds: 3600,
+            form_token_secret: "a-secret".into(),
+            captcha: None,
+        })
+    }
+
+    fn submission<'a>(
+        honeypot: Option<&'a str>,
+        form_token: Option<&'a str>,
+    ) -> FormSubmission<'a> {
+        FormSubmission {
+            honeypot,
+            form_token,
+            captcha_response: None,
+        }
+    }
+
+    #[test]
+    fn an_empty_honeypot_is_accepted() {
+        let check = bot_protection(0).check_form(&submission(Some(""), None), Utc::now());
+
+        assert_ok!(check);
+    }
+
+    #[test]
+    fn a_filled_honeypot_is_rejected() {
+        let check = bot_protection(0).check_form(&submission(Some("spam"), None), Utc::now());
+
+        assert_err!(check);
+    }
+
+    #[test]
+    fn a_missing_form_token_is_rejected_when_a_fill_time_is_required() {
+        let check = bot_protection(3).check_form(&submission(None, None), Utc::now());
+
+        assert_err!(check);
+    }
+
+    #[test]
+    fn a_form_filled_too_quickly_is_rejected() {
+        let protection = bot_protection(3);
+        let now = Utc::now();
+        let token = protection.form_token_at(now - TimeDelta::seconds(1));
+
+        let check = protection.check_form(&submission(None, Some(&token)), now);
+
+        assert_err!(check);
+    }
+
+    #[test]
+    fn a_start_time_in_the_future_is_rejected() {
+        let protection = bot_protection(3);
+        let now = Utc::now();
+        let token = protection.form_token_at(now + TimeDelta::seconds(60));
+
+        let check = protection.check_form(&submission(None, Some(&token)), now);
+
+        assert_err!(check);
+    }
+
+    #[test]
+    fn a_form_filled_at_human_speed_is_accepted() {
+        let protection = bot_protection(3);
+        let now = Utc::now();
+        let token = protection.form_token_at(now - TimeDelta::seconds(10));
+
+        let check = protection.check_form(&submission(None, Some(&token)), now);
+
+        assert_ok!(check);
+    }
+
+    #[test]
+    fn a_form_token_older_than_the_maximum_age_is_rejected() {
+        let protection = bot_protection(3);
+        let now = Utc::now();
+        let token = protection.form_token_at(now - TimeDelta::hours(2));
+
+        let check = protection.check_form(&submission(None, Some(&token)), now);
+
+        assert_err!(check);
+    }
+
+    #[test]
+    fn a_start_time_signed_with_another_key_is_rejected() {
+        let now = Utc::now();
+        let forged = BotProtection::new(&BotProtectionSettings {
+            min_form_fill_seconds: 3,
+            max_form_age_seconds: 3600,
+            form_token_secret: "another-secret".into(),
+            captcha: None,
+        })
+        .form_token_at(now - TimeDelta::seconds(10));
+
+        let check = bot_protection(3).check_form(&submission(None, Some(&forged)), now);
+
+        assert_err!(check);
+    }
+
+    #[test]
+    fn an_unsigned_start_time_is_rejected() {
+        let now = Utc::now();
+        let started_at = (now - TimeDelta::seconds(10)).timestamp().to_string();
+
+        let check = bot_protection(3).check_form(&submission(None, Some(&started_at)), now);
+
+        assert_err!(check);
+    }
+}
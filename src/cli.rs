@@ -0,0 +1,450 @@
+//! Command line interface of the `zero2prod-axum` binary.
+
+use chrono::Utc;
+use clap::{Parser, Subcommand};
+use entity::prelude::{Lists, Subscriptions, Tenants, Users};
+use entity::{lists, subscriber_tags, subscription_lists, subscriptions, tenants, users};
+use migration::{
+    DEFAULT_LIST_ID, DEFAULT_LIST_NAME, DEFAULT_TENANT_ID, DEFAULT_TENANT_SLUG, Migrator,
+    MigratorTrait,
+};
+use rand::distributions::Alphanumeric;
+use rand::{Rng, thread_rng};
+use reqwest::Url;
+use sea_orm::ActiveValue::Set;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+    TransactionTrait,
+};
+use secrecy::{ExposeSecret, SecretString};
+use uuid::Uuid;
+
+use crate::authentication::compute_password_hash;
+use crate::configuration::Settings;
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+
+#[derive(Parser)]
+#[command(version, about = "A newsletter delivery service")]
+pub struct Cli {
+    /// Starts the server when omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Serve the API
+    Serve,
+    /// Manage the database schema
+    #[command(subcommand)]
+    Migrate(MigrateCommand),
+    /// Create a user allowed to publish newsletters
+    CreateUser {
+        #[arg(long)]
+        username: String,
+        /// A random password is generated and printed when omitted
+        #[arg(long)]
+        password: Option<String>,
+        /// Slug of the tenant the user publishes for
+        #[arg(long, default_value = DEFAULT_TENANT_SLUG)]
+        tenant: String,
+    },
+    /// Host another publication on this deployment
+    CreateTenant {
+        /// Identifies the tenant in `/tenants/{slug}` paths
+        #[arg(long)]
+        slug: String,
+        /// Host the tenant is served on, besides its path prefix
+        #[arg(long)]
+        host: Option<String>,
+        /// Prefix of the links sent by email, under the deployment's by default
+        #[arg(long)]
+        base_url: Option<String>,
+        /// The deployment's sender is used when omitted
+        #[arg(long)]
+        sender_email: Option<String>,
+        /// Name of an account in `email_client.accounts` of the configuration, the
+        /// deployment's account is used when omitted
+        #[arg(long)]
+        email_account: Option<String>,
+    },
+    /// Send an email through the configured provider, to check its settings
+    SendTestEmail {
+        /// Recipient of the test email
+        address: String,
+    },
+    /// Load and validate the configuration, then print it with secrets redacted
+    CheckConfig,
+    /// Fill the database with sample lists and subscribers for development
+    Seed,
+}
+
+#[derive(Subcommand)]
+pub enum MigrateCommand {
+    /// Apply pending migrations
+    Up,
+    /// Roll back applied migrations
+    Down {
+        /// Number of migrations to roll back
+        #[arg(long, default_value_t = 1)]
+        steps: u32,
+    },
+    /// List migrations and whether they are applied
+    Status,
+}
+
+/// Stores a new publisher of the tenant with the given slug, returning its id.
+#[tracing::instrument(name = "Create a user", skip(db_connection, password))]
+pub async fn create_user(
+    db_connection: &DatabaseConnection,
+    tenant_slug: &str,
+    username: &str,
+    password: SecretString,
+) -> Result<Uuid, anyhow::Error> {
+    let Some(tenant) = Tenants::find()
+        .filter(tenants::Column::Slug.eq(tenant_slug))
+        .one(db_connection)
+        .await?
+    else {
+        anyhow::bail!("{} is not a known tenant", tenant_slug);
+    };
+
+    // Usernames are unique across tenants, the tenant of a user follows from it
+    if Users::find()
+        .filter(users::Column::Username.eq(username))
+        .one(db_connection)
+        .await?
+        .is_some()
+    {
+        anyhow::bail!("A user named {} already exists", username);
+    }
+
+    let password_hash = compute_password_hash(password)?;
+    let user = users::ActiveModel {
+        user_id: Set(Uuid::new_v4()),
+        username: Set(username.to_string()),
+        password_hash: Set(password_hash.expose_secret().to_string()),
+        tenant_id: Set(tenant.id),
+    }
+    .insert(db_connection)
+    .await?;
+
+    Ok(user.user_id)
+}
+
+pub struct NewTenant {
+    pub slug: String,
+    pub host: Option<String>,
+    pub base_url: Option<String>,
+    pub sender_email: Option<String>,
+    pub email_account: Option<String>,
+}
+
+/// Stores a new tenant along with its default list, returning its id.
+#[tracing::instrument(name = "Create a tenant", skip(db_connection, tenant), fields(slug = %tenant.slug))]
+pub async fn create_tenant(
+    db_connection: &DatabaseConnection,
+    tenant: NewTenant,
+) -> Result<Uuid, anyhow::Error> {
+    if !is_valid_slug(&tenant.slug) {
+        anyhow::bail!(
+            "{} is not a valid slug, use lowercase letters, digits and dashes",
+            tenant.slug
+        );
+    }
+    if let Some(base_url) = &tenant.base_url {
+        Url::parse(base_url)
+            .map_err(|e| anyhow::anyhow!("{} is not a valid base URL: {}", base_url, e))?;
+    }
+    if let Some(sender_email) = &tenant.sender_email {
+        SubscriberEmail::parse(sender_email.clone()).map_err(anyhow::Error::msg)?;
+    }
+
+    let transaction = db_connection.begin().await?;
+    let mut taken = tenants::Column::Slug.eq(&tenant.slug);
+    let host = tenant.host.map(|host| host.to_ascii_lowercase());
+    if let Some(host) = &host {
+        taken = taken.or(tenants::Column::Host.eq(host));
+    }
+    if Tenants::find()
+        .filter(taken)
+        .one(&transaction)
+        .await?
+        .is_some()
+    {
+        anyhow::bail!("A tenant with this slug or host already exists");
+    }
+
+    let now = DateTimeWithTimeZone::from(Utc::now());
+    let tenant = tenants::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        slug: Set(tenant.slug),
+        host: Set(host),
+        base_url: Set(tenant
+            .base_url
+            .map(|base_url| base_url.trim_end_matches('/').to_string())),
+        sender_email: Set(tenant.sender_email),
+        email_account: Set(tenant.email_account),
+        created_at: Set(now),
+    }
+    .insert(&transaction)
+    .await?;
+
+    // Subscribing without naming a list joins the default one
+    lists::ActiveModel {
+        id: Set(Uuid::new_v4()),
+        name: Set(DEFAULT_LIST_NAME.to_string()),
+        created_at: Set(now),
+        tenant_id: Set(tenant.id),
+    }
+    .insert(&transaction)
+    .await?;
+
+    transaction.commit().await?;
+
+    Ok(tenant.id)
+}
+
+fn is_valid_slug(slug: &str) -> bool {
+    !slug.is_empty()
+        && !slug.starts_with('-')
+        && slug
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+pub fn generate_password() -> SecretString {
+    let mut rng = thread_rng();
+    let password: String = std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(24)
+        .collect();
+
+    SecretString::from(password)
+}
+
+#[tracing::instrument(name = "Send a test email", skip(email_client))]
+pub async fn send_test_email(
+    email_client: &EmailClient,
+    address: &str,
+) -> Result<(), anyhow::Error> {
+    let recipient = SubscriberEmail::parse(address.to_string()).map_err(anyhow::Error::msg)?;
+
+    email_client
+        .send_email(
+            &recipient,
+            "Test email",
+            "<p>This is a test email, your email settings work.</p>",
+            "This is a test email, your email settings work.",
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// The settings as JSON, with secrets redacted, or every reason they are invalid.
+pub fn check_config(settings: &Settings) -> Result<String, Vec<String>> {
+    settings.validate()?;
+
+    serde_json::to_string_pretty(settings).map_err(|e| vec![e.to_string()])
+}
+
+/// Each migration of this build, and whether it is applied.
+pub async fn migration_status(
+    db_connection: &DatabaseConnection,
+) -> Result<Vec<(String, bool)>, DbErr> {
+    let pending: Vec<String> = Migrator::get_pending_migrations(db_connection)
+        .await?
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .collect();
+
+    Ok(Migrator::migrations()
+        .iter()
+        .map(|migration| {
+            let name = migration.name().to_string();
+            let applied = !pending.contains(&name);
+            (name, applied)
+        })
+        .collect())
+}
+
+pub const SEED_LIST_NAME: &str = "product-updates";
+
+/// Name, email, status and tags of the sample subscribers.
+const SEED_SUBSCRIBERS: [(&str, &str, &str, &[&str]); 4] = [
+    (
+        "Ursula Le Guin",
+        "ursula@example.com",
+        "confirmed",
+        &["sci-fi"],
+    ),
+    (
+        "Octavia Butler",
+        "octavia@example.com",
+        "confirmed",
+        &["sci-fi", "beta"],
+    ),
+    (
+        "Terry Pratchett",
+        "terry@example.com",
+        "pending_confirmation",
+        &[],
+    ),
+    ("Iain Banks", "iain@example.com", "unsubscribed", &[]),
+];
+
+/// Inserts sample lists and subscribers in the default tenant, leaving those already present
+/// untouched so that seeding twice is harmless. Returns the number of subscribers inserted.
+#[tracing::instrument(name = "Seed the database", skip(db_connection))]
+pub async fn seed(db_connection: &DatabaseConnection) -> Result<usize, anyhow::Error> {
+    let transaction = db_connection.begin().await?;
+    let now = DateTimeWithTimeZone::from(Utc::now());
+
+    let list_id = match Lists::find()
+        .filter(lists::Column::TenantId.eq(DEFAULT_TENANT_ID))
+        .filter(lists::Column::Name.eq(SEED_LIST_NAME))
+        .one(&transaction)
+        .await?
+    {
+        Some(list) => list.id,
+        None => {
+            lists::ActiveModel {
+                id: Set(Uuid::new_v4()),
+                name: Set(SEED_LIST_NAME.to_string()),
+                created_at: Set(now),
+                tenant_id: Set(DEFAULT_TENANT_ID),
+            }
+            .insert(&transaction)
+            .await?
+            .id
+        }
+    };
+
+    let mut inserted = 0;
+    for (name, email, status, tags) in SEED_SUBSCRIBERS {
+        let email = SubscriberEmail::parse(email.to_string()).map_err(anyhow::Error::msg)?;
+        let existing = Subscriptions::find()
+            .filter(subscriptions::Column::TenantId.eq(DEFAULT_TENANT_ID))
+            .filter(subscriptions::Column::NormalizedEmail.eq(email.normalized()))
+            .one(&transaction)
+            .await?;
+        if existing.is_some() {
+            continue;
+        }
+
+        let subscriber_id = subscriptions::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            email: Set(email.as_ref().to_string()),
+            ascii_email: Set(email.ascii_compatible().to_string()),
+            normalized_email: Set(email.normalized().to_string()),
+            name: Set(name.to_string()),
+            subscribed_at: Set(now),
+            status: Set(status.to_string()),
+            tenant_id: Set(DEFAULT_TENANT_ID),
+        }
+        .insert(&transaction)
+        .await?
+        .id;
+
+        // Confirmed subscribers also follow the sample list
+        let list_ids = if status == "confirmed" {
+            vec![DEFAULT_LIST_ID, list_id]
+        } else {
+            vec![DEFAULT_LIST_ID]
+        };
+        subscription_lists::Entity::insert_many(list_ids.into_iter().map(|list_id| {
+            subscription_lists::ActiveModel {
+                subscriber_id: Set(subscriber_id),
+                list_id: Set(list_id),
+                status: Set(status.to_string()),
+                subscribed_at: Set(now),
+            }
+        }))
+        .exec(&transaction)
+        .await?;
+
+        if !tags.is_empty() {
+            subscriber_tags::Entity::insert_many(tags.iter().map(|tag| {
+                subscriber_tags::ActiveModel {
+                    subscriber_id: Set(subscriber_id),
+                    tag: Set(tag.to_string()),
+                }
+            }))
+            .on_conflict(
+                OnConflict::columns([
+                    subscriber_tags::Column::SubscriberId,
+                    subscriber_tags::Column::Tag,
+                ])
+                .do_nothing()
+                .to_owned(),
+            )
+            .do_nothing()
+            .exec(&transaction)
+            .await?;
+        }
+
+        inserted += 1;
+    }
+
+    transaction.commit().await?;
+
+    Ok(inserted)
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, Parser};
+
+    use crate::cli::{Cli, Command, MigrateCommand, is_valid_slug};
+
+    #[test]
+    fn cli_is_well_formed() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn serving_is_the_default() {
+        let cli = Cli::try_parse_from(["zero2prod-axum"]).unwrap();
+
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn migrate_down_rolls_back_one_migration_by_default() {
+        let cli = Cli::try_parse_from(["zero2prod-axum", "migrate", "down"]).unwrap();
+
+        assert!(matches!(
+            cli.command,
+            Some(Command::Migrate(MigrateCommand::Down { steps: 1 }))
+        ));
+    }
+
+    #[test]
+    fn users_are_created_in_the_default_tenant_by_default() {
+        let cli =
+            Cli::try_parse_from(["zero2prod-axum", "create-user", "--username", "ursula"]).unwrap();
+
+        assert!(matches!(
+            cli.command,
+            Some(Command::CreateUser { tenant, .. }) if tenant == "default"
+        ));
+    }
+
+    #[test]
+    fn slugs_are_lowercase_letters_digits_and_dashes() {
+        assert!(is_valid_slug("acme-2"));
+        assert!(!is_valid_slug(""));
+        assert!(!is_valid_slug("-acme"));
+        assert!(!is_valid_slug("Acme"));
+        assert!(!is_valid_slug("acme/news"));
+    }
+
+    #[test]
+    fn send_test_email_requires_an_address() {
+        assert!(Cli::try_parse_from(["zero2prod-axum", "send-test-email"]).is_err());
+    }
+}
@@ -1,11 +1,20 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
 use std::time::Duration;
 
-use sea_orm::sqlx::ConnectOptions;
+use reqwest::Url;
+use sea_orm::sqlx::pool::PoolOptions;
 use sea_orm::sqlx::postgres::{PgConnectOptions, PgSslMode};
+use sea_orm::sqlx::sqlite::SqliteConnectOptions;
+use sea_orm::sqlx::{ConnectOptions, Database};
 use secrecy::{ExposeSecret, SecretString};
 use serde_aux::field_attributes::deserialize_number_from_string;
 
-use crate::domain::SubscriberEmail;
+use crate::domain::{EmailPolicy, SubscriberEmail};
+
+/// The form token key `base.yaml` used to ship with, public and so no secret at all.
+const FORM_TOKEN_SECRET_PLACEHOLDER: &str = "form-token-secret";
 
 pub enum Environment {
     Local,
@@ -36,30 +45,258 @@ impl TryFrom<String> for Environment {
     }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct Settings {
     pub application: ApplicationSettings,
     pub database: DatabaseSettings,
     pub email_client: EmailClientSettings,
+    pub telemetry: TelemetrySettings,
+}
+
+impl Settings {
+    /// Checks what deserializing cannot, e.g. that addresses parse, reporting every problem.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = vec![];
+
+        if let Err(e) = Url::parse(&self.application.base_url) {
+            errors.push(format!("application.base_url: {}", e));
+        }
+        if let Err(e) = EmailPolicy::new(&self.application.email_policy) {
+            errors.push(format!("application.email_policy: {}", e));
+        }
+        if let Err(e) = Url::parse(&self.email_client.base_url) {
+            errors.push(format!("email_client.base_url: {}", e));
+        }
+        if let Err(e) = self.email_client.sender() {
+            errors.push(format!("email_client.sender_email: {}", e));
+        }
+        let bot_protection = &self.application.bot_protection;
+        if bot_protection.min_form_fill_seconds > 0 {
+            let secret = bot_protection.form_token_secret.expose_secret();
+            if secret.is_empty() || secret == FORM_TOKEN_SECRET_PLACEHOLDER {
+                errors.push(
+                    "application.bot_protection.form_token_secret: must be set to a secret key"
+                        .to_string(),
+                );
+            }
+            if bot_protection.max_form_age_seconds <= bot_protection.min_form_fill_seconds {
+                errors.push(
+                    "application.bot_protection.max_form_age_seconds: must exceed \
+                    min_form_fill_seconds"
+                        .to_string(),
+                );
+            }
+        }
+        let rate_limit = &self.application.rate_limit;
+        for (name, bucket) in [
+            ("per_ip", rate_limit.per_ip),
+            ("per_email", rate_limit.per_email),
+        ] {
+            // A bucket that never refills would lock clients out for good
+            if bucket.refill_per_minute == 0 {
+                errors.push(format!(
+                    "application.rate_limit.{}.refill_per_minute: must be at least 1",
+                    name
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct TelemetrySettings {
+    /// Spans are only exported when set
+    pub otlp: Option<OtlpSettings>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+pub struct OtlpSettings {
+    /// Full URL of the traces endpoint of the collector, e.g. `http://localhost:4318/v1/traces`
+    pub endpoint: String,
+    pub protocol: OtlpProtocol,
+    pub timeout_milliseconds: u64,
+}
+
+impl OtlpSettings {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+#[derive(Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub enum OtlpProtocol {
+    #[serde(rename = "http/protobuf")]
+    HttpProtobuf,
+    #[serde(rename = "http/json")]
+    HttpJson,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct ApplicationSettings {
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    /// Apply pending migrations in `Application::build`, instead of running the
+    /// `migration` binary before deploying
+    pub run_migrations_on_startup: bool,
+    pub rate_limit: RateLimitSettings,
+    pub bot_protection: BotProtectionSettings,
+    pub email_policy: EmailPolicySettings,
+    pub health: HealthSettings,
+    pub shutdown: ShutdownSettings,
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct ShutdownSettings {
+    /// How long in-flight requests and background tasks may take to complete once
+    /// shutdown starts, before they are abandoned
+    pub drain_timeout_milliseconds: u64,
+}
+
+impl ShutdownSettings {
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_millis(self.drain_timeout_milliseconds)
+    }
+}
+
+/// Dependencies checked by `GET /health/ready`.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct HealthSettings {
+    /// Upper bound of each check, so that a hanging dependency reports as down
+    pub timeout_milliseconds: u64,
+    /// Also report not ready while the email provider cannot be reached
+    pub check_email_provider: bool,
+}
+
+impl HealthSettings {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+}
+
+/// Domain rules for the addresses of public signups.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct EmailPolicySettings {
+    pub reject_disposable_domains: bool,
+    /// Replaces the bundled list of disposable domains, one domain per line
+    pub disposable_domains_path: Option<String>,
+    /// Asks signups from near misses of popular domains, e.g. `gmial.com`, to confirm their
+    /// address, suggesting a correction
+    pub suggest_typo_corrections: bool,
+    /// Only these domains, and their subdomains, are accepted when the list is not empty
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    #[serde(default)]
+    pub denied_domains: Vec<String>,
+}
+
+/// Limits on `POST /subscriptions`, each valid call of which sends an email.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct RateLimitSettings {
+    /// Proxies trusted to report the client address in `X-Forwarded-For`
+    #[serde(default)]
+    pub trusted_proxies: Vec<IpAddr>,
+    pub per_ip: TokenBucketSettings,
+    pub per_email: TokenBucketSettings,
 }
 
-#[derive(Clone, serde::Deserialize)]
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct BotProtectionSettings {
+    /// Signup forms submitted faster than this are rejected, `0` disables the check. Once
+    /// enabled, every signup must send back a token from `GET /subscriptions/form-token`
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_form_fill_seconds: u64,
+    /// Form tokens older than this are rejected, so that a scraped token cannot be replayed
+    /// for good
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_form_age_seconds: u64,
+    /// Key signing the start times handed out to signup forms, required once the fill-time
+    /// check is enabled
+    #[serde(default, serialize_with = "redact")]
+    pub form_token_secret: SecretString,
+    /// Server-side CAPTCHA verification, skipped when absent
+    pub captcha: Option<CaptchaSettings>,
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct CaptchaSettings {
+    pub verification_url: String,
+    #[serde(serialize_with = "redact")]
+    pub secret: SecretString,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub timeout_milliseconds: u64,
+}
+
+#[derive(Clone, Copy, serde::Deserialize, serde::Serialize)]
+pub struct TokenBucketSettings {
+    /// Number of requests allowed in a burst
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub capacity: u32,
+    /// Number of requests regained every minute
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub refill_per_minute: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    #[default]
+    Postgres,
+    /// Needs no server, e.g. to run the test suite. Replicas and statement timeouts are
+    /// Postgres only
+    Sqlite,
+}
+
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
 pub struct DatabaseSettings {
+    #[serde(default)]
+    pub backend: Backend,
+    /// Database file of the `sqlite` backend, `:memory:` for an in-memory database named
+    /// after `database_name`
+    pub sqlite_path: String,
     pub username: String,
+    #[serde(serialize_with = "redact")]
     pub password: SecretString,
     pub host: String,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub port: u16,
     pub database_name: String,
     pub require_ssl: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_connections: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_connections: u32,
+    /// How long a query waits for a free connection before failing with 503
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub acquire_timeout_milliseconds: u64,
+    /// Idle connections above `min_connections` are closed after this long, never if unset
+    pub idle_timeout_milliseconds: Option<u64>,
+    /// Connections are recycled after this long, never if unset
+    pub max_lifetime_milliseconds: Option<u64>,
+    /// Postgres aborts statements running longer than this, unbounded if unset
+    pub statement_timeout_milliseconds: Option<u64>,
+    /// Serves read-only queries, e.g. admin listings and exports, when set
+    #[serde(default)]
+    pub replica: Option<ReplicaSettings>,
+}
+
+/// A streaming replica of the database. It shares the credentials, database name and pool
+/// settings of the primary.
+#[derive(Clone, serde::Deserialize, serde::Serialize)]
+pub struct ReplicaSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    /// Shorter than that of the primary, as queries fall back to the primary after it
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub acquire_timeout_milliseconds: u64,
 }
 
 impl DatabaseSettings {
@@ -79,18 +316,80 @@ impl DatabaseSettings {
     }
 
     pub fn with_db(&self) -> PgConnectOptions {
-        self.without_db()
+        let options = self
+            .without_db()
             .database(&self.database_name)
-            .log_statements(tracing_log::log::LevelFilter::Trace)
+            .log_statements(tracing_log::log::LevelFilter::Trace);
+
+        match self.statement_timeout_milliseconds {
+            Some(timeout) => options.options([("statement_timeout", timeout.to_string())]),
+            None => options,
+        }
+    }
+
+    /// The settings of the replica, if any, as a database of its own.
+    pub fn replica(&self) -> Option<DatabaseSettings> {
+        self.replica.as_ref().map(|replica| DatabaseSettings {
+            host: replica.host.clone(),
+            port: replica.port,
+            acquire_timeout_milliseconds: replica.acquire_timeout_milliseconds,
+            replica: None,
+            ..self.clone()
+        })
+    }
+
+    pub fn sqlite_options(&self) -> SqliteConnectOptions {
+        let options = if self.is_in_memory() {
+            // Connections to a shared-cache in-memory database of the same name share its data
+            SqliteConnectOptions::from_str(&format!(
+                "sqlite:file:{}?mode=memory&cache=shared",
+                self.database_name
+            ))
+            .expect("Failed to parse in-memory SQLite options")
+        } else {
+            SqliteConnectOptions::new()
+                .filename(&self.sqlite_path)
+                .create_if_missing(true)
+        };
+
+        options.log_statements(tracing_log::log::LevelFilter::Trace)
+    }
+
+    pub fn is_in_memory(&self) -> bool {
+        self.backend == Backend::Sqlite && self.sqlite_path == ":memory:"
+    }
+
+    pub fn pool_options<DB: Database>(&self) -> PoolOptions<DB> {
+        let options = PoolOptions::new()
+            .max_connections(self.max_connections)
+            .acquire_timeout(Duration::from_millis(self.acquire_timeout_milliseconds));
+
+        // An in-memory database is gone once its last connection closes
+        if self.is_in_memory() {
+            options.min_connections(self.min_connections.max(1))
+        } else {
+            options
+                .min_connections(self.min_connections)
+                .idle_timeout(self.idle_timeout_milliseconds.map(Duration::from_millis))
+                .max_lifetime(self.max_lifetime_milliseconds.map(Duration::from_millis))
+        }
     }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct EmailClientSettings {
     pub base_url: String,
     pub sender_email: String,
+    #[serde(serialize_with = "redact")]
     pub authorization_token: SecretString,
+    /// Authorization tokens of other accounts with the provider, by name. Tenants sending
+    /// through an account of their own only store its name, the token stays out of the
+    /// database
+    #[serde(default, serialize_with = "redact_values")]
+    pub accounts: HashMap<String, SecretString>,
     pub timeout_milliseconds: u64,
+    /// Whether the provider accepts recipients with non-ASCII local parts (RFC 6531)
+    pub supports_smtputf8: bool,
 }
 
 impl EmailClientSettings {
@@ -103,6 +402,18 @@ impl EmailClientSettings {
     }
 }
 
+/// Secrets are left out of serialized settings, e.g. in the output of `check-config`.
+fn redact<S: serde::Serializer>(_secret: &SecretString, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str("[REDACTED]")
+}
+
+fn redact_values<S: serde::Serializer>(
+    secrets: &HashMap<String, SecretString>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(secrets.keys().map(|name| (name, "[REDACTED]")))
+}
+
 pub fn get_configuration() -> Result<Settings, config::ConfigError> {
     let base_path = std::env::current_dir().expect("Failed to determine the current directory");
     let configuration_directory = base_path.join("configuration");
@@ -0,0 +1,374 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+use crate::configuration::EmailPolicySettings;
+use crate::domain::SubscriberEmail;
+
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// Domains popular enough that a near miss is likely a typo.
+const POPULAR_DOMAINS: [&str; 20] = [
+    "aol.com",
+    "comcast.net",
+    "gmail.com",
+    "gmx.com",
+    "gmx.de",
+    "googlemail.com",
+    "hotmail.co.uk",
+    "hotmail.com",
+    "hotmail.fr",
+    "icloud.com",
+    "live.com",
+    "mail.com",
+    "me.com",
+    "msn.com",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+    "yahoo.co.uk",
+    "yahoo.com",
+    "ymail.com",
+];
+
+/// Real providers close to a popular domain, which must never be taken for its typos.
+const KNOWN_DOMAINS: [&str; 31] = [
+    "aim.com",
+    "email.com",
+    "gmx.at",
+    "gmx.ch",
+    "gmx.net",
+    "hotmail.be",
+    "hotmail.ca",
+    "hotmail.de",
+    "hotmail.es",
+    "hotmail.it",
+    "hotmail.nl",
+    "live.ca",
+    "live.co.uk",
+    "live.de",
+    "live.fr",
+    "live.nl",
+    "mac.com",
+    "mail.de",
+    "mail.ru",
+    "outlook.de",
+    "outlook.es",
+    "outlook.fr",
+    "protonmail.ch",
+    "yahoo.ca",
+    "yahoo.co.in",
+    "yahoo.co.jp",
+    "yahoo.de",
+    "yahoo.es",
+    "yahoo.fr",
+    "yahoo.in",
+    "yahoo.it",
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EmailPolicyViolation {
+    DisposableDomain(String),
+    DeniedDomain(String),
+    DomainNotAllowed(String),
+}
+
+impl Display for EmailPolicyViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::DisposableDomain(domain) => {
+                write!(f, "Disposable addresses from {} are not accepted", domain)
+            }
+            Self::DeniedDomain(domain) | Self::DomainNotAllowed(domain) => {
+                write!(f, "Addresses from {} are not accepted", domain)
+            }
+        }
+    }
+}
+
+/// Extra rules on top of `SubscriberEmail::parse`, applied to public signups.
+pub struct EmailPolicy {
+    disposable_domains: HashSet<String>,
+    suggest_typo_corrections: bool,
+    allowed_domains: Vec<String>,
+    denied_domains: Vec<String>,
+}
+
+impl EmailPolicy {
+    pub fn new(settings: &EmailPolicySettings) -> Result<Self, std::io::Error> {
+        let disposable_domains = if !settings.reject_disposable_domains {
+            HashSet::new()
+        } else if let Some(path) = &settings.disposable_domains_path {
+            parse_domain_list(&std::fs::read_to_string(path)?)
+        } else {
+            parse_domain_list(BUNDLED_DISPOSABLE_DOMAINS)
+        };
+
+        Ok(Self {
+            disposable_domains,
+            suggest_typo_corrections: settings.suggest_typo_corrections,
+            allowed_domains: normalize_domains(&settings.allowed_domains),
+            denied_domains: normalize_domains(&settings.denied_domains),
+        })
+    }
+
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), EmailPolicyViolation> {
+        let (_, domain) = split_domain(email);
+
+        if !self.allowed_domains.is_empty()
+            && !self
+                .allowed_domains
+                .iter()
+                .any(|allowed| matches_domain(&domain, allowed))
+        {
+            return Err(EmailPolicyViolation::DomainNotAllowed(domain));
+        }
+
+        if self
+            .denied_domains
+            .iter()
+            .any(|denied| matches_domain(&domain, denied))
+        {
+            return Err(EmailPolicyViolation::DeniedDomain(domain));
+        }
+
+        if parent_domains(&domain).any(|parent| self.disposable_domains.contains(parent)) {
+            return Err(EmailPolicyViolation::DisposableDomain(domain));
+        }
+
+        Ok(())
+    }
+
+    /// Suggests a corrected address when the domain is one typo away from a popular one.
+    ///
+    /// Only a hint, the person signing up may still confirm the address as they typed it.
+    pub fn suggest_correction(&self, email: &SubscriberEmail) -> Option<String> {
+        // An allow list already pins the domains down, so there is nothing to correct
+        if !self.suggest_typo_corrections || !self.allowed_domains.is_empty() {
+            return None;
+        }
+
+        let (local_part, domain) = split_domain(email);
+        suggest_domain(&domain).map(|correction| format!("{}@{}", local_part, correction))
+    }
+}
+
+/// The local part and the lowercase ASCII-compatible domain of `email`.
+fn split_domain(email: &SubscriberEmail) -> (&str, String) {
+    let (local_part, domain) = email
+        .ascii_compatible()
+        .rsplit_once('@')
+        .expect("A parsed email always contains an @");
+
+    (local_part, domain.to_lowercase())
+}
+
+fn parse_domain_list(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_lowercase)
+        .collect()
+}
+
+fn normalize_domains(domains: &[String]) -> Vec<String> {
+    domains
+        .iter()
+        .map(|domain| domain.trim().to_lowercase())
+        .collect()
+}
+
+/// Whether `domain` is `rule` or one of its subdomains.
+fn matches_domain(domain: &str, rule: &str) -> bool {
+    parent_domains(domain).any(|parent| parent == rule)
+}
+
+/// `mail.example.com`, `example.com` and `com` for `mail.example.com`.
+fn parent_domains(domain: &str) -> impl Iterator<Item = &str> {
+    std::iter::once(domain).chain(
+        domain
+            .match_indices('.')
+            .map(move |(index, _)| &domain[index + 1..]),
+    )
+}
+
+fn suggest_domain(domain: &str) -> Option<&'static str> {
+    if POPULAR_DOMAINS.contains(&domain) || KNOWN_DOMAINS.contains(&domain) {
+        return None;
+    }
+
+    // Two edits away already reaches other real providers, e.g. `hotmail.it` from `hotmail.fr`
+    POPULAR_DOMAINS
+        .iter()
+        .find(|popular| edit_distance(domain, popular) <= 1)
+        .copied()
+}
+
+/// Optimal string alignment distance, where swapping two adjacent characters is a single edit.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut distances = vec![vec![0; b.len() + 1]; a.len() + 1];
+
+    for (i, row) in distances.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, distance) in distances[0].iter_mut().enumerate() {
+        *distance = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut distance = (distances[i - 1][j] + 1)
+                .min(distances[i][j - 1] + 1)
+                .min(distances[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                distance = distance.min(distances[i - 2][j - 2] + 1);
+            }
+            distances[i][j] = distance;
+        }
+    }
+
+    distances[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err_eq, assert_none, assert_ok, assert_some_eq};
+
+    use crate::configuration::EmailPolicySettings;
+    use crate::domain::{EmailPolicy, EmailPolicyViolation, SubscriberEmail};
+
+    fn settings() -> EmailPolicySettings {
+        EmailPolicySettings {
+            reject_disposable_domains: true,
+            disposable_domains_path: None,
+            suggest_typo_corrections: true,
+            allowed_domains: vec![],
+            denied_domains: vec![],
+        }
+    }
+
+    fn check(settings: EmailPolicySettings, email: &str) -> Result<(), EmailPolicyViolation> {
+        let email = SubscriberEmail::parse(email.to_string()).unwrap();
+        EmailPolicy::new(&settings).unwrap().check(&email)
+    }
+
+    #[test]
+    fn a_regular_address_is_accepted() {
+        assert_ok!(check(settings(), "ursula@gmail.com"));
+        assert_ok!(check(settings(), "ursula@example.org"));
+    }
+
+    #[test]
+    fn bundled_disposable_domains_and_their_subdomains_are_rejected() {
+        assert_err_eq!(
+            check(settings(), "ursula@Mailinator.com"),
+            EmailPolicyViolation::DisposableDomain("mailinator.com".into())
+        );
+        assert_err_eq!(
+            check(settings(), "ursula@inbox.yopmail.com"),
+            EmailPolicyViolation::DisposableDomain("inbox.yopmail.com".into())
+        );
+    }
+
+    #[test]
+    fn disposable_domains_are_accepted_when_the_check_is_disabled() {
+        let settings = EmailPolicySettings {
+            reject_disposable_domains: false,
+            ..settings()
+        };
+
+        assert_ok!(check(settings, "ursula@mailinator.com"));
+    }
+
+    fn suggestion(settings: EmailPolicySettings, email: &str) -> Option<String> {
+        let email = SubscriberEmail::parse(email.to_string()).unwrap();
+        EmailPolicy::new(&settings)
+            .unwrap()
+            .suggest_correction(&email)
+    }
+
+    #[test]
+    fn typos_of_popular_domains_come_with_a_suggestion() {
+        for (email, expected) in [
+            ("ursula@gmial.com", "ursula@gmail.com"),
+            ("ursula@gmail.con", "ursula@gmail.com"),
+            ("ursula@hotmial.com", "ursula@hotmail.com"),
+            ("ursula@yaho.com", "ursula@yahoo.com"),
+        ] {
+            assert_some_eq!(suggestion(settings(), email), expected);
+            // Only a suggestion, the address is still acceptable
+            assert_ok!(check(settings(), email));
+        }
+    }
+
+    #[test]
+    fn popular_domains_close_to_each_other_are_not_typos() {
+        assert_none!(suggestion(settings(), "ursula@mail.com"));
+        assert_none!(suggestion(settings(), "ursula@gmx.de"));
+        assert_none!(suggestion(settings(), "ursula@ymail.com"));
+    }
+
+    #[test]
+    fn known_providers_are_not_typos() {
+        for email in [
+            "ursula@yahoo.co.in",
+            "ursula@yahoo.co.jp",
+            "ursula@hotmail.it",
+            "ursula@hotmail.es",
+            "ursula@hotmail.de",
+            "ursula@hotmail.be",
+            "ursula@email.com",
+        ] {
+            assert_none!(
+                suggestion(settings(), email),
+                "{} was taken for a typo",
+                email
+            );
+        }
+    }
+
+    #[test]
+    fn domains_two_typos_away_are_not_corrected() {
+        assert_none!(suggestion(settings(), "ursula@gmial.con"));
+    }
+
+    #[test]
+    fn no_correction_is_suggested_when_disabled() {
+        let settings = EmailPolicySettings {
+            suggest_typo_corrections: false,
+            ..settings()
+        };
+
+        assert_none!(suggestion(settings, "ursula@gmial.com"));
+    }
+
+    #[test]
+    fn only_allowed_domains_are_accepted_when_an_allow_list_is_set() {
+        let settings = EmailPolicySettings {
+            allowed_domains: vec!["Example.com".into()],
+            ..settings()
+        };
+
+        assert_ok!(check(settings.clone(), "ursula@example.com"));
+        assert_ok!(check(settings.clone(), "ursula@staff.example.com"));
+        assert_err_eq!(
+            check(settings, "ursula@gmail.com"),
+            EmailPolicyViolation::DomainNotAllowed("gmail.com".into())
+        );
+    }
+
+    #[test]
+    fn denied_domains_are_rejected() {
+        let settings = EmailPolicySettings {
+            denied_domains: vec!["competitor.com".into()],
+            ..settings()
+        };
+
+        assert_err_eq!(
+            check(settings, "ursula@competitor.com"),
+            EmailPolicyViolation::DeniedDomain("competitor.com".into())
+        );
+    }
+}
@@ -1,7 +1,11 @@
+mod email_policy;
 mod new_subscriber;
 mod subscriber_email;
 mod subscriber_name;
+mod subscriber_tag;
 
+pub use email_policy::{EmailPolicy, EmailPolicyViolation};
 pub use new_subscriber::NewSubscriber;
 pub use subscriber_email::SubscriberEmail;
 pub use subscriber_name::SubscriberName;
+pub use subscriber_tag::SubscriberTag;
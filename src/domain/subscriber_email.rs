@@ -1,29 +1,93 @@
 use std::fmt::{Debug, Display, Formatter};
 
+use unicode_normalization::UnicodeNormalization;
 use validator::ValidateEmail;
 
-#[derive(Debug)]
-pub struct SubscriberEmail(String);
+#[derive(Debug, Clone)]
+pub struct SubscriberEmail {
+    email: String,
+    ascii: String,
+    normalized: String,
+}
 
 impl Display for SubscriberEmail {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        Display::fmt(&self, f)
+        Display::fmt(&self.email, f)
     }
 }
 
 impl SubscriberEmail {
+    /// Returns an instance of `SubscriberEmail` if the input is a valid address.
+    /// The address is trimmed and its domain is lowercased. The local part keeps its case,
+    /// as typed by the subscriber, and may contain non-ASCII characters (RFC 6531),
+    /// in which case it is brought to NFC.
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if ValidateEmail::validate_email(&s) {
-            Ok(Self(s))
+        let invalid = || format!("{} is not a valid subscriber email", s);
+
+        let (local_part, domain) = s.trim().rsplit_once('@').ok_or_else(invalid)?;
+        let local_part: String = local_part.nfc().collect();
+        let ascii_domain = idna::domain_to_ascii(domain).map_err(|_| invalid())?;
+        let (unicode_domain, result) = idna::domain_to_unicode(&ascii_domain);
+        result.map_err(|_| invalid())?;
+
+        let ascii = format!("{}@{}", local_part, ascii_domain);
+        let is_valid = if local_part.is_ascii() {
+            ValidateEmail::validate_email(&ascii)
         } else {
-            Err(format!("{} is not a valid subscriber email", s))
+            is_valid_utf8_local_part(&local_part)
+                && ValidateEmail::validate_email(&format!("a@{}", ascii_domain))
+        };
+
+        if is_valid {
+            Ok(Self {
+                email: format!("{}@{}", local_part, unicode_domain),
+                normalized: ascii.to_lowercase(),
+                ascii,
+            })
+        } else {
+            Err(invalid())
         }
     }
+
+    /// The address with its domain in punycode, which is what mail servers route on.
+    /// Its local part is still non-ASCII when [`Self::requires_smtputf8`] holds.
+    pub fn ascii_compatible(&self) -> &str {
+        &self.ascii
+    }
+
+    /// The address used to tell subscribers apart, which ignores case entirely.
+    pub fn normalized(&self) -> &str {
+        &self.normalized
+    }
+
+    /// Whether delivering to this address needs a transport supporting SMTPUTF8.
+    /// Internationalized domains alone do not, since they have an ASCII form.
+    pub fn requires_smtputf8(&self) -> bool {
+        !self.ascii.is_ascii()
+    }
+}
+
+/// Validates a dot-atom local part where, as allowed by RFC 6531, any non-ASCII
+/// character that is not a control or whitespace character counts as `atext`.
+fn is_valid_utf8_local_part(local_part: &str) -> bool {
+    const ASCII_ATEXT_SPECIALS: &str = "!#$%&'*+-/=?^_`{|}~";
+
+    local_part.len() <= 64
+        && local_part.split('.').all(|atom| {
+            !atom.is_empty()
+                && atom.chars().all(|c| {
+                    if c.is_ascii() {
+                        c.is_ascii_alphanumeric() || ASCII_ATEXT_SPECIALS.contains(c)
+                    } else {
+                        !c.is_control() && !c.is_whitespace()
+                    }
+                })
+        })
 }
 
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.email
     }
 }
 
@@ -56,6 +120,69 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn email_is_trimmed_and_its_domain_lowercased() {
+        let email = SubscriberEmail::parse(" Ursula@Example.COM\n".to_string()).unwrap();
+
+        assert_eq!(email.as_ref(), "Ursula@example.com");
+        assert_eq!(email.normalized(), "ursula@example.com");
+    }
+
+    #[test]
+    fn internationalized_domain_is_kept_in_both_forms() {
+        let email = SubscriberEmail::parse("ursula@Bücher.de".to_string()).unwrap();
+
+        assert_eq!(email.as_ref(), "ursula@bücher.de");
+        assert_eq!(email.ascii_compatible(), "ursula@xn--bcher-kva.de");
+        assert!(!email.requires_smtputf8());
+    }
+
+    #[test]
+    fn punycode_domain_round_trips_to_unicode() {
+        let email = SubscriberEmail::parse("ursula@xn--bcher-kva.de".to_string()).unwrap();
+
+        assert_eq!(email.as_ref(), "ursula@bücher.de");
+        assert_eq!(email.ascii_compatible(), "ursula@xn--bcher-kva.de");
+    }
+
+    #[test]
+    fn unicode_local_part_is_accepted_and_requires_smtputf8() {
+        let email = SubscriberEmail::parse("用户@例子.广告".to_string()).unwrap();
+
+        assert_eq!(email.as_ref(), "用户@例子.广告");
+        assert_eq!(email.ascii_compatible(), "用户@xn--fsqu00a.xn--4rr70v");
+        assert!(email.requires_smtputf8());
+    }
+
+    #[test]
+    fn unicode_local_part_is_brought_to_nfc() {
+        let decomposed = SubscriberEmail::parse("jose\u{301}@example.com".to_string()).unwrap();
+
+        assert_eq!(decomposed.as_ref(), "jos\u{e9}@example.com");
+    }
+
+    #[test]
+    fn unicode_local_part_with_whitespace_is_rejected() {
+        assert_err!(SubscriberEmail::parse("jo sé@example.com".to_string()));
+        assert_err!(SubscriberEmail::parse("josé.@example.com".to_string()));
+        assert_err!(SubscriberEmail::parse("josé@".to_string()));
+    }
+
+    #[test]
+    fn addresses_differing_only_by_case_have_the_same_normalized_form() {
+        let lower = SubscriberEmail::parse("foo@example.com".to_string()).unwrap();
+        let mixed = SubscriberEmail::parse("Foo@Example.com".to_string()).unwrap();
+
+        assert_eq!(lower.normalized(), mixed.normalized());
+    }
+
+    #[test]
+    fn email_is_displayed_as_parsed() {
+        let email = SubscriberEmail::parse("Ursula@Example.com".to_string()).unwrap();
+
+        assert_eq!(email.to_string(), "Ursula@example.com");
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
 
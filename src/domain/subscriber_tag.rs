@@ -0,0 +1,86 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SubscriberTag(String);
+
+impl SubscriberTag {
+    /// Returns an instance of `SubscriberTag` if the input satisfies all the validation constraints.
+    /// Tags are free-form but trimmed and lowercased, so `VIP` and ` vip ` are the same tag.
+    pub fn parse(s: String) -> Result<SubscriberTag, String> {
+        let tag = s.trim().to_lowercase();
+
+        let is_empty = tag.is_empty();
+        let is_too_long = tag.graphemes(true).count() > 64;
+        // Commas separate tags in the signup form
+        let contains_forbidden_characters = tag.chars().any(|c| c == ',' || c.is_control());
+
+        if is_empty || is_too_long || contains_forbidden_characters {
+            Err(format!("{} is not a valid subscriber tag", s))
+        } else {
+            Ok(Self(tag))
+        }
+    }
+
+    /// Parses a comma separated list of tags, as submitted by the hidden signup form field.
+    pub fn parse_list(s: &str) -> Result<Vec<SubscriberTag>, String> {
+        let mut tags = Vec::new();
+
+        for tag in s.split(',').filter(|tag| !tag.trim().is_empty()) {
+            let tag = Self::parse(tag.to_string())?;
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+
+        Ok(tags)
+    }
+}
+
+impl AsRef<str> for SubscriberTag {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_err, assert_ok};
+
+    use super::SubscriberTag;
+
+    #[test]
+    fn tags_are_trimmed_and_lowercased() {
+        let tag = SubscriberTag::parse(" Early-Adopter ".to_string()).unwrap();
+        assert_eq!(tag.as_ref(), "early-adopter");
+    }
+
+    #[test]
+    fn a_64_grapheme_long_tag_is_valid() {
+        let tag = "ё".repeat(64);
+        assert_ok!(SubscriberTag::parse(tag));
+    }
+
+    #[test]
+    fn a_tag_longer_than_64_graphemes_is_rejected() {
+        let tag = "a".repeat(65);
+        assert_err!(SubscriberTag::parse(tag));
+    }
+
+    #[test]
+    fn whitespace_only_tags_are_rejected() {
+        assert_err!(SubscriberTag::parse(" ".to_string()));
+    }
+
+    #[test]
+    fn tags_containing_control_characters_are_rejected() {
+        assert_err!(SubscriberTag::parse("v\u{7}ip".to_string()));
+    }
+
+    #[test]
+    fn tag_lists_are_split_on_commas_and_deduplicated() {
+        let tags = SubscriberTag::parse_list("vip, beta,,VIP").unwrap();
+        let tags: Vec<&str> = tags.iter().map(AsRef::as_ref).collect();
+
+        assert_eq!(tags, vec!["vip", "beta"]);
+    }
+}
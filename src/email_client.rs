@@ -1,15 +1,42 @@
-use std::time::Duration;
-
-use reqwest::Client;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use prometheus::core::Collector;
+use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, Response};
 use secrecy::{ExposeSecret, SecretString};
 
 use crate::domain::SubscriberEmail;
+use crate::routes::error_chain_fmt;
+use crate::telemetry::inject_trace_context;
 
+#[derive(Clone)]
 pub struct EmailClient {
     http_client: Client,
     base_url: String,
     sender: SubscriberEmail,
     authorization_token: SecretString,
+    /// Tokens of the other accounts `with_account` may switch to, by name
+    accounts: Arc<HashMap<String, SecretString>>,
+    supports_smtputf8: bool,
+    request_duration: HistogramVec,
+    errors: IntCounterVec,
+}
+
+#[derive(thiserror::Error)]
+pub enum SendEmailError {
+    #[error("{0} has a non-ASCII local part, which cannot be delivered without SMTPUTF8 support")]
+    Smtputf8Unsupported(String),
+    #[error(transparent)]
+    RequestError(#[from] reqwest::Error),
+}
+
+impl std::fmt::Debug for SendEmailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
 }
 
 impl EmailClient {
@@ -18,43 +45,146 @@ impl EmailClient {
         sender: SubscriberEmail,
         authorization_token: SecretString,
         timeout: Duration,
+        supports_smtputf8: bool,
     ) -> Self {
         let http_client = Client::builder().timeout(timeout).build().unwrap();
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "email_client_request_duration_seconds",
+                "Time spent waiting for the email provider, by outcome",
+            ),
+            &["outcome"],
+        )
+        .expect("Invalid email client metric");
+        let errors = IntCounterVec::new(
+            Opts::new("email_client_errors_total", "Emails that could not be sent"),
+            &["kind"],
+        )
+        .expect("Invalid email client metric");
 
         Self {
             http_client,
             base_url,
             sender,
             authorization_token,
+            accounts: Arc::new(HashMap::new()),
+            supports_smtputf8,
+            request_duration,
+            errors,
+        }
+    }
+
+    /// Registers the other accounts with the provider, for `with_account` to pick from.
+    pub fn with_accounts(mut self, accounts: HashMap<String, SecretString>) -> Self {
+        self.accounts = Arc::new(accounts);
+        self
+    }
+
+    /// The same client, sending from another address or through another of the registered
+    /// accounts where given. Its metrics are shared with this one.
+    pub fn with_account(
+        &self,
+        sender: Option<SubscriberEmail>,
+        account: Option<&str>,
+    ) -> Result<Self, String> {
+        let mut client = self.clone();
+        if let Some(sender) = sender {
+            client.sender = sender;
+        }
+        if let Some(account) = account {
+            client.authorization_token = self
+                .accounts
+                .get(account)
+                .cloned()
+                .ok_or_else(|| format!("{} is not a configured email account", account))?;
+        }
+        Ok(client)
+    }
+
+    /// Collectors for the latency and failures of email delivery, to be registered once.
+    pub fn metrics(&self) -> Vec<Box<dyn Collector>> {
+        vec![
+            Box::new(self.request_duration.clone()),
+            Box::new(self.errors.clone()),
+        ]
+    }
+
+    /// Fails if the recipient can only be reached through SMTPUTF8 and the transport lacks it.
+    /// Internationalized domains are always deliverable, using their punycode form.
+    pub fn check_recipient(&self, recipient: &SubscriberEmail) -> Result<(), SendEmailError> {
+        if recipient.requires_smtputf8() && !self.supports_smtputf8 {
+            Err(SendEmailError::Smtputf8Unsupported(recipient.to_string()))
+        } else {
+            Ok(())
         }
     }
 
+    /// Succeeds when the provider answers HTTP requests at all, whatever the status code.
+    pub async fn check_reachable(&self) -> Result<(), reqwest::Error> {
+        self.http_client.head(&self.base_url).send().await?;
+
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Send an email", skip_all)]
     pub async fn send_email(
         &self,
         recipient: &SubscriberEmail,
         subject: &str,
         html_content: &str,
         text_content: &str,
-    ) -> Result<(), reqwest::Error> {
+    ) -> Result<(), SendEmailError> {
+        if let Err(e) = self.check_recipient(recipient) {
+            self.errors
+                .with_label_values(&["smtputf8_unsupported"])
+                .inc();
+            return Err(e);
+        }
+
         let url = format!("{}/email", self.base_url);
         let request_body = SendEmailRequest {
-            from: self.sender.as_ref(),
-            to: recipient.as_ref(),
+            from: self.sender.ascii_compatible(),
+            to: recipient.ascii_compatible(),
             subject,
             html_body: html_content,
             text_body: text_content,
         };
 
-        self.http_client
+        // Lets the email provider, or a proxy in front of it, join our trace
+        let mut trace_headers = HeaderMap::new();
+        inject_trace_context(&mut trace_headers);
+
+        let start = Instant::now();
+        let result = self
+            .http_client
             .post(&url)
+            .headers(trace_headers)
             .header(
                 "X-Postmark-Server-Token",
                 self.authorization_token.expose_secret(),
             )
             .json(&request_body)
             .send()
-            .await?
-            .error_for_status()?;
+            .await
+            .and_then(Response::error_for_status);
+        let outcome = if result.is_ok() { "success" } else { "error" };
+        self.request_duration
+            .with_label_values(&[outcome])
+            .observe(start.elapsed().as_secs_f64());
+
+        if let Err(e) = result {
+            let kind = if e.is_timeout() {
+                "timeout"
+            } else if e.is_connect() {
+                "connect"
+            } else if e.is_status() {
+                "status"
+            } else {
+                "request"
+            };
+            self.errors.with_label_values(&[kind]).inc();
+            return Err(e.into());
+        }
 
         Ok(())
     }
@@ -72,6 +202,7 @@ struct SendEmailRequest<'a> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::time::Duration;
 
     use claims::{assert_err, assert_ok};
@@ -79,11 +210,11 @@ mod tests {
     use fake::faker::lorem::en::{Paragraph, Sentence};
     use fake::{Fake, Faker};
     use secrecy::SecretString;
-    use wiremock::matchers::{any, header, header_exists, method, path};
+    use wiremock::matchers::{any, body_partial_json, header, header_exists, method, path};
     use wiremock::{Match, Mock, MockServer, Request, ResponseTemplate};
 
     use crate::domain::SubscriberEmail;
-    use crate::email_client::EmailClient;
+    use crate::email_client::{EmailClient, SendEmailError};
 
     struct SendEmailBodyMatcher;
 
@@ -111,6 +242,7 @@ mod tests {
             email(),
             SecretString::from(Faker.fake::<String>()),
             Duration::from_millis(200),
+            false,
         )
     }
 
@@ -209,4 +341,121 @@ mod tests {
         // Assert
         assert_err!(result);
     }
+
+    #[tokio::test]
+    async fn send_email_uses_the_ascii_compatible_form_of_idn_recipients() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = SubscriberEmail::parse("ursula@bücher.de".to_string()).unwrap();
+
+        Mock::given(body_partial_json(
+            serde_json::json!({ "To": "ursula@xn--bcher-kva.de" }),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+        // Act
+        let result = email_client
+            .send_email(&recipient, &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(result);
+    }
+
+    #[tokio::test]
+    async fn send_email_rejects_unicode_local_parts_without_smtputf8() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(mock_server.uri());
+        let recipient = SubscriberEmail::parse("josé@example.com".to_string()).unwrap();
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let result = email_client
+            .send_email(&recipient, &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert!(matches!(
+            result,
+            Err(SendEmailError::Smtputf8Unsupported(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn send_email_delivers_unicode_local_parts_with_smtputf8() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let email_client = EmailClient::new(
+            mock_server.uri(),
+            email(),
+            SecretString::from(Faker.fake::<String>()),
+            Duration::from_millis(200),
+            true,
+        );
+        let recipient = SubscriberEmail::parse("josé@bücher.de".to_string()).unwrap();
+
+        Mock::given(body_partial_json(
+            serde_json::json!({ "To": "josé@xn--bcher-kva.de" }),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&mock_server)
+        .await;
+
+        // Act
+        let result = email_client
+            .send_email(&recipient, &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(result);
+    }
+
+    #[test]
+    fn with_account_rejects_an_unknown_account() {
+        let result = email_client("http://localhost".into()).with_account(None, Some("acme"));
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn with_account_sends_from_the_given_sender_and_token() {
+        // Arrange
+        let mock_server = MockServer::start().await;
+        let sender = SubscriberEmail::parse("news@acme.example.com".to_string()).unwrap();
+        let email_client = email_client(mock_server.uri())
+            .with_accounts(HashMap::from([(
+                "acme".to_string(),
+                SecretString::from("acme-token"),
+            )]))
+            .with_account(Some(sender), Some("acme"))
+            .unwrap();
+
+        Mock::given(header("X-Postmark-Server-Token", "acme-token"))
+            .and(body_partial_json(
+                serde_json::json!({ "From": "news@acme.example.com" }),
+            ))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        // Act
+        let result = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        // Assert
+        assert_ok!(result);
+    }
 }
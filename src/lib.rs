@@ -1,6 +1,17 @@
+pub mod audit;
+pub mod authentication;
+pub mod bot_protection;
+pub mod cli;
 pub mod configuration;
 pub mod domain;
 pub mod email_client;
+pub mod metrics;
+pub mod migrations;
+pub mod problem;
+pub mod rate_limit;
+pub mod replica;
 pub mod routes;
+pub mod shutdown;
 pub mod startup;
 pub mod telemetry;
+pub mod tenant;
@@ -1,17 +1,139 @@
-use zero2prod_axum::configuration::get_configuration;
-use zero2prod_axum::startup::Application;
-use zero2prod_axum::telemetry::{get_subscriber, init_subscriber};
+use clap::Parser;
+use migration::{Migrator, MigratorTrait};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use secrecy::{ExposeSecret, SecretString};
+use zero2prod_axum::cli::{
+    Cli, Command, MigrateCommand, NewTenant, check_config, create_tenant, create_user,
+    generate_password, migration_status, seed, send_test_email,
+};
+use zero2prod_axum::configuration::{Settings, get_configuration};
+use zero2prod_axum::migrations::run_migrations;
+use zero2prod_axum::startup::{Application, get_database_connection, get_email_client};
+use zero2prod_axum::telemetry::{get_subscriber, get_tracer_provider, init_subscriber};
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    let configuration = get_configuration()?;
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(configuration).await?,
+        command => {
+            // Only warnings, on stderr, so that the output of commands stays readable
+            init_subscriber(get_subscriber(
+                "zero2prod-axum".into(),
+                "warn".into(),
+                std::io::stderr,
+                &SdkTracerProvider::builder().build(),
+            ));
+            run_command(command, configuration).await?
+        }
+    }
+
+    Ok(())
+}
+
+async fn serve(configuration: Settings) -> std::io::Result<()> {
+    // The same checks as `check-config`, before anything is started
+    configuration
+        .validate()
+        .map_err(|errors| std::io::Error::other(errors.join("\n")))?;
+
     // Setup logger
-    let tracing_subscriber =
-        get_subscriber("zero2prod-axum".into(), "info".into(), std::io::stdout);
+    let tracer_provider = get_tracer_provider("zero2prod-axum".into(), &configuration.telemetry)
+        .expect("Failed to build the OpenTelemetry exporter");
+    let tracing_subscriber = get_subscriber(
+        "zero2prod-axum".into(),
+        "info".into(),
+        std::io::stdout,
+        &tracer_provider,
+    );
     init_subscriber(tracing_subscriber);
 
-    let configuration = get_configuration().expect("Failed to read configuration");
-
     let application = Application::build(configuration).await?;
+    let result = application.start_service().await;
+
+    // Flush the spans that have not been exported yet
+    if let Err(e) = tracer_provider.shutdown() {
+        eprintln!("Failed to shut down the OpenTelemetry exporter: {}", e);
+    }
+
+    result
+}
+
+async fn run_command(command: Command, configuration: Settings) -> anyhow::Result<()> {
+    match command {
+        Command::Serve => unreachable!("Serving is handled by `serve`"),
+        Command::Migrate(command) => {
+            let db_connection = get_database_connection(&configuration.database);
+            match command {
+                MigrateCommand::Up => run_migrations(&db_connection).await?,
+                MigrateCommand::Down { steps } => {
+                    Migrator::down(&db_connection, Some(steps)).await?
+                }
+                MigrateCommand::Status => {
+                    for (name, applied) in migration_status(&db_connection).await? {
+                        println!("{} {}", if applied { "applied" } else { "pending" }, name);
+                    }
+                }
+            }
+        }
+        Command::CreateUser {
+            username,
+            password,
+            tenant,
+        } => {
+            let db_connection = get_database_connection(&configuration.database);
+            let (password, generated) = match password {
+                Some(password) => (SecretString::from(password), false),
+                None => (generate_password(), true),
+            };
+            create_user(&db_connection, &tenant, &username, password.clone()).await?;
+            println!("Created user {}", username);
+            if generated {
+                println!("Password: {}", password.expose_secret());
+            }
+        }
+        Command::CreateTenant {
+            slug,
+            host,
+            base_url,
+            sender_email,
+            email_account,
+        } => {
+            // The token of the account is only known to the configuration
+            if let Some(account) = &email_account
+                && !configuration.email_client.accounts.contains_key(account)
+            {
+                anyhow::bail!("{} is not an account of email_client.accounts", account);
+            }
+            let db_connection = get_database_connection(&configuration.database);
+            let tenant = NewTenant {
+                slug,
+                host,
+                base_url,
+                sender_email,
+                email_account,
+            };
+            let slug = tenant.slug.clone();
+            create_tenant(&db_connection, tenant).await?;
+            println!("Created tenant {}", slug);
+        }
+        Command::SendTestEmail { address } => {
+            let email_client = get_email_client(configuration.email_client);
+            send_test_email(&email_client, &address).await?;
+            println!("Sent a test email to {}", address);
+        }
+        Command::CheckConfig => match check_config(&configuration) {
+            Ok(dump) => println!("{}", dump),
+            Err(errors) => anyhow::bail!("Invalid configuration:\n{}", errors.join("\n")),
+        },
+        Command::Seed => {
+            let db_connection = get_database_connection(&configuration.database);
+            let inserted = seed(&db_connection).await?;
+            println!("Inserted {} subscribers", inserted);
+        }
+    }
 
-    application.start_service().await
+    Ok(())
 }
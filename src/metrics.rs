@@ -0,0 +1,192 @@
+//! Prometheus metrics, scraped from `GET /metrics`.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::extract::{MatchedPath, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use sea_orm::{ConnectionTrait, DatabaseBackend, DatabaseConnection};
+
+use crate::email_client::EmailClient;
+use crate::startup::ApplicationState;
+
+/// Every metric of one application instance. Each instance has a registry of its own,
+/// so that tests spawning several applications do not share counters.
+pub struct Metrics {
+    registry: Registry,
+    http_requests: IntCounterVec,
+    http_request_duration: HistogramVec,
+    pub signups: IntCounter,
+    pub confirmations: IntCounter,
+    /// Labelled by `outcome`, either `sent` or `skipped`
+    pub newsletter_emails: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new(
+        db_connection: &DatabaseConnection,
+        email_client: &EmailClient,
+    ) -> Result<Self, prometheus::Error> {
+        let registry = Registry::new();
+
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time spent handling HTTP requests",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let signups = IntCounter::new(
+            "subscription_signups_total",
+            "Signups that were sent a confirmation email",
+        )?;
+        let confirmations = IntCounter::new(
+            "subscription_confirmations_total",
+            "Subscriptions confirmed through their confirmation link",
+        )?;
+        let newsletter_emails = IntCounterVec::new(
+            Opts::new(
+                "newsletter_emails_total",
+                "Newsletter issue emails, by delivery outcome",
+            ),
+            &["outcome"],
+        )?;
+
+        registry.register(Box::new(http_requests.clone()))?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        registry.register(Box::new(signups.clone()))?;
+        registry.register(Box::new(confirmations.clone()))?;
+        registry.register(Box::new(newsletter_emails.clone()))?;
+        for collector in email_client.metrics() {
+            registry.register(collector)?;
+        }
+        registry.register(Box::new(PoolCollector::new(db_connection.clone())?))?;
+
+        Ok(Self {
+            registry,
+            http_requests,
+            http_request_duration,
+            signups,
+            confirmations,
+            newsletter_emails,
+        })
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn encode(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+
+        String::from_utf8(buffer).map_err(|e| prometheus::Error::Msg(e.to_string()))
+    }
+}
+
+/// Middleware counting and timing requests by method, route template and status code.
+pub async fn track_http_metrics(
+    State(state): State<Arc<ApplicationState>>,
+    matched_path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    // The route template rather than the URI, e.g. `/admin/api/subscribers/{id}`, so that
+    // the number of series stays bounded
+    let route = matched_path
+        .as_ref()
+        .map(MatchedPath::as_str)
+        .unwrap_or("unmatched")
+        .to_string();
+    let method = request.method().to_string();
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let status = response.status().as_u16().to_string();
+    let labels = [method.as_str(), route.as_str(), status.as_str()];
+    state.metrics.http_requests.with_label_values(&labels).inc();
+    state
+        .metrics
+        .http_request_duration
+        .with_label_values(&labels)
+        .observe(elapsed);
+
+    response
+}
+
+/// Reads the utilisation of the connection pool when metrics are gathered.
+struct PoolCollector {
+    db_connection: DatabaseConnection,
+    connections: IntGaugeVec,
+    max_connections: IntGauge,
+}
+
+impl PoolCollector {
+    fn new(db_connection: DatabaseConnection) -> Result<Self, prometheus::Error> {
+        Ok(Self {
+            db_connection,
+            connections: IntGaugeVec::new(
+                Opts::new("db_pool_connections", "Open database connections, by state"),
+                &["state"],
+            )?,
+            max_connections: IntGauge::new(
+                "db_pool_max_connections",
+                "Maximum number of database connections",
+            )?,
+        })
+    }
+}
+
+impl Collector for PoolCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.connections
+            .desc()
+            .into_iter()
+            .chain(self.max_connections.desc())
+            .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let (size, idle, max_connections) = match self.db_connection.get_database_backend() {
+            DatabaseBackend::Sqlite => {
+                let pool = self.db_connection.get_sqlite_connection_pool();
+                (
+                    pool.size(),
+                    pool.num_idle(),
+                    pool.options().get_max_connections(),
+                )
+            }
+            _ => {
+                let pool = self.db_connection.get_postgres_connection_pool();
+                (
+                    pool.size(),
+                    pool.num_idle(),
+                    pool.options().get_max_connections(),
+                )
+            }
+        };
+        let idle = idle as i64;
+
+        self.connections.with_label_values(&["idle"]).set(idle);
+        self.connections
+            .with_label_values(&["in_use"])
+            .set(i64::from(size) - idle);
+        self.max_connections.set(i64::from(max_connections));
+
+        self.connections
+            .collect()
+            .into_iter()
+            .chain(self.max_connections.collect())
+            .collect()
+    }
+}
@@ -0,0 +1,103 @@
+//! Applying the schema migrations of the `migration` crate when the application starts.
+
+use std::collections::HashSet;
+
+use migration::{Migrator, MigratorTrait};
+use sea_orm::{ConnectionTrait, DatabaseBackend, DatabaseConnection, DbErr, TransactionTrait};
+
+use crate::routes::error_chain_fmt;
+
+/// Key of the Postgres advisory lock held while migrating, arbitrary but shared by every
+/// replica of the application.
+const MIGRATION_LOCK_KEY: i64 = 0x7a65_726f_3270_726f;
+
+#[derive(thiserror::Error)]
+pub enum MigrationError {
+    #[error(
+        "The database schema is ahead of this build, which does not know migrations {0:?}. \
+        Deploy a newer build or roll the schema back"
+    )]
+    SchemaAhead(Vec<String>),
+    #[error("Failed to migrate the database")]
+    DatabaseError(#[from] DbErr),
+}
+
+impl std::fmt::Debug for MigrationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Applies pending migrations, unless the schema has migrations this build does not know of.
+///
+/// Replicas starting together serialize on an advisory lock, or the write lock of the database
+/// on SQLite, which is released with the transaction: the first one migrates, the others then
+/// find nothing pending.
+#[tracing::instrument(name = "Run database migrations", skip(db_connection))]
+pub async fn run_migrations(db_connection: &DatabaseConnection) -> Result<(), MigrationError> {
+    let backend = db_connection.get_database_backend();
+    if backend == DatabaseBackend::Sqlite {
+        // Taking the write lock below needs a table to write to
+        Migrator::install(db_connection).await?;
+    }
+
+    let transaction = db_connection.begin().await?;
+    match backend {
+        DatabaseBackend::Postgres => {
+            transaction
+                .execute_unprepared(&format!(
+                    "SELECT pg_advisory_xact_lock({})",
+                    MIGRATION_LOCK_KEY
+                ))
+                .await?;
+            // Backfills may legitimately run longer than the statement timeout meant for requests
+            transaction
+                .execute_unprepared("SET LOCAL statement_timeout = 0")
+                .await?;
+        }
+        DatabaseBackend::Sqlite => {
+            // Replicas would otherwise all read the migrations table first, then deadlock
+            // upgrading to the write lock
+            transaction
+                .execute_unprepared("DELETE FROM seaql_migrations WHERE FALSE")
+                .await?;
+        }
+        DatabaseBackend::MySql => {}
+    }
+
+    let unknown = unknown_migrations(&transaction).await?;
+    if !unknown.is_empty() {
+        return Err(MigrationError::SchemaAhead(unknown));
+    }
+
+    let pending = Migrator::get_pending_migrations(&transaction).await?;
+    if !pending.is_empty() {
+        tracing::info!(
+            pending = ?pending.iter().map(|migration| migration.name()).collect::<Vec<_>>(),
+            "Applying migrations"
+        );
+    }
+    Migrator::up(&transaction, None).await?;
+    transaction.commit().await?;
+
+    Ok(())
+}
+
+/// Migrations applied to the database that are missing from this build, sorted.
+async fn unknown_migrations(db: &impl ConnectionTrait) -> Result<Vec<String>, DbErr> {
+    Migrator::install(db).await?;
+    let known: HashSet<String> = Migrator::migrations()
+        .iter()
+        .map(|migration| migration.name().to_string())
+        .collect();
+
+    let mut unknown: Vec<String> = Migrator::get_migration_models(db)
+        .await?
+        .into_iter()
+        .map(|model| model.version)
+        .filter(|version| !known.contains(version))
+        .collect();
+    unknown.sort();
+
+    Ok(unknown)
+}
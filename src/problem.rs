@@ -0,0 +1,284 @@
+//! RFC 7807 problem details, the body of every error response of the API.
+
+use std::error::Error;
+
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use sea_orm::{ConnAcquireErr, DbErr};
+use serde::Serialize;
+use tower_request_id::RequestId;
+use utoipa::ToSchema;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+pub const VALIDATION_ERROR: &str = "/problems/validation-error";
+pub const DATABASE_UNAVAILABLE: &str = "/problems/database-unavailable";
+
+#[derive(Clone, Debug, Serialize, ToSchema)]
+pub struct Problem {
+    /// `about:blank` unless the problem has a type of its own, e.g. `/problems/validation-error`
+    #[serde(rename = "type")]
+    problem_type: &'static str,
+    title: String,
+    #[serde(serialize_with = "serialize_status")]
+    #[schema(value_type = u16)]
+    status: StatusCode,
+    /// Only set for client errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    /// The id of the request, filled in by [`problem_instance`] as handlers do not know it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance: Option<String>,
+    /// Every invalid field, for validation errors
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    errors: Vec<FieldError>,
+    #[serde(skip)]
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+/// Why the value of a single request field was rejected.
+#[derive(Clone, Debug, Serialize, PartialEq, Eq, ToSchema)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub detail: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            field,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl Problem {
+    /// A problem without further semantics than its status code, as per RFC 7807 `about:blank`.
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            problem_type: "about:blank",
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status,
+            detail: None,
+            instance: None,
+            errors: vec![],
+            headers: vec![],
+        }
+    }
+
+    /// A request with invalid fields, each of which is listed in `errors`.
+    pub fn invalid_fields(errors: Vec<FieldError>) -> Self {
+        let detail = format!(
+            "Invalid {}",
+            errors
+                .iter()
+                .map(|error| error.field)
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        Self::new(StatusCode::BAD_REQUEST)
+            .with_type(VALIDATION_ERROR, "Your request parameters didn't validate")
+            .with_detail(detail)
+            .with_field_errors(errors)
+    }
+
+    /// Client errors explain themselves with the error message, server errors only say
+    /// that something went wrong: their cause chain is for the logs.
+    ///
+    /// Server errors caused by an exhausted connection pool become 503, as the request
+    /// may succeed once the load decreases.
+    pub fn from_error(status: StatusCode, error: &(impl Error + 'static)) -> Self {
+        if status.is_server_error() && is_pool_timeout(error) {
+            return Self::new(StatusCode::SERVICE_UNAVAILABLE)
+                .with_type(
+                    DATABASE_UNAVAILABLE,
+                    "No database connection was available in time",
+                )
+                .with_header(header::RETRY_AFTER, HeaderValue::from_static("1"));
+        }
+
+        let problem = Self::new(status);
+        if status.is_client_error() {
+            problem.with_detail(error.to_string())
+        } else {
+            problem
+        }
+    }
+
+    /// Identifies a kind of problem clients can branch on, e.g. `/problems/validation-error`.
+    pub fn with_type(mut self, problem_type: &'static str, title: &str) -> Self {
+        self.problem_type = problem_type;
+        self.title = title.to_string();
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_field_errors(mut self, errors: Vec<FieldError>) -> Self {
+        self.errors = errors;
+        self
+    }
+
+    pub fn with_header(mut self, name: HeaderName, value: HeaderValue) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    fn to_body(&self) -> Body {
+        Body::from(serde_json::to_vec(self).expect("A problem always serializes to JSON"))
+    }
+}
+
+impl IntoResponse for Problem {
+    fn into_response(self) -> Response {
+        let mut response = (
+            self.status,
+            [(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON))],
+            self.to_body(),
+        )
+            .into_response();
+        for (name, value) in &self.headers {
+            response.headers_mut().insert(name, value.clone());
+        }
+        // Kept around for `problem_instance`, which knows the request id
+        response.extensions_mut().insert(self);
+        response
+    }
+}
+
+/// Middleware setting the `instance` of problem responses to the id of the request.
+pub async fn problem_instance(request: Request, next: Next) -> Response {
+    let request_id = request.extensions().get::<RequestId>().cloned();
+    let mut response = next.run(request).await;
+
+    if let (Some(mut problem), Some(request_id)) =
+        (response.extensions_mut().remove::<Problem>(), request_id)
+    {
+        problem.instance = Some(request_id.to_string());
+        response.headers_mut().remove(header::CONTENT_LENGTH);
+        *response.body_mut() = problem.to_body();
+    }
+
+    response
+}
+
+/// Collects the failures of several field validations, so they are reported together.
+#[derive(Default)]
+pub struct FieldErrors(Vec<FieldError>);
+
+impl FieldErrors {
+    /// Returns the parsed value, or records why the field is invalid.
+    pub fn check<T>(&mut self, field: &'static str, result: Result<T, String>) -> Option<T> {
+        result
+            .map_err(|detail| self.0.push(FieldError::new(field, detail)))
+            .ok()
+    }
+
+    pub fn into_vec(self) -> Vec<FieldError> {
+        self.0
+    }
+}
+
+fn is_pool_timeout(error: &(dyn Error + 'static)) -> bool {
+    std::iter::successors(Some(error), |&error| error.source()).any(|error| {
+        matches!(
+            error.downcast_ref::<DbErr>(),
+            Some(DbErr::ConnectionAcquire(ConnAcquireErr::Timeout))
+        )
+    })
+}
+
+fn serialize_status<S: serde::Serializer>(
+    status: &StatusCode,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u16(status.as_u16())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{StatusCode, header};
+    use sea_orm::{ConnAcquireErr, DbErr};
+    use serde_json::json;
+
+    use crate::problem::{DATABASE_UNAVAILABLE, FieldError, FieldErrors, Problem};
+
+    #[test]
+    fn problem_serializes_to_rfc_7807_members() {
+        let problem = Problem::new(StatusCode::BAD_REQUEST)
+            .with_type("/problems/validation-error", "Invalid request")
+            .with_detail("The email is invalid")
+            .with_field_errors(vec![FieldError::new("email", "not an email")]);
+
+        assert_eq!(
+            serde_json::to_value(&problem).unwrap(),
+            json!({
+                "type": "/problems/validation-error",
+                "title": "Invalid request",
+                "status": 400,
+                "detail": "The email is invalid",
+                "errors": [{ "field": "email", "detail": "not an email" }],
+            })
+        );
+    }
+
+    #[test]
+    fn server_errors_do_not_leak_their_message() {
+        let error = std::io::Error::other("connection refused by 10.0.0.3");
+
+        let problem = Problem::from_error(StatusCode::INTERNAL_SERVER_ERROR, &error);
+
+        assert_eq!(
+            serde_json::to_value(&problem).unwrap(),
+            json!({
+                "type": "about:blank",
+                "title": "Internal Server Error",
+                "status": 500,
+            })
+        );
+    }
+
+    #[test]
+    fn pool_timeouts_are_reported_as_unavailable() {
+        #[derive(thiserror::Error, Debug)]
+        enum HandlerError {
+            #[error(transparent)]
+            UnexpectedError(#[from] anyhow::Error),
+        }
+        let error = HandlerError::from(
+            anyhow::Error::new(DbErr::ConnectionAcquire(ConnAcquireErr::Timeout))
+                .context("Failed to insert new subscriber"),
+        );
+
+        let problem = Problem::from_error(StatusCode::INTERNAL_SERVER_ERROR, &error);
+
+        assert_eq!(problem.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(problem.problem_type, DATABASE_UNAVAILABLE);
+        assert_eq!(problem.headers[0].0, header::RETRY_AFTER);
+    }
+
+    #[test]
+    fn field_errors_are_collected_in_order() {
+        let mut errors = FieldErrors::default();
+
+        let email = errors.check("email", Err::<(), _>("bad email".to_string()));
+        let name = errors.check("name", Ok::<_, String>("le guin"));
+        let tags = errors.check("tags", Err::<(), _>("bad tag".to_string()));
+
+        assert_eq!(email, None);
+        assert_eq!(name, Some("le guin"));
+        assert_eq!(tags, None);
+        assert_eq!(
+            errors.into_vec(),
+            vec![
+                FieldError::new("email", "bad email"),
+                FieldError::new("tags", "bad tag"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,287 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::http::HeaderMap;
+use uuid::Uuid;
+
+use crate::configuration::{RateLimitSettings, TokenBucketSettings};
+use crate::domain::SubscriberEmail;
+
+/// Once this many keys are tracked, the bucket used least recently makes room for a new one.
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+/// Throttles signups, so `POST /subscriptions` cannot be used to spam arbitrary addresses.
+///
+/// Addresses are throttled within each tenant, signing up to several publications is fine.
+/// Clients are throttled across tenants, since they all send through this deployment.
+pub struct SubscriptionRateLimiter {
+    trusted_proxies: Vec<IpAddr>,
+    per_ip: RateLimiter<IpAddr>,
+    per_email: RateLimiter<(Uuid, String)>,
+}
+
+impl SubscriptionRateLimiter {
+    pub fn new(settings: &RateLimitSettings) -> Self {
+        Self {
+            trusted_proxies: settings.trusted_proxies.clone(),
+            per_ip: RateLimiter::new(settings.per_ip),
+            per_email: RateLimiter::new(settings.per_email),
+        }
+    }
+
+    /// Address of the client, as reported by our trusted proxies.
+    pub fn client_ip(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        client_ip(peer, headers, &self.trusted_proxies)
+    }
+
+    /// Takes a token from the bucket of the client, returning how long to wait if it is empty.
+    pub fn check_client(&self, client_ip: IpAddr) -> Result<(), Duration> {
+        self.per_ip.check(client_ip)
+    }
+
+    /// Takes a token from the bucket of the address within the tenant, returning how long to
+    /// wait if it is empty.
+    pub fn check_email(&self, tenant_id: Uuid, email: &SubscriberEmail) -> Result<(), Duration> {
+        self.per_email
+            .check((tenant_id, email.normalized().to_string()))
+    }
+}
+
+struct RateLimiter<K> {
+    capacity: f64,
+    tokens_per_second: f64,
+    max_keys: usize,
+    buckets: Mutex<Buckets<K>>,
+}
+
+/// Buckets by key, along with the order they were last used in.
+struct Buckets<K> {
+    by_key: HashMap<K, Bucket>,
+    /// Keyed by the last use of each bucket, a sequence number telling apart simultaneous uses
+    by_last_use: BTreeMap<(Instant, u64), K>,
+    next_sequence: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+    sequence: u64,
+}
+
+impl<K: Eq + Hash + Clone> RateLimiter<K> {
+    fn new(settings: TokenBucketSettings) -> Self {
+        Self {
+            capacity: settings.capacity as f64,
+            tokens_per_second: settings.refill_per_minute as f64 / 60.0,
+            max_keys: MAX_TRACKED_KEYS,
+            buckets: Mutex::new(Buckets {
+                by_key: HashMap::new(),
+                by_last_use: BTreeMap::new(),
+                next_sequence: 0,
+            }),
+        }
+    }
+
+    fn check(&self, key: K) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: K, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets {
+            by_key,
+            by_last_use,
+            next_sequence,
+        } = &mut *buckets;
+
+        let tokens = match by_key.remove(&key) {
+            Some(bucket) => {
+                by_last_use.remove(&(bucket.updated_at, bucket.sequence));
+                self.refilled(&bucket, now)
+            }
+            None => {
+                if by_key.len() >= self.max_keys
+                    && let Some((_, evicted)) = by_last_use.pop_first()
+                {
+                    by_key.remove(&evicted);
+                }
+                self.capacity
+            }
+        };
+
+        let (tokens, result) = if tokens >= 1.0 {
+            (tokens - 1.0, Ok(()))
+        } else {
+            let retry_after = Duration::from_secs_f64((1.0 - tokens) / self.tokens_per_second);
+            (tokens, Err(retry_after))
+        };
+
+        let sequence = *next_sequence;
+        *next_sequence += 1;
+        by_last_use.insert((now, sequence), key.clone());
+        by_key.insert(
+            key,
+            Bucket {
+                tokens,
+                updated_at: now,
+                sequence,
+            },
+        );
+
+        result
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.updated_at);
+        (bucket.tokens + elapsed.as_secs_f64() * self.tokens_per_second).min(self.capacity)
+    }
+}
+
+/// Finds the address of the client, looking through `X-Forwarded-For` only when the request
+/// came from a trusted proxy.
+///
+/// The header is read from right to left, since only the entries appended by our own proxies
+/// can be trusted. The first address that is not one of them is the client.
+pub fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    let forwarded: Vec<&str> = headers
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+
+    for address in forwarded.into_iter().rev() {
+        match address.trim().parse() {
+            Ok(address) if trusted_proxies.contains(&address) => continue,
+            Ok(address) => return address,
+            Err(_) => break,
+        }
+    }
+
+    peer
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+    use std::time::{Duration, Instant};
+
+    use axum::http::HeaderMap;
+    use claims::{assert_err, assert_ok};
+
+    use crate::configuration::TokenBucketSettings;
+    use crate::rate_limit::{RateLimiter, client_ip};
+
+    fn limiter(capacity: u32, refill_per_minute: u32) -> RateLimiter<&'static str> {
+        RateLimiter::new(TokenBucketSettings {
+            capacity,
+            refill_per_minute,
+        })
+    }
+
+    fn headers(forwarded_for: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Forwarded-For", forwarded_for.parse().unwrap());
+        headers
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn a_burst_up_to_the_capacity_is_allowed() {
+        let limiter = limiter(3, 1);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert_ok!(limiter.check_at("client", now));
+        }
+        assert_err!(limiter.check_at("client", now));
+    }
+
+    #[test]
+    fn an_empty_bucket_reports_when_the_next_token_arrives() {
+        let limiter = limiter(1, 2);
+        let now = Instant::now();
+        limiter.check_at("client", now).unwrap();
+
+        let retry_after = limiter.check_at("client", now).unwrap_err();
+
+        assert_eq!(retry_after, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn tokens_are_refilled_over_time() {
+        let limiter = limiter(1, 60);
+        let now = Instant::now();
+        limiter.check_at("client", now).unwrap();
+
+        assert_ok!(limiter.check_at("client", now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn keys_have_separate_buckets() {
+        let limiter = limiter(1, 1);
+        let now = Instant::now();
+        limiter.check_at("client", now).unwrap();
+
+        assert_ok!(limiter.check_at("another client", now));
+    }
+
+    #[test]
+    fn the_least_recently_used_bucket_is_evicted_beyond_the_limit() {
+        let mut limiter = limiter(1, 1);
+        limiter.max_keys = 2;
+        let now = Instant::now();
+        limiter.check_at("first", now).unwrap();
+        limiter.check_at("second", now).unwrap();
+        limiter
+            .check_at("first", now + Duration::from_secs(1))
+            .unwrap_err();
+
+        limiter
+            .check_at("third", now + Duration::from_secs(2))
+            .unwrap();
+
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(buckets.by_key.len(), 2);
+        assert_eq!(buckets.by_last_use.len(), 2);
+        assert!(!buckets.by_key.contains_key("second"));
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_from_untrusted_peers() {
+        let client = client_ip(ip("203.0.113.7"), &headers("198.51.100.1"), &[]);
+
+        assert_eq!(client, ip("203.0.113.7"));
+    }
+
+    #[test]
+    fn forwarded_for_is_used_from_trusted_proxies() {
+        let proxies = [ip("10.0.0.1"), ip("10.0.0.2")];
+
+        let client = client_ip(
+            ip("10.0.0.1"),
+            &headers("192.0.2.66, 198.51.100.1, 10.0.0.2"),
+            &proxies,
+        );
+
+        // The left-most entry was supplied by the client and could be forged
+        assert_eq!(client, ip("198.51.100.1"));
+    }
+
+    #[test]
+    fn a_trusted_proxy_without_forwarded_for_is_the_client() {
+        let client = client_ip(ip("10.0.0.1"), &HeaderMap::new(), &[ip("10.0.0.1")]);
+
+        assert_eq!(client, ip("10.0.0.1"));
+    }
+}
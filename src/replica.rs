@@ -0,0 +1,60 @@
+//! Read-only queries served by a replica of the primary database.
+
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use sea_orm::{DatabaseConnection, DbErr};
+
+/// How long queries go straight to the primary after the replica failed to provide a
+/// connection, so that an outage does not slow every read down by the acquire timeout.
+const UNAVAILABLE_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+pub struct ReadReplica {
+    connection: DatabaseConnection,
+    unavailable_until: Mutex<Option<Instant>>,
+}
+
+impl ReadReplica {
+    pub fn new(connection: DatabaseConnection) -> Self {
+        Self {
+            connection,
+            unavailable_until: Mutex::new(None),
+        }
+    }
+
+    /// Runs `query` on the replica, or on `primary` when the replica is unavailable.
+    /// Queries may see data slightly behind the primary, so they must not follow a write
+    /// whose result they are expected to read.
+    pub async fn read<T, F, Fut>(&self, primary: &DatabaseConnection, query: F) -> Result<T, DbErr>
+    where
+        F: Fn(DatabaseConnection) -> Fut,
+        Fut: Future<Output = Result<T, DbErr>>,
+    {
+        if self.is_unavailable() {
+            return query(primary.clone()).await;
+        }
+
+        match query(self.connection.clone()).await {
+            Err(DbErr::ConnectionAcquire(_) | DbErr::Conn(_)) => {
+                tracing::warn!("The database replica is unavailable, reading from the primary");
+                *self.unavailable_until.lock().unwrap() =
+                    Some(Instant::now() + UNAVAILABLE_RETRY_INTERVAL);
+                query(primary.clone()).await
+            }
+            result => result,
+        }
+    }
+
+    fn is_unavailable(&self) -> bool {
+        let mut unavailable_until = self.unavailable_until.lock().unwrap();
+        match *unavailable_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                *unavailable_until = None;
+                false
+            }
+            None => false,
+        }
+    }
+}
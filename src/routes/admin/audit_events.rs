@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::extract::{Extension, Json, State};
+use axum::response::{IntoResponse, Response};
+use entity::audit_events;
+use entity::prelude::AuditEvents;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::{
+    ColumnTrait, Condition, EntityTrait, QueryFilter, QueryOrder, QuerySelect, QueryTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tower_request_id::RequestId;
+use uuid::Uuid;
+
+use super::subscribers::{decode_cursor, encode_cursor};
+use crate::audit::{AuditEvent, record_audit_event};
+use crate::authentication::AuthenticatedUser;
+use crate::routes::{AdminError, AdminQuery};
+use crate::startup::ApplicationState;
+
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 200;
+
+#[derive(Deserialize, Debug)]
+pub struct ListAuditEventsParameters {
+    /// Username of the administrator who performed the action
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target: Option<String>,
+    /// Opaque `next_cursor` returned by the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct AuditEventPage {
+    pub events: Vec<AuditEventSummary>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AuditEventSummary {
+    pub id: Uuid,
+    pub occurred_at: DateTimeWithTimeZone,
+    pub actor_id: Uuid,
+    pub actor: String,
+    pub action: String,
+    pub target: Option<String>,
+    pub request_id: String,
+    pub diff: Option<Value>,
+}
+
+impl From<audit_events::Model> for AuditEventSummary {
+    fn from(value: audit_events::Model) -> Self {
+        Self {
+            id: value.id,
+            occurred_at: value.occurred_at,
+            actor_id: value.actor_id,
+            actor: value.actor,
+            action: value.action,
+            target: value.target,
+            request_id: value.request_id,
+            diff: value.diff,
+        }
+    }
+}
+
+#[tracing::instrument(
+    name = "List audit events",
+    skip(state, user),
+    fields(username = %user.username)
+)]
+pub async fn list_audit_events(
+    user: AuthenticatedUser,
+    Extension(request_id): Extension<RequestId>,
+    State(state): State<Arc<ApplicationState>>,
+    AdminQuery(parameters): AdminQuery<ListAuditEventsParameters>,
+) -> Result<Response, AdminError> {
+    let cursor = parameters
+        .cursor
+        .as_deref()
+        .map(decode_cursor)
+        .transpose()
+        .map_err(AdminError::ValidationError)?;
+    let limit = parameters
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+
+    // Newest events come first
+    let query = AuditEvents::find()
+        .filter(audit_events::Column::TenantId.eq(user.tenant_id))
+        .apply_if(parameters.actor, |query, actor| {
+            query.filter(audit_events::Column::Actor.eq(actor))
+        })
+        .apply_if(parameters.action, |query, action| {
+            query.filter(audit_events::Column::Action.eq(action))
+        })
+        .apply_if(parameters.target, |query, target| {
+            query.filter(audit_events::Column::Target.eq(target))
+        })
+        .apply_if(cursor, |query, (occurred_at, id)| {
+            query.filter(
+                Condition::any()
+                    .add(audit_events::Column::OccurredAt.lt(occurred_at))
+                    .add(
+                        Condition::all()
+                            .add(audit_events::Column::OccurredAt.eq(occurred_at))
+                            .add(audit_events::Column::Id.lt(id)),
+                    ),
+            )
+        })
+        .order_by_desc(audit_events::Column::OccurredAt)
+        .order_by_desc(audit_events::Column::Id)
+        // Fetch one extra row to find out whether there is a next page
+        .limit(limit + 1);
+    let mut events = state
+        .read_only(|db_connection| {
+            let query = query.clone();
+            async move { query.all(&db_connection).await }
+        })
+        .await
+        .context("Failed to fetch audit events from the database")?;
+
+    let next_cursor = if events.len() as u64 > limit {
+        events.truncate(limit as usize);
+        events
+            .last()
+            .map(|last| encode_cursor(&last.occurred_at, last.id))
+    } else {
+        None
+    };
+
+    record_audit_event(
+        &state.db_connection,
+        &user,
+        &request_id,
+        AuditEvent {
+            action: "audit_events.list",
+            target: None,
+            diff: None,
+        },
+    )
+    .await
+    .context("Failed to record the audit event")?;
+
+    Ok(Json(AuditEventPage {
+        events: events.into_iter().map(Into::into).collect(),
+        next_cursor,
+    })
+    .into_response())
+}
@@ -0,0 +1,7 @@
+mod audit_events;
+mod subscribers;
+mod subscribers_csv;
+
+pub use audit_events::*;
+pub use subscribers::*;
+pub use subscribers_csv::*;
@@ -0,0 +1,589 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::{Extension, FromRequest, FromRequestParts, Json, Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::DateTime;
+use entity::prelude::{SubscriberTags, SubscriptionLists, Subscriptions};
+use entity::{lists, subscriber_tags, subscription_lists, subscriptions};
+use sea_orm::ActiveValue::Set;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::sea_query::{Expr, Func, LikeExpr};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseTransaction, DbErr,
+    EntityTrait, IntoActiveModel, JoinType, Order, QueryFilter, QueryOrder, QuerySelect,
+    QueryTrait, RelationTrait, SqlErr, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tower_request_id::RequestId;
+use uuid::Uuid;
+
+use crate::audit::{AuditEvent, changed_fields, record_audit_event};
+use crate::authentication::AuthenticatedUser;
+use crate::domain::{SubscriberEmail, SubscriberName, SubscriberTag};
+use crate::problem::{FieldError, FieldErrors, Problem};
+use crate::routes::{add_subscriber_tags, error_chain_fmt};
+use crate::startup::ApplicationState;
+
+const SUBSCRIPTION_STATUSES: [&str; 3] = ["pending_confirmation", "confirmed", "unsubscribed"];
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const MAX_PAGE_SIZE: u64 = 200;
+
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    #[error(transparent)]
+    JsonRejection(#[from] JsonRejection),
+    #[error(transparent)]
+    QueryRejection(#[from] QueryRejection),
+    #[error(transparent)]
+    PathRejection(#[from] PathRejection),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Invalid fields: {0:?}")]
+    InvalidFields(Vec<FieldError>),
+    #[error("{0}")]
+    NotFoundError(String),
+    #[error("{0}")]
+    ConflictError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        tracing::error!("{:?}", self);
+
+        let status = match self {
+            AdminError::InvalidFields(errors) => {
+                return Problem::invalid_fields(errors).into_response();
+            }
+            AdminError::JsonRejection(_)
+            | AdminError::QueryRejection(_)
+            | AdminError::PathRejection(_)
+            | AdminError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            AdminError::NotFoundError(_) => StatusCode::NOT_FOUND,
+            AdminError::ConflictError(_) => StatusCode::CONFLICT,
+            AdminError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Problem::from_error(status, &self).into_response()
+    }
+}
+
+#[derive(FromRequest)]
+#[from_request(via(Json), rejection(AdminError))]
+pub struct AdminJson<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(Query), rejection(AdminError))]
+pub struct AdminQuery<T>(pub T);
+
+#[derive(FromRequestParts)]
+#[from_request(via(Path), rejection(AdminError))]
+pub struct AdminPath<T>(pub T);
+
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ListSubscribersParameters {
+    pub status: Option<String>,
+    /// Case-insensitive substring of the subscriber email
+    pub email: Option<String>,
+    /// Sort direction on `subscribed_at`
+    #[serde(default)]
+    pub order: SortOrder,
+    /// Opaque `next_cursor` returned by the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberPage {
+    pub subscribers: Vec<SubscriberSummary>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriberSummary {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub subscribed_at: DateTimeWithTimeZone,
+}
+
+impl From<subscriptions::Model> for SubscriberSummary {
+    fn from(value: subscriptions::Model) -> Self {
+        Self {
+            id: value.id,
+            email: value.email,
+            name: value.name,
+            status: value.status,
+            subscribed_at: value.subscribed_at,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SubscriberDetails {
+    #[serde(flatten)]
+    pub summary: SubscriberSummary,
+    pub lists: Vec<ListMembership>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct ListMembership {
+    pub name: String,
+    pub status: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SubscriberPatch {
+    pub email: Option<String>,
+    pub name: Option<String>,
+    /// Also applies to the list memberships, every one of them when unsubscribing
+    pub status: Option<String>,
+    /// Replaces every tag of the subscriber
+    pub tags: Option<Vec<String>>,
+}
+
+#[tracing::instrument(
+    name = "List subscribers",
+    skip(state, user),
+    fields(username = %user.username)
+)]
+pub async fn list_subscribers(
+    user: AuthenticatedUser,
+    Extension(request_id): Extension<RequestId>,
+    State(state): State<Arc<ApplicationState>>,
+    AdminQuery(parameters): AdminQuery<ListSubscribersParameters>,
+) -> Result<Response, AdminError> {
+    if let Some(status) = &parameters.status {
+        validate_status(status).map_err(AdminError::ValidationError)?;
+    }
+
+    let cursor = parameters
+        .cursor
+        .as_deref()
+        .map(decode_cursor)
+        .transpose()
+        .map_err(AdminError::ValidationError)?;
+    let limit = parameters
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let order = match parameters.order {
+        SortOrder::Asc => Order::Asc,
+        SortOrder::Desc => Order::Desc,
+    };
+
+    let query = Subscriptions::find()
+        .filter(subscriptions::Column::TenantId.eq(user.tenant_id))
+        .apply_if(parameters.status, |query, status| {
+            query.filter(subscriptions::Column::Status.eq(status))
+        })
+        .apply_if(parameters.email, |query, email| {
+            query.filter(
+                Expr::expr(Func::lower(Expr::col(subscriptions::Column::Email))).like(
+                    LikeExpr::new(format!("%{}%", escape_like(&email.to_lowercase()))).escape('\\'),
+                ),
+            )
+        })
+        .apply_if(cursor, |query, (subscribed_at, id)| {
+            let after = match parameters.order {
+                SortOrder::Asc => Condition::any()
+                    .add(subscriptions::Column::SubscribedAt.gt(subscribed_at))
+                    .add(
+                        Condition::all()
+                            .add(subscriptions::Column::SubscribedAt.eq(subscribed_at))
+                            .add(subscriptions::Column::Id.gt(id)),
+                    ),
+                SortOrder::Desc => Condition::any()
+                    .add(subscriptions::Column::SubscribedAt.lt(subscribed_at))
+                    .add(
+                        Condition::all()
+                            .add(subscriptions::Column::SubscribedAt.eq(subscribed_at))
+                            .add(subscriptions::Column::Id.lt(id)),
+                    ),
+            };
+
+            query.filter(after)
+        })
+        .order_by(subscriptions::Column::SubscribedAt, order.clone())
+        .order_by(subscriptions::Column::Id, order)
+        // Fetch one extra row to find out whether there is a next page
+        .limit(limit + 1);
+    let mut subscribers = state
+        .read_only(|db_connection| {
+            let query = query.clone();
+            async move { query.all(&db_connection).await }
+        })
+        .await
+        .context("Failed to fetch subscribers from the database")?;
+
+    let next_cursor = if subscribers.len() as u64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers
+            .last()
+            .map(|last| encode_cursor(&last.subscribed_at, last.id))
+    } else {
+        None
+    };
+
+    record_audit_event(
+        &state.db_connection,
+        &user,
+        &request_id,
+        AuditEvent {
+            action: "subscribers.list",
+            target: None,
+            diff: None,
+        },
+    )
+    .await
+    .context("Failed to record the audit event")?;
+
+    Ok(Json(SubscriberPage {
+        subscribers: subscribers.into_iter().map(Into::into).collect(),
+        next_cursor,
+    })
+    .into_response())
+}
+
+#[tracing::instrument(
+    name = "Get subscriber details",
+    skip(state, user),
+    fields(username = %user.username)
+)]
+pub async fn get_subscriber(
+    user: AuthenticatedUser,
+    Extension(request_id): Extension<RequestId>,
+    State(state): State<Arc<ApplicationState>>,
+    AdminPath(subscriber_id): AdminPath<Uuid>,
+) -> Result<Response, AdminError> {
+    let subscription = find_subscriber(&state.db_connection, user.tenant_id, subscriber_id).await?;
+    let details = get_subscriber_details(&state.db_connection, subscription)
+        .await
+        .context("Failed to fetch subscriber details from the database")?;
+
+    record_audit_event(
+        &state.db_connection,
+        &user,
+        &request_id,
+        AuditEvent {
+            action: "subscriber.view",
+            target: Some(subscriber_id.to_string()),
+            diff: None,
+        },
+    )
+    .await
+    .context("Failed to record the audit event")?;
+
+    Ok(Json(details).into_response())
+}
+
+#[tracing::instrument(
+    name = "Update subscriber",
+    skip(state, user),
+    fields(username = %user.username)
+)]
+pub async fn update_subscriber(
+    user: AuthenticatedUser,
+    Extension(request_id): Extension<RequestId>,
+    State(state): State<Arc<ApplicationState>>,
+    AdminPath(subscriber_id): AdminPath<Uuid>,
+    AdminJson(patch): AdminJson<SubscriberPatch>,
+) -> Result<Response, AdminError> {
+    let mut errors = FieldErrors::default();
+    let email = errors.check("email", patch.email.map(SubscriberEmail::parse).transpose());
+    let name = errors.check("name", patch.name.map(SubscriberName::parse).transpose());
+    let status = errors.check(
+        "status",
+        patch.status.as_deref().map(validate_status).transpose(),
+    );
+    let tags = errors.check(
+        "tags",
+        patch
+            .tags
+            .map(|tags| {
+                tags.into_iter()
+                    .map(SubscriberTag::parse)
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose(),
+    );
+    let (Some(email), Some(name), Some(_), Some(tags)) = (email, name, status, tags) else {
+        return Err(AdminError::InvalidFields(errors.into_vec()));
+    };
+
+    let transaction = state
+        .db_connection
+        .begin()
+        .await
+        .context("Failed to begin a Postgres transaction")?;
+
+    let subscription = find_subscriber(&transaction, user.tenant_id, subscriber_id).await?;
+    let before = get_subscriber_details(&transaction, subscription.clone())
+        .await
+        .context("Failed to fetch subscriber details from the database")?;
+    let mut subscription = subscription.into_active_model();
+
+    if let Some(email) = email {
+        subscription.email = Set(email.as_ref().to_string());
+        subscription.ascii_email = Set(email.ascii_compatible().to_string());
+        subscription.normalized_email = Set(email.normalized().to_string());
+    }
+    if let Some(name) = name {
+        subscription.name = Set(name.as_ref().to_string());
+    }
+    let subscription_status = patch.status;
+    if let Some(status) = &subscription_status {
+        subscription.status = Set(status.clone());
+    }
+
+    let subscription = subscription.update(&transaction).await.map_err(|e| {
+        if let Some(SqlErr::UniqueConstraintViolation(_)) = e.sql_err() {
+            AdminError::ConflictError("Another subscriber already uses this email".into())
+        } else {
+            AdminError::UnexpectedError(
+                anyhow::Error::new(e).context("Failed to update the subscriber in the database"),
+            )
+        }
+    })?;
+
+    // Mail goes out per list membership, they must follow the status of the subscriber
+    if let Some(status) = &subscription_status {
+        let updated = set_membership_statuses(&transaction, subscriber_id, status)
+            .await
+            .context("Failed to update the list memberships")?;
+        if updated == 0 && status != "unsubscribed" {
+            return Err(AdminError::InvalidFields(vec![FieldError::new(
+                "status",
+                format!("The subscriber is on no list to be {} on", status),
+            )]));
+        }
+    }
+
+    if let Some(tags) = tags {
+        replace_subscriber_tags(&transaction, subscriber_id, &tags)
+            .await
+            .context("Failed to update the subscriber tags")?;
+    }
+
+    let details = get_subscriber_details(&transaction, subscription)
+        .await
+        .context("Failed to fetch subscriber details from the database")?;
+
+    record_audit_event(
+        &transaction,
+        &user,
+        &request_id,
+        AuditEvent {
+            action: "subscriber.update",
+            target: Some(subscriber_id.to_string()),
+            diff: Some(changed_fields(&to_json(&before)?, &to_json(&details)?)),
+        },
+    )
+    .await
+    .context("Failed to record the audit event")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the Postgres transaction")?;
+
+    Ok(Json(details).into_response())
+}
+
+#[tracing::instrument(
+    name = "Delete subscriber",
+    skip(state, user),
+    fields(username = %user.username)
+)]
+pub async fn delete_subscriber(
+    user: AuthenticatedUser,
+    Extension(request_id): Extension<RequestId>,
+    State(state): State<Arc<ApplicationState>>,
+    AdminPath(subscriber_id): AdminPath<Uuid>,
+) -> Result<Response, AdminError> {
+    let transaction = state
+        .db_connection
+        .begin()
+        .await
+        .context("Failed to begin a Postgres transaction")?;
+
+    let subscription = find_subscriber(&transaction, user.tenant_id, subscriber_id).await?;
+    let before = get_subscriber_details(&transaction, subscription)
+        .await
+        .context("Failed to fetch subscriber details from the database")?;
+
+    Subscriptions::delete_by_id(subscriber_id)
+        .exec(&transaction)
+        .await
+        .context("Failed to delete the subscriber")?;
+
+    record_audit_event(
+        &transaction,
+        &user,
+        &request_id,
+        AuditEvent {
+            action: "subscriber.delete",
+            target: Some(subscriber_id.to_string()),
+            diff: Some(changed_fields(&to_json(&before)?, &Value::Null)),
+        },
+    )
+    .await
+    .context("Failed to record the audit event")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the Postgres transaction")?;
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+pub fn validate_status(status: &str) -> Result<(), String> {
+    if SUBSCRIPTION_STATUSES.contains(&status) {
+        Ok(())
+    } else {
+        Err(format!("{} is not a valid subscription status", status))
+    }
+}
+
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+pub(super) fn to_json(value: &impl Serialize) -> Result<Value, AdminError> {
+    Ok(serde_json::to_value(value).context("Failed to serialize the audit diff")?)
+}
+
+pub(super) fn encode_cursor(subscribed_at: &DateTimeWithTimeZone, id: Uuid) -> String {
+    URL_SAFE_NO_PAD.encode(format!("{}|{}", subscribed_at.to_rfc3339(), id))
+}
+
+pub(super) fn decode_cursor(cursor: &str) -> Result<(DateTimeWithTimeZone, Uuid), String> {
+    let invalid = || format!("{} is not a valid cursor", cursor);
+
+    let decoded = URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(invalid)?;
+    let (subscribed_at, id) = decoded.split_once('|').ok_or_else(invalid)?;
+
+    let subscribed_at = DateTime::parse_from_rfc3339(subscribed_at).map_err(|_| invalid())?;
+    let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+
+    Ok((subscribed_at, id))
+}
+
+/// Subscribers of other tenants are not found either.
+async fn find_subscriber(
+    db_connection: &impl ConnectionTrait,
+    tenant_id: Uuid,
+    subscriber_id: Uuid,
+) -> Result<subscriptions::Model, AdminError> {
+    Subscriptions::find_by_id(subscriber_id)
+        .filter(subscriptions::Column::TenantId.eq(tenant_id))
+        .one(db_connection)
+        .await
+        .context("Failed to fetch the subscriber from the database")?
+        .ok_or_else(|| AdminError::NotFoundError(format!("Subscriber {} not found", subscriber_id)))
+}
+
+#[tracing::instrument(
+    name = "Get subscriber lists and tags",
+    skip(db_connection, subscription)
+)]
+async fn get_subscriber_details(
+    db_connection: &impl ConnectionTrait,
+    subscription: subscriptions::Model,
+) -> Result<SubscriberDetails, DbErr> {
+    let lists = SubscriptionLists::find()
+        .select_only()
+        .column(lists::Column::Name)
+        .column(subscription_lists::Column::Status)
+        .join(
+            JoinType::InnerJoin,
+            subscription_lists::Relation::Lists.def(),
+        )
+        .filter(subscription_lists::Column::SubscriberId.eq(subscription.id))
+        .order_by_asc(lists::Column::Name)
+        .into_tuple::<(String, String)>()
+        .all(db_connection)
+        .await?
+        .into_iter()
+        .map(|(name, status)| ListMembership { name, status })
+        .collect();
+
+    let tags = SubscriberTags::find()
+        .select_only()
+        .column(subscriber_tags::Column::Tag)
+        .filter(subscriber_tags::Column::SubscriberId.eq(subscription.id))
+        .order_by_asc(subscriber_tags::Column::Tag)
+        .into_tuple::<String>()
+        .all(db_connection)
+        .await?;
+
+    Ok(SubscriberDetails {
+        summary: subscription.into(),
+        lists,
+        tags,
+    })
+}
+
+/// Gives the memberships of a subscriber their new status, returning how many changed.
+///
+/// Unsubscribing covers every list, other statuses leave the lists the subscriber left alone.
+#[tracing::instrument(name = "Set list membership statuses", skip(transaction))]
+async fn set_membership_statuses(
+    transaction: &DatabaseTransaction,
+    subscriber_id: Uuid,
+    status: &str,
+) -> Result<u64, DbErr> {
+    let result = SubscriptionLists::update_many()
+        .col_expr(subscription_lists::Column::Status, Expr::value(status))
+        .filter(subscription_lists::Column::SubscriberId.eq(subscriber_id))
+        .apply_if((status != "unsubscribed").then_some(()), |query, _| {
+            query.filter(subscription_lists::Column::Status.ne("unsubscribed"))
+        })
+        .exec(transaction)
+        .await?;
+
+    Ok(result.rows_affected)
+}
+
+#[tracing::instrument(name = "Replace subscriber tags", skip(transaction))]
+async fn replace_subscriber_tags(
+    transaction: &DatabaseTransaction,
+    subscriber_id: Uuid,
+    tags: &[SubscriberTag],
+) -> Result<(), DbErr> {
+    SubscriberTags::delete_many()
+        .filter(subscriber_tags::Column::SubscriberId.eq(subscriber_id))
+        .exec(transaction)
+        .await?;
+
+    add_subscriber_tags(transaction, subscriber_id, tags).await
+}
@@ -0,0 +1,709 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::body::Body;
+use axum::extract::{Extension, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Json, Response};
+use chrono::Utc;
+use csv_async::{AsyncReaderBuilder, AsyncWriterBuilder, StringRecord, Trim};
+use entity::prelude::{PreferenceTokens, SubscriptionLists, SubscriptionTokens, Subscriptions};
+use entity::{preference_tokens, subscription_lists, subscription_tokens, subscriptions};
+use futures_util::{StreamExt, TryStreamExt};
+use migration::DEFAULT_LIST_NAME;
+use sea_orm::ActiveValue::Set;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, DatabaseTransaction, DbErr, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect, QueryTrait, TransactionTrait,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use tokio_util::io::StreamReader;
+use tower_request_id::RequestId;
+use uuid::Uuid;
+
+use crate::audit::{AuditEvent, diff, record_audit_event};
+use crate::authentication::AuthenticatedUser;
+use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+use crate::routes::{
+    AdminError, AdminQuery, generate_subscription_token, get_list_id, send_confirmation_email,
+    validate_status,
+};
+use crate::startup::ApplicationState;
+use crate::tenant::Tenant;
+
+const IMPORT_BATCH_SIZE: usize = 500;
+const EXPORT_BATCH_SIZE: u64 = 500;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Imported subscribers are trusted and confirmed straight away
+    Confirmed,
+    /// Imported subscribers receive a confirmation email, like a regular signup
+    #[default]
+    DoubleOptIn,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ImportParameters {
+    #[serde(default)]
+    pub mode: ImportMode,
+    /// Name of the mailing list to import into, the default list is used if omitted
+    pub list: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: Vec<RowReport>,
+    pub errors: Vec<RowReport>,
+    /// First row of the batch the import stopped at. Earlier batches stay imported, this
+    /// row and every later one were not
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed: Option<RowReport>,
+}
+
+#[derive(Serialize)]
+pub struct RowReport {
+    /// Line of the CSV input the report refers to
+    pub row: u64,
+    pub email: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ExportParameters {
+    pub status: Option<String>,
+}
+
+struct ImportRow {
+    row: u64,
+    new_subscriber: NewSubscriber,
+}
+
+#[tracing::instrument(
+    name = "Import subscribers from CSV",
+    skip(state, user, tenant, body),
+    fields(username = %user.username, tenant = %tenant.slug)
+)]
+pub async fn import_subscribers(
+    user: AuthenticatedUser,
+    tenant: Tenant,
+    Extension(request_id): Extension<RequestId>,
+    State(state): State<Arc<ApplicationState>>,
+    AdminQuery(parameters): AdminQuery<ImportParameters>,
+    body: Body,
+) -> Result<Response, AdminError> {
+    let list_name = parameters
+        .list
+        .unwrap_or_else(|| DEFAULT_LIST_NAME.to_string());
+    let list_id = get_list_id(&state.db_connection, tenant.id, &list_name)
+        .await
+        .context("Failed to fetch the mailing list from the database")?
+        .ok_or_else(|| {
+            AdminError::ValidationError(format!("{} is not a known mailing list", list_name))
+        })?;
+
+    // The body is parsed while it is being received, so large files are never held in memory
+    let body_reader = StreamReader::new(body.into_data_stream().map_err(std::io::Error::other));
+    let mut reader = AsyncReaderBuilder::new()
+        .flexible(true)
+        .trim(Trim::All)
+        .create_reader(body_reader);
+
+    let headers = reader
+        .headers()
+        .await
+        .map_err(|e| AdminError::ValidationError(format!("Invalid CSV header: {}", e)))?
+        .clone();
+    let email_column = find_column(&headers, "email")?;
+    let name_column = find_column(&headers, "name")?;
+
+    let mut report = ImportReport::default();
+    let mut seen_emails = HashSet::new();
+    let mut batch = Vec::with_capacity(IMPORT_BATCH_SIZE);
+    let mut records = reader.records();
+
+    while let Some(record) = records.next().await {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                report.errors.push(RowReport {
+                    row: e.position().map(|p| p.line()).unwrap_or_default(),
+                    email: None,
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let row = record.position().map(|p| p.line()).unwrap_or_default();
+        let email = record.get(email_column).unwrap_or_default().to_string();
+        let name = record.get(name_column).unwrap_or_default().to_string();
+
+        let new_subscriber = match SubscriberEmail::parse(email.clone()).and_then(|email| {
+            Ok(NewSubscriber {
+                email,
+                name: SubscriberName::parse(name)?,
+                tags: vec![],
+            })
+        }) {
+            Ok(new_subscriber) => new_subscriber,
+            Err(reason) => {
+                report.errors.push(RowReport {
+                    row,
+                    email: Some(email),
+                    reason,
+                });
+                continue;
+            }
+        };
+
+        if let Err(e) = tenant.email_client.check_recipient(&new_subscriber.email) {
+            report.errors.push(RowReport {
+                row,
+                email: Some(email),
+                reason: e.to_string(),
+            });
+            continue;
+        }
+
+        if !seen_emails.insert(new_subscriber.email.normalized().to_string()) {
+            report.skipped.push(RowReport {
+                row,
+                email: Some(email),
+                reason: "Duplicate of an earlier row".into(),
+            });
+            continue;
+        }
+
+        batch.push(ImportRow {
+            row,
+            new_subscriber,
+        });
+
+        if batch.len() == IMPORT_BATCH_SIZE {
+            if let Err(failed) = import_batch(
+                &state,
+                &tenant,
+                list_id,
+                parameters.mode,
+                &batch,
+                &mut report,
+            )
+            .await
+            {
+                report.failed = Some(failed_row(&batch, failed));
+                break;
+            }
+            batch.clear();
+        }
+    }
+
+    if report.failed.is_none()
+        && !batch.is_empty()
+        && let Err(failed) = import_batch(
+            &state,
+            &tenant,
+            list_id,
+            parameters.mode,
+            &batch,
+            &mut report,
+        )
+        .await
+    {
+        report.failed = Some(failed_row(&batch, failed));
+    }
+
+    let summary = json!({
+        "mode": parameters.mode,
+        "imported": report.imported,
+        "skipped": report.skipped.len(),
+        "errors": report.errors.len(),
+        "failed": report.failed.is_some(),
+    });
+    record_audit_event(
+        &state.db_connection,
+        &user,
+        &request_id,
+        AuditEvent {
+            action: "subscribers.import",
+            target: Some(list_name),
+            diff: Some(diff(&Value::Null, &summary)),
+        },
+    )
+    .await
+    .context("Failed to record the audit event")?;
+
+    // What was committed before the failure is reported all the same
+    let status = if report.failed.is_some() {
+        StatusCode::INTERNAL_SERVER_ERROR
+    } else {
+        StatusCode::OK
+    };
+    Ok((status, Json(report)).into_response())
+}
+
+/// Reports the batch an import stopped at by its first row, the cause goes to the logs.
+fn failed_row(batch: &[ImportRow], error: anyhow::Error) -> RowReport {
+    tracing::error!("Failed to import a batch of subscribers: {:?}", error);
+
+    let first = &batch[0];
+    RowReport {
+        row: first.row,
+        email: Some(first.new_subscriber.email.as_ref().to_string()),
+        reason: "The import stopped at this row".into(),
+    }
+}
+
+#[tracing::instrument(
+    name = "Export subscribers as CSV",
+    skip(state, user),
+    fields(username = %user.username)
+)]
+pub async fn export_subscribers(
+    user: AuthenticatedUser,
+    Extension(request_id): Extension<RequestId>,
+    State(state): State<Arc<ApplicationState>>,
+    AdminQuery(parameters): AdminQuery<ExportParameters>,
+) -> Result<Response, AdminError> {
+    if let Some(status) = &parameters.status {
+        validate_status(status).map_err(AdminError::ValidationError)?;
+    }
+
+    record_audit_event(
+        &state.db_connection,
+        &user,
+        &request_id,
+        AuditEvent {
+            action: "subscribers.export",
+            target: None,
+            diff: None,
+        },
+    )
+    .await
+    .context("Failed to record the audit event")?;
+
+    let header = encode_csv_rows(vec![StringRecord::from(vec![
+        "id",
+        "email",
+        "name",
+        "status",
+        "subscribed_at",
+    ])])
+    .await
+    .context("Failed to encode the CSV header")?;
+
+    let tenant_id = user.tenant_id;
+    // Subscribers are read page by page while the response is being sent.
+    // The state is the id to resume after, or `None` once the last page has been sent.
+    let rows =
+        futures_util::stream::try_unfold(Some(None), move |last_id: Option<Option<Uuid>>| {
+            let state = state.clone();
+            let status = parameters.status.clone();
+
+            async move {
+                let Some(last_id) = last_id else {
+                    return Ok(None);
+                };
+
+                let page = state
+                    .read_only(|db_connection| {
+                        let status = status.clone();
+                        async move {
+                            get_subscribers_page(&db_connection, tenant_id, status, last_id).await
+                        }
+                    })
+                    .await
+                    .context("Failed to fetch subscribers from the database")?;
+                let next = if (page.len() as u64) < EXPORT_BATCH_SIZE {
+                    None
+                } else {
+                    page.last().map(|subscriber| Some(subscriber.id))
+                };
+
+                let chunk = encode_csv_rows(
+                    page.into_iter()
+                        .map(|subscriber| {
+                            StringRecord::from(vec![
+                                subscriber.id.to_string(),
+                                subscriber.email,
+                                subscriber.name,
+                                subscriber.status,
+                                subscriber.subscribed_at.to_rfc3339(),
+                            ])
+                        })
+                        .collect(),
+                )
+                .await?;
+
+                Ok::<_, anyhow::Error>(Some((chunk, next)))
+            }
+        });
+    let body = futures_util::stream::once(async { Ok(header) })
+        .chain(rows)
+        .inspect_err(|e| tracing::error!("Failed to export subscribers: {:?}", e))
+        .map_err(std::io::Error::other);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                r#"attachment; filename="subscribers.csv""#,
+            ),
+        ],
+        Body::from_stream(body),
+    )
+        .into_response())
+}
+
+fn find_column(headers: &StringRecord, name: &str) -> Result<usize, AdminError> {
+    headers
+        .iter()
+        .position(|header| header.eq_ignore_ascii_case(name))
+        .ok_or_else(|| AdminError::ValidationError(format!("The CSV has no `{}` column", name)))
+}
+
+async fn encode_csv_rows(records: Vec<StringRecord>) -> Result<Vec<u8>, anyhow::Error> {
+    let mut writer = AsyncWriterBuilder::new()
+        .has_headers(false)
+        .create_writer(Vec::new());
+
+    for record in records {
+        writer.write_record(&record).await?;
+    }
+
+    writer
+        .into_inner()
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to flush the CSV writer: {}", e))
+}
+
+#[tracing::instrument(name = "Get a page of subscribers to export", skip(db_connection))]
+async fn get_subscribers_page(
+    db_connection: &DatabaseConnection,
+    tenant_id: Uuid,
+    status: Option<String>,
+    after_id: Option<Uuid>,
+) -> Result<Vec<subscriptions::Model>, DbErr> {
+    Subscriptions::find()
+        .filter(subscriptions::Column::TenantId.eq(tenant_id))
+        .apply_if(status, |query, status| {
+            query.filter(subscriptions::Column::Status.eq(status))
+        })
+        .apply_if(after_id, |query, after_id| {
+            query.filter(subscriptions::Column::Id.gt(after_id))
+        })
+        .order_by_asc(subscriptions::Column::Id)
+        .limit(EXPORT_BATCH_SIZE)
+        .all(db_connection)
+        .await
+}
+
+/// What became of a row of the import.
+enum RowOutcome {
+    /// Added to the list, with the subscription and preference tokens of the confirmation
+    /// email to send
+    Imported(Option<(String, String)>),
+    Skipped(&'static str),
+}
+
+/// Imports a batch in a single transaction, then leaves its confirmation emails to a
+/// background task.
+#[tracing::instrument(name = "Import a batch of subscribers", skip_all, fields(size = batch.len()))]
+async fn import_batch(
+    state: &ApplicationState,
+    tenant: &Tenant,
+    list_id: Uuid,
+    mode: ImportMode,
+    batch: &[ImportRow],
+    report: &mut ImportReport,
+) -> Result<(), anyhow::Error> {
+    let transaction = state
+        .db_connection
+        .begin()
+        .await
+        .context("Failed to begin a Postgres transaction")?;
+
+    let outcomes = insert_batch(&transaction, tenant.id, list_id, mode, batch)
+        .await
+        .context("Failed to insert the imported subscribers")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the Postgres transaction")?;
+
+    let mut confirmations = Vec::new();
+    for (row, outcome) in batch.iter().zip(outcomes) {
+        match outcome {
+            RowOutcome::Imported(tokens) => {
+                report.imported += 1;
+                if let Some((subscription_token, preference_token)) = tokens {
+                    confirmations.push((
+                        row.new_subscriber.email.clone(),
+                        subscription_token,
+                        preference_token,
+                    ));
+                }
+            }
+            RowOutcome::Skipped(reason) => report.skipped.push(RowReport {
+                row: row.row,
+                email: Some(row.new_subscriber.email.as_ref().to_string()),
+                reason: reason.into(),
+            }),
+        }
+    }
+
+    if !confirmations.is_empty() {
+        state
+            .shutdown
+            .spawn(send_confirmation_emails(tenant.clone(), confirmations));
+    }
+
+    Ok(())
+}
+
+/// Adds the subscribers of a batch to the list, creating those whose address is not known yet.
+///
+/// Every step is a single statement for the whole batch. Conflicts are left alone rather than
+/// failing the batch, so a signup racing with the import is not an error.
+async fn insert_batch(
+    transaction: &DatabaseTransaction,
+    tenant_id: Uuid,
+    list_id: Uuid,
+    mode: ImportMode,
+    batch: &[ImportRow],
+) -> Result<Vec<RowOutcome>, DbErr> {
+    let now = DateTimeWithTimeZone::from(Utc::now());
+    let status = match mode {
+        ImportMode::Confirmed => "confirmed",
+        ImportMode::DoubleOptIn => "pending_confirmation",
+    };
+    let new_ids: Vec<Uuid> = batch.iter().map(|_| Uuid::new_v4()).collect();
+
+    Subscriptions::insert_many(batch.iter().zip(&new_ids).map(|(row, id)| {
+        subscriptions::ActiveModel {
+            id: Set(*id),
+            email: Set(row.new_subscriber.email.as_ref().to_string()),
+            ascii_email: Set(row.new_subscriber.email.ascii_compatible().to_string()),
+            normalized_email: Set(row.new_subscriber.email.normalized().to_string()),
+            name: Set(row.new_subscriber.name.as_ref().to_string()),
+            subscribed_at: Set(now),
+            status: Set(status.to_string()),
+            tenant_id: Set(tenant_id),
+        }
+    }))
+    .on_conflict(
+        OnConflict::columns([
+            subscriptions::Column::TenantId,
+            subscriptions::Column::NormalizedEmail,
+        ])
+        .do_nothing()
+        .to_owned(),
+    )
+    .exec_without_returning(transaction)
+    .await?;
+
+    // Rows whose address was already known resolve to the existing subscriber
+    let subscriber_ids: HashMap<String, Uuid> = Subscriptions::find()
+        .select_only()
+        .column(subscriptions::Column::NormalizedEmail)
+        .column(subscriptions::Column::Id)
+        .filter(subscriptions::Column::TenantId.eq(tenant_id))
+        .filter(
+            subscriptions::Column::NormalizedEmail.is_in(
+                batch
+                    .iter()
+                    .map(|row| row.new_subscriber.email.normalized()),
+            ),
+        )
+        .into_tuple::<(String, Uuid)>()
+        .all(transaction)
+        .await?
+        .into_iter()
+        .collect();
+
+    let memberships: HashMap<Uuid, String> = SubscriptionLists::find()
+        .select_only()
+        .column(subscription_lists::Column::SubscriberId)
+        .column(subscription_lists::Column::Status)
+        .filter(subscription_lists::Column::ListId.eq(list_id))
+        .filter(subscription_lists::Column::SubscriberId.is_in(subscriber_ids.values().copied()))
+        .into_tuple::<(Uuid, String)>()
+        .all(transaction)
+        .await?
+        .into_iter()
+        .collect();
+
+    let mut joined = Vec::new();
+    let mut existing = Vec::new();
+    let mut skipped = Vec::with_capacity(batch.len());
+    for (row, new_id) in batch.iter().zip(&new_ids) {
+        let subscriber_id = *subscriber_ids
+            .get(row.new_subscriber.email.normalized())
+            .ok_or_else(|| DbErr::RecordNotFound("imported subscriber".into()))?;
+
+        let reason = match memberships.get(&subscriber_id).map(String::as_str) {
+            // Those who left the list are not for an import to bring back
+            Some("unsubscribed") => Some("Unsubscribed from the list"),
+            Some(_) => Some("Already subscribed"),
+            None => {
+                if subscriber_id != *new_id {
+                    existing.push(subscriber_id);
+                }
+                joined.push(subscriber_id);
+                None
+            }
+        };
+        skipped.push(reason);
+    }
+
+    if !joined.is_empty() {
+        SubscriptionLists::insert_many(joined.iter().map(|subscriber_id| {
+            subscription_lists::ActiveModel {
+                subscriber_id: Set(*subscriber_id),
+                list_id: Set(list_id),
+                status: Set(status.to_string()),
+                subscribed_at: Set(now),
+            }
+        }))
+        .on_conflict(
+            OnConflict::columns([
+                subscription_lists::Column::SubscriberId,
+                subscription_lists::Column::ListId,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .exec_without_returning(transaction)
+        .await?;
+    }
+
+    if !existing.is_empty() {
+        // Joining a list confirms the subscriber, or makes them pending again if they had left
+        // every other list
+        let update = Subscriptions::update_many()
+            .col_expr(subscriptions::Column::Status, Expr::value(status))
+            .filter(subscriptions::Column::Id.is_in(existing));
+        match mode {
+            ImportMode::Confirmed => update,
+            ImportMode::DoubleOptIn => {
+                update.filter(subscriptions::Column::Status.eq("unsubscribed"))
+            }
+        }
+        .exec(transaction)
+        .await?;
+    }
+
+    let mut tokens = match mode {
+        ImportMode::Confirmed => HashMap::new(),
+        ImportMode::DoubleOptIn => issue_tokens(transaction, list_id, &joined).await?,
+    };
+
+    Ok(batch
+        .iter()
+        .zip(skipped)
+        .map(|(row, reason)| match reason {
+            Some(reason) => RowOutcome::Skipped(reason),
+            None => {
+                let subscriber_id = subscriber_ids[row.new_subscriber.email.normalized()];
+                RowOutcome::Imported(tokens.remove(&subscriber_id))
+            }
+        })
+        .collect())
+}
+
+/// Stores a subscription token for each subscriber joining the list, and gives a preference
+/// token to those who have none yet.
+async fn issue_tokens(
+    transaction: &DatabaseTransaction,
+    list_id: Uuid,
+    subscriber_ids: &[Uuid],
+) -> Result<HashMap<Uuid, (String, String)>, DbErr> {
+    if subscriber_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let subscription_tokens: Vec<(Uuid, String)> = subscriber_ids
+        .iter()
+        .map(|subscriber_id| (*subscriber_id, generate_subscription_token()))
+        .collect();
+    SubscriptionTokens::insert_many(subscription_tokens.iter().map(
+        |(subscriber_id, subscription_token)| subscription_tokens::ActiveModel {
+            subscription_token: Set(subscription_token.clone()),
+            subscriber_id: Set(*subscriber_id),
+            list_id: Set(list_id),
+        },
+    ))
+    .exec_without_returning(transaction)
+    .await?;
+
+    PreferenceTokens::insert_many(subscriber_ids.iter().map(|subscriber_id| {
+        preference_tokens::ActiveModel {
+            preference_token: Set(generate_subscription_token()),
+            subscriber_id: Set(*subscriber_id),
+        }
+    }))
+    .on_conflict(
+        OnConflict::column(preference_tokens::Column::SubscriberId)
+            .do_nothing()
+            .to_owned(),
+    )
+    .exec_without_returning(transaction)
+    .await?;
+
+    let preference_tokens: HashMap<Uuid, String> = PreferenceTokens::find()
+        .select_only()
+        .column(preference_tokens::Column::SubscriberId)
+        .column(preference_tokens::Column::PreferenceToken)
+        .filter(preference_tokens::Column::SubscriberId.is_in(subscriber_ids.iter().copied()))
+        .into_tuple::<(Uuid, String)>()
+        .all(transaction)
+        .await?
+        .into_iter()
+        .collect();
+
+    subscription_tokens
+        .into_iter()
+        .map(|(subscriber_id, subscription_token)| {
+            let preference_token = preference_tokens
+                .get(&subscriber_id)
+                .cloned()
+                .ok_or_else(|| DbErr::RecordNotFound("preference token".into()))?;
+            Ok((subscriber_id, (subscription_token, preference_token)))
+        })
+        .collect()
+}
+
+/// Sends the confirmation emails of an imported batch, once the response may already be gone.
+#[tracing::instrument(
+    name = "Send confirmation emails to imported subscribers",
+    skip_all,
+    fields(tenant = %tenant.slug, count = confirmations.len())
+)]
+async fn send_confirmation_emails(
+    tenant: Tenant,
+    confirmations: Vec<(SubscriberEmail, String, String)>,
+) {
+    for (email, subscription_token, preference_token) in confirmations {
+        if let Err(e) = send_confirmation_email(
+            &tenant.email_client,
+            &email,
+            &tenant.base_url,
+            &subscription_token,
+            &preference_token,
+        )
+        .await
+        {
+            tracing::error!(
+                "Failed to send a confirmation email to an imported subscriber: {:?}",
+                e
+            );
+        }
+    }
+}
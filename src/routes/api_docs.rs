@@ -0,0 +1,50 @@
+use std::sync::Arc;
+
+use axum::Json;
+use axum::extract::State;
+use utoipa::openapi::OpenApi as OpenApiDocument;
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+use crate::startup::ApplicationState;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "zero2prod", description = "Newsletter subscriptions and publishing"),
+    tags(
+        (name = "subscriptions", description = "Public signup flow"),
+        (name = "newsletters", description = "Publishing, for authenticated users"),
+    ),
+    modifiers(&BasicAuth)
+)]
+pub struct ApiDoc;
+
+struct BasicAuth;
+
+impl Modify for BasicAuth {
+    fn modify(&self, openapi: &mut OpenApiDocument) {
+        openapi
+            .components
+            .get_or_insert_default()
+            .add_security_scheme(
+                "basic_auth",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Basic).build()),
+            );
+    }
+}
+
+/// The routes client teams build against. Registering them here is what puts them in
+/// the document served at `/api-docs/openapi.json`.
+pub fn api_routes() -> OpenApiRouter<Arc<ApplicationState>> {
+    OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .routes(routes!(super::subscriptions::subscribe))
+        .routes(routes!(super::subscriptions::form_token))
+        .routes(routes!(super::subscriptions_confirm::confirm))
+        .routes(routes!(super::newsletters::publish_newsletters))
+}
+
+pub async fn openapi_json(State(state): State<Arc<ApplicationState>>) -> Json<OpenApiDocument> {
+    Json(state.openapi.clone())
+}
@@ -1,6 +1,143 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+
+use axum::Json;
+use axum::extract::State;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
+use migration::{Migrator, MigratorTrait};
+use serde::Serialize;
+
+use crate::startup::ApplicationState;
 
 pub async fn health_check() -> Response {
     StatusCode::OK.into_response()
 }
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Up,
+    Down,
+}
+
+#[derive(Serialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    /// Keyed by dependency, e.g. `database`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<&'static str, CheckReport>,
+}
+
+#[derive(Serialize)]
+pub struct CheckReport {
+    pub status: HealthStatus,
+    pub duration_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+/// Liveness: the process is up and serving requests, whatever the state of its dependencies.
+pub async fn health_live() -> Json<HealthReport> {
+    Json(HealthReport {
+        status: HealthStatus::Up,
+        checks: BTreeMap::new(),
+    })
+}
+
+/// Readiness: every dependency needed to serve traffic is available.
+/// Answers 503 when any check fails, so orchestrators stop routing requests here.
+#[tracing::instrument(name = "Check readiness", skip(state))]
+pub async fn health_ready(State(state): State<Arc<ApplicationState>>) -> Response {
+    let database = run_check(&state, async {
+        state.db_connection.ping().await.map_err(|e| e.to_string())
+    });
+    let migrations = run_check(&state, async {
+        let pending = Migrator::get_pending_migrations(&state.db_connection)
+            .await
+            .map_err(|e| e.to_string())?;
+        if pending.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} pending migrations: {}",
+                pending.len(),
+                pending
+                    .iter()
+                    .map(|migration| migration.name())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        }
+    });
+    let email_provider = async {
+        if state.health.check_email_provider {
+            let check = run_check(&state, async {
+                state
+                    .email_client
+                    .check_reachable()
+                    .await
+                    .map_err(|e| e.to_string())
+            });
+            Some(check.await)
+        } else {
+            None
+        }
+    };
+
+    let (database, migrations, email_provider) = tokio::join!(database, migrations, email_provider);
+
+    let mut checks = BTreeMap::from([("database", database), ("migrations", migrations)]);
+    if let Some(email_provider) = email_provider {
+        checks.insert("email_provider", email_provider);
+    }
+
+    let status = if checks
+        .values()
+        .all(|check| check.status == HealthStatus::Up)
+    {
+        HealthStatus::Up
+    } else {
+        tracing::warn!(
+            failed_checks = ?checks
+                .iter()
+                .filter(|(_, check)| check.status == HealthStatus::Down)
+                .map(|(name, check)| (name, check.detail.as_deref()))
+                .collect::<Vec<_>>(),
+            "The application is not ready"
+        );
+        HealthStatus::Down
+    };
+    let status_code = match status {
+        HealthStatus::Up => StatusCode::OK,
+        HealthStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+    };
+
+    (status_code, Json(HealthReport { status, checks })).into_response()
+}
+
+async fn run_check(
+    state: &ApplicationState,
+    check: impl Future<Output = Result<(), String>>,
+) -> CheckReport {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(state.health.timeout(), check).await {
+        Ok(result) => result,
+        Err(_) => Err(format!(
+            "Timed out after {}ms",
+            state.health.timeout().as_millis()
+        )),
+    };
+
+    CheckReport {
+        status: if result.is_ok() {
+            HealthStatus::Up
+        } else {
+            HealthStatus::Down
+        },
+        duration_ms: start.elapsed().as_millis(),
+        detail: result.err(),
+    }
+}
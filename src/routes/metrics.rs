@@ -0,0 +1,22 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use prometheus::TEXT_FORMAT;
+
+use crate::startup::ApplicationState;
+
+pub async fn get_metrics(State(state): State<Arc<ApplicationState>>) -> Response {
+    match state.metrics.encode() {
+        Ok(metrics) => (
+            [(header::CONTENT_TYPE, HeaderValue::from_static(TEXT_FORMAT))],
+            metrics,
+        )
+            .into_response(),
+        Err(e) => {
+            tracing::error!(error.cause_chain = ?e, "Failed to encode the metrics");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -1,13 +1,23 @@
+mod admin;
+mod api_docs;
 mod health_check;
+mod metrics;
 mod newsletters;
+mod personal_data;
+mod preferences;
 mod subscriptions;
 mod subscriptions_confirm;
 
 use std::error::Error;
 use std::fmt::Formatter;
 
+pub use admin::*;
+pub use api_docs::*;
 pub use health_check::*;
+pub use metrics::*;
 pub use newsletters::*;
+pub use personal_data::*;
+pub use preferences::*;
 pub use subscriptions::*;
 pub use subscriptions_confirm::*;
 
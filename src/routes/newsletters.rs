@@ -1,23 +1,41 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use anyhow::Context;
 use axum::extract::rejection::JsonRejection;
-use axum::extract::{FromRequest, Json, State};
+use axum::extract::{Extension, FromRequest, Json, State};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
-use entity::prelude::Subscriptions;
-use entity::subscriptions;
-use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, SelectColumns};
+use chrono::{DateTime, Utc};
+use entity::prelude::{Lists, SubscriberTags, Subscriptions};
+use entity::{lists, subscriber_tags, subscription_lists, subscriptions};
+use migration::DEFAULT_LIST_NAME;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::sea_query::Query;
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, DbErr, EntityTrait, JoinType, QueryFilter, QuerySelect,
+    QueryTrait, RelationTrait,
+};
 use serde::Deserialize;
+use serde_json::{Value, json};
+use tower_request_id::RequestId;
+use utoipa::ToSchema;
+use uuid::Uuid;
 
-use crate::domain::SubscriberEmail;
-use crate::routes::error_chain_fmt;
+use crate::audit::{AuditEvent, diff, record_audit_event};
+use crate::authentication::AuthenticatedUser;
+use crate::domain::{SubscriberEmail, SubscriberTag};
+use crate::problem::{PROBLEM_JSON, Problem};
+use crate::routes::{error_chain_fmt, get_or_create_preference_token};
 use crate::startup::ApplicationState;
+use crate::tenant::Tenant;
 
 #[derive(thiserror::Error)]
 pub enum PublishError {
     #[error(transparent)]
     JsonRejection(#[from] JsonRejection),
+    #[error("{0}")]
+    ValidationError(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -32,10 +50,13 @@ impl IntoResponse for PublishError {
     fn into_response(self) -> Response {
         tracing::error!("{:?}", self);
 
-        match self {
-            PublishError::JsonRejection(_) => StatusCode::BAD_REQUEST.into_response(),
-            PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-        }
+        let status = match self {
+            PublishError::JsonRejection(_) | PublishError::ValidationError(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            PublishError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Problem::from_error(status, &self).into_response()
     }
 }
 
@@ -43,48 +64,188 @@ impl IntoResponse for PublishError {
 #[from_request(via(Json), rejection(PublishError))]
 pub struct PublishBody<T>(T);
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct BodyData {
     pub title: String,
     pub content: Content,
+    /// Names of the mailing lists to deliver to, the default list is used if empty
+    #[serde(default)]
+    pub lists: Vec<String>,
+    /// Narrows the audience within the target lists
+    #[serde(default)]
+    pub segment: Segment,
+}
+
+#[derive(Deserialize, Debug, Default, ToSchema)]
+pub struct Segment {
+    /// Only deliver to subscribers carrying at least one of these tags
+    #[serde(default)]
+    pub include_tags: Vec<String>,
+    /// Never deliver to subscribers carrying any of these tags
+    #[serde(default)]
+    pub exclude_tags: Vec<String>,
+    pub subscribed_after: Option<DateTime<Utc>>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, ToSchema)]
 pub struct Content {
     pub html: String,
     pub text: String,
 }
 
+/// Sends a newsletter issue to the confirmed subscribers of the target lists, from the sender
+/// of the tenant the publisher belongs to.
+#[utoipa::path(
+    post,
+    path = "/newsletters",
+    tag = "newsletters",
+    request_body = BodyData,
+    security(("basic_auth" = [])),
+    responses(
+        (status = 200, description = "The issue was sent"),
+        (status = 400, body = Problem, content_type = PROBLEM_JSON),
+        (status = 401, description = "Missing or invalid credentials",
+            body = Problem, content_type = PROBLEM_JSON,
+            headers(("WWW-Authenticate" = String))),
+        (status = 500, body = Problem, content_type = PROBLEM_JSON),
+    )
+)]
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(state, user, tenant, body),
+    fields(username = %user.username, tenant = %tenant.slug)
+)]
 pub async fn publish_newsletters(
+    user: AuthenticatedUser,
+    tenant: Tenant,
+    Extension(request_id): Extension<RequestId>,
     State(state): State<Arc<ApplicationState>>,
     PublishBody(body): PublishBody<BodyData>,
 ) -> Result<Response, PublishError> {
-    let subscribers = get_confirmed_subscribers(&state.db_connection)
+    let mut list_names = if body.lists.is_empty() {
+        vec![DEFAULT_LIST_NAME.to_string()]
+    } else {
+        body.lists
+    };
+    // Naming a list twice targets it once, and must not count as an unknown list
+    let mut seen = HashSet::new();
+    list_names.retain(|name| seen.insert(name.clone()));
+
+    let list_ids = get_list_ids(&state.db_connection, tenant.id, &list_names)
+        .await
+        .context("Failed to get the target mailing lists")?;
+
+    if list_ids.len() != list_names.len() {
+        return Err(PublishError::ValidationError(format!(
+            "{:?} contains an unknown mailing list",
+            list_names
+        )));
+    }
+
+    let parse_tags = |tags: Vec<String>| {
+        tags.into_iter()
+            .map(SubscriberTag::parse)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(PublishError::ValidationError)
+    };
+
+    let audience = Audience {
+        tenant_id: tenant.id,
+        list_ids,
+        include_tags: parse_tags(body.segment.include_tags)?,
+        exclude_tags: parse_tags(body.segment.exclude_tags)?,
+        subscribed_after: body.segment.subscribed_after,
+    };
+
+    let subscribers = state
+        .read_only(|db_connection| {
+            let audience = &audience;
+            async move { get_confirmed_subscribers(&db_connection, audience).await }
+        })
         .await
         .context("Failed to get confirmed subscribers")?;
 
+    // Recorded before delivery starts, so a partially delivered issue is still accounted for
+    let issue = json!({
+        "title": body.title,
+        "lists": list_names,
+        "include_tags": audience.include_tags.iter().map(AsRef::as_ref).collect::<Vec<&str>>(),
+        "exclude_tags": audience.exclude_tags.iter().map(AsRef::as_ref).collect::<Vec<&str>>(),
+        "subscribed_after": audience.subscribed_after,
+        "recipients": subscribers.len(),
+    });
+    record_audit_event(
+        &state.db_connection,
+        &user,
+        &request_id,
+        AuditEvent {
+            action: "newsletter.publish",
+            target: Some(body.title.clone()),
+            diff: Some(diff(&Value::Null, &issue)),
+        },
+    )
+    .await
+    .context("Failed to record the audit event")?;
+
     for subscriber in subscribers {
         match subscriber {
             Ok(subscriber) => {
-                state
+                if let Err(error) = tenant.email_client.check_recipient(&subscriber.email) {
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        "Skipping a confirmed subscriber. The email transport cannot reach them"
+                    );
+                    state
+                        .metrics
+                        .newsletter_emails
+                        .with_label_values(&["skipped"])
+                        .inc();
+                    continue;
+                }
+                let preference_token =
+                    get_or_create_preference_token(&state.db_connection, subscriber.id)
+                        .await
+                        .context("Failed to get the subscriber's preference token")?;
+                let preferences_link = format!(
+                    "{}/preferences?preference_token={}",
+                    tenant.base_url, preference_token
+                );
+
+                tenant
                     .email_client
                     .send_email(
                         &subscriber.email,
                         &body.title,
-                        &body.content.html,
-                        &body.content.text,
+                        &format!(
+                            "{}<p><a href=\"{}\">Manage your subscription</a></p>",
+                            body.content.html, preferences_link
+                        ),
+                        &format!(
+                            "{}\n\nManage your subscription: {}",
+                            body.content.text, preferences_link
+                        ),
                     )
                     .await
                     // `with_context` is lazy so it only allocate memory when email delivery fails
                     .with_context(|| {
                         format!("Failed to send newsletter issue to {}", subscriber.email)
                     })?;
+                state
+                    .metrics
+                    .newsletter_emails
+                    .with_label_values(&["sent"])
+                    .inc();
             }
             Err(error) => {
                 tracing::warn!(
                     error.cause_chain = ?error,
                     "Skipping a confirmed subscriber. The stored contact details are invalid"
-                )
+                );
+                state
+                    .metrics
+                    .newsletter_emails
+                    .with_label_values(&["skipped"])
+                    .inc();
             }
         }
     }
@@ -93,21 +254,88 @@ pub async fn publish_newsletters(
 }
 
 struct ConfirmedSubscriber {
+    id: Uuid,
     email: SubscriberEmail,
 }
 
+/// Validated form of the target lists and `Segment` of a newsletter issue
+#[derive(Debug)]
+struct Audience {
+    tenant_id: Uuid,
+    list_ids: Vec<Uuid>,
+    include_tags: Vec<SubscriberTag>,
+    exclude_tags: Vec<SubscriberTag>,
+    subscribed_after: Option<DateTime<Utc>>,
+}
+
+#[tracing::instrument(name = "Get mailing list ids by name", skip(db_connection))]
+async fn get_list_ids(
+    db_connection: &DatabaseConnection,
+    tenant_id: Uuid,
+    list_names: &[String],
+) -> Result<Vec<Uuid>, DbErr> {
+    let list_ids = Lists::find()
+        .select_only()
+        .column(lists::Column::Id)
+        .filter(lists::Column::TenantId.eq(tenant_id))
+        .filter(lists::Column::Name.is_in(list_names))
+        .into_tuple()
+        .all(db_connection)
+        .await?;
+
+    Ok(list_ids)
+}
+
 #[tracing::instrument(name = "Get confirmed subscribers", skip(db_connection))]
 async fn get_confirmed_subscribers(
     db_connection: &DatabaseConnection,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
+    audience: &Audience,
+) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, DbErr> {
+    let tagged_subscribers = |tags: &[SubscriberTag]| {
+        Query::select()
+            .column(subscriber_tags::Column::SubscriberId)
+            .from(SubscriberTags)
+            .and_where(subscriber_tags::Column::Tag.is_in(tags.iter().map(AsRef::as_ref)))
+            .to_owned()
+    };
+
+    // Subscribers on several of the target lists must only receive the issue once
     let confirmed_subscribers = Subscriptions::find()
-        .select_column(subscriptions::Column::Email)
-        .filter(subscriptions::Column::Status.eq("confirmed"))
+        .select_only()
+        .column(subscriptions::Column::Id)
+        .column(subscriptions::Column::Email)
+        .distinct()
+        .join(
+            JoinType::InnerJoin,
+            subscriptions::Relation::SubscriptionLists.def(),
+        )
+        .filter(subscriptions::Column::TenantId.eq(audience.tenant_id))
+        .filter(subscription_lists::Column::ListId.is_in(audience.list_ids.iter().copied()))
+        .filter(subscription_lists::Column::Status.eq("confirmed"))
+        .apply_if(
+            (!audience.include_tags.is_empty()).then_some(&audience.include_tags),
+            |query, tags| {
+                query.filter(subscriptions::Column::Id.in_subquery(tagged_subscribers(tags)))
+            },
+        )
+        .apply_if(
+            (!audience.exclude_tags.is_empty()).then_some(&audience.exclude_tags),
+            |query, tags| {
+                query.filter(subscriptions::Column::Id.not_in_subquery(tagged_subscribers(tags)))
+            },
+        )
+        .apply_if(audience.subscribed_after, |query, subscribed_after| {
+            query.filter(
+                subscriptions::Column::SubscribedAt
+                    .gt(DateTimeWithTimeZone::from(subscribed_after)),
+            )
+        })
+        .into_tuple::<(Uuid, String)>()
         .all(db_connection)
         .await?
         .into_iter()
-        .map(|row| match SubscriberEmail::parse(row.email) {
-            Ok(email) => Ok(ConfirmedSubscriber { email }),
+        .map(|(id, email)| match SubscriberEmail::parse(email) {
+            Ok(email) => Ok(ConfirmedSubscriber { id, email }),
             Err(error) => Err(anyhow::anyhow!(error)),
         })
         .collect();
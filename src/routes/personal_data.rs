@@ -0,0 +1,205 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::header;
+use axum::response::{Html, IntoResponse, Response};
+use entity::prelude::{PreferenceTokens, SubscriberTags, SubscriptionLists, Subscriptions};
+use entity::{lists, preference_tokens, subscriber_tags, subscription_lists, subscription_tokens};
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::{
+    ColumnTrait, DatabaseConnection, DbErr, EntityTrait, JoinType, QueryFilter, QueryOrder,
+    QuerySelect, RelationTrait, TransactionTrait,
+};
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::preferences::{
+    PreferencesError, PreferencesForm, PreferencesParameters, UnsubscribeInfo, authorize,
+};
+use crate::startup::ApplicationState;
+use crate::tenant::Tenant;
+
+/// Everything stored about a single subscriber.
+///
+/// Newsletter deliveries are not recorded per subscriber, so there is nothing to report for them.
+#[derive(Serialize)]
+pub struct PersonalData {
+    subscription: SubscriptionData,
+    /// When and how the subscriber consented to each mailing list
+    consent: Vec<ConsentData>,
+    tags: Vec<String>,
+    subscription_tokens: Vec<SubscriptionTokenData>,
+    preference_token: String,
+}
+
+#[derive(Serialize)]
+pub struct SubscriptionData {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    subscribed_at: DateTimeWithTimeZone,
+}
+
+#[derive(Serialize)]
+pub struct ConsentData {
+    list: String,
+    status: String,
+    subscribed_at: DateTimeWithTimeZone,
+}
+
+#[derive(Serialize)]
+pub struct SubscriptionTokenData {
+    subscription_token: String,
+    list: String,
+}
+
+#[tracing::instrument(
+    name = "Export subscriber personal data",
+    skip(state, tenant, parameters),
+    fields(tenant = %tenant.slug)
+)]
+pub async fn export_personal_data(
+    State(state): State<Arc<ApplicationState>>,
+    tenant: Tenant,
+    Query(parameters): Query<PreferencesParameters>,
+) -> Result<Response, PreferencesError> {
+    let subscriber_id = authorize(
+        &state.db_connection,
+        tenant.id,
+        &parameters.preference_token,
+    )
+    .await?;
+
+    // Read from the primary, a replica lagging behind the signup would miss the preference
+    // token or the latest consent, and the export must be complete
+    let personal_data = get_personal_data(&state.db_connection, subscriber_id)
+        .await
+        .context("Failed to collect the subscriber's personal data")?;
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            r#"attachment; filename="personal-data.json""#,
+        )],
+        Json(personal_data),
+    )
+        .into_response())
+}
+
+#[tracing::instrument(
+    name = "Erase subscriber personal data",
+    skip(state, tenant, form),
+    fields(tenant = %tenant.slug)
+)]
+pub async fn erase_personal_data(
+    State(state): State<Arc<ApplicationState>>,
+    tenant: Tenant,
+    PreferencesForm(form): PreferencesForm<UnsubscribeInfo>,
+) -> Result<Response, PreferencesError> {
+    let subscriber_id = authorize(&state.db_connection, tenant.id, &form.preference_token).await?;
+
+    let transaction = state
+        .db_connection
+        .begin()
+        .await
+        .context("Failed to begin a Postgres transaction")?;
+
+    // Memberships, tags, and every kind of token cascade from the subscription row
+    Subscriptions::delete_by_id(subscriber_id)
+        .exec(&transaction)
+        .await
+        .context("Failed to erase the subscriber")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the Postgres transaction")?;
+
+    Ok(Html("All personal data we held about you has been erased.").into_response())
+}
+
+#[tracing::instrument(name = "Get subscriber personal data", skip(db_connection))]
+async fn get_personal_data(
+    db_connection: &DatabaseConnection,
+    subscriber_id: Uuid,
+) -> Result<PersonalData, DbErr> {
+    let subscription = Subscriptions::find_by_id(subscriber_id)
+        .one(db_connection)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound("subscription".into()))?;
+
+    let consent = SubscriptionLists::find()
+        .select_only()
+        .column(lists::Column::Name)
+        .column(subscription_lists::Column::Status)
+        .column(subscription_lists::Column::SubscribedAt)
+        .join(
+            JoinType::InnerJoin,
+            subscription_lists::Relation::Lists.def(),
+        )
+        .filter(subscription_lists::Column::SubscriberId.eq(subscriber_id))
+        .order_by_asc(lists::Column::Name)
+        .into_tuple::<(String, String, DateTimeWithTimeZone)>()
+        .all(db_connection)
+        .await?
+        .into_iter()
+        .map(|(list, status, subscribed_at)| ConsentData {
+            list,
+            status,
+            subscribed_at,
+        })
+        .collect();
+
+    let tags = SubscriberTags::find()
+        .select_only()
+        .column(subscriber_tags::Column::Tag)
+        .filter(subscriber_tags::Column::SubscriberId.eq(subscriber_id))
+        .order_by_asc(subscriber_tags::Column::Tag)
+        .into_tuple::<String>()
+        .all(db_connection)
+        .await?;
+
+    let subscription_tokens = subscription_tokens::Entity::find()
+        .select_only()
+        .column(subscription_tokens::Column::SubscriptionToken)
+        .column(lists::Column::Name)
+        .join(
+            JoinType::InnerJoin,
+            subscription_tokens::Relation::Lists.def(),
+        )
+        .filter(subscription_tokens::Column::SubscriberId.eq(subscriber_id))
+        .order_by_asc(lists::Column::Name)
+        .into_tuple::<(String, String)>()
+        .all(db_connection)
+        .await?
+        .into_iter()
+        .map(|(subscription_token, list)| SubscriptionTokenData {
+            subscription_token,
+            list,
+        })
+        .collect();
+
+    let preference_token = PreferenceTokens::find()
+        .filter(preference_tokens::Column::SubscriberId.eq(subscriber_id))
+        .one(db_connection)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound("preference token".into()))?
+        .preference_token;
+
+    Ok(PersonalData {
+        subscription: SubscriptionData {
+            id: subscription.id,
+            email: subscription.email,
+            name: subscription.name,
+            status: subscription.status,
+            subscribed_at: subscription.subscribed_at,
+        },
+        consent,
+        tags,
+        subscription_tokens,
+        preference_token,
+    })
+}
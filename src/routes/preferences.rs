@@ -0,0 +1,490 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{FromRequest, Query, State};
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum_extra::extract::Form;
+use axum_extra::extract::FormRejection;
+use chrono::Utc;
+use entity::prelude::{
+    Lists, PreferenceTokens, SubscriptionLists, SubscriptionTokens, Subscriptions,
+};
+use entity::{lists, preference_tokens, subscription_lists, subscription_tokens, subscriptions};
+use htmlescape::encode_minimal;
+use sea_orm::ActiveValue::Set;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::sea_query::{Expr, OnConflict};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DatabaseTransaction, DbErr,
+    EntityTrait, IntoActiveModel, QueryFilter, QuerySelect, TransactionTrait,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::domain::{SubscriberEmail, SubscriberName};
+use crate::problem::{FieldError, FieldErrors, Problem};
+use crate::routes::{
+    error_chain_fmt, generate_subscription_token, send_confirmation_email, store_token,
+};
+use crate::startup::ApplicationState;
+use crate::tenant::Tenant;
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error(transparent)]
+    QueryRejection(#[from] QueryRejection),
+    #[error(transparent)]
+    FormRejection(#[from] FormRejection),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("Invalid fields: {0:?}")]
+    InvalidFields(Vec<FieldError>),
+    #[error("{0}")]
+    UnknownTokenError(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for PreferencesError {
+    fn into_response(self) -> Response {
+        tracing::error!("{:?}", self);
+
+        let status = match self {
+            PreferencesError::InvalidFields(errors) => {
+                return Problem::invalid_fields(errors).into_response();
+            }
+            PreferencesError::QueryRejection(_)
+            | PreferencesError::FormRejection(_)
+            | PreferencesError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            PreferencesError::UnknownTokenError(_) => StatusCode::UNAUTHORIZED,
+            PreferencesError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Problem::from_error(status, &self).into_response()
+    }
+}
+
+#[derive(FromRequest)]
+#[from_request(via(Form), rejection(PreferencesError))]
+pub struct PreferencesForm<T>(pub T);
+
+#[derive(Deserialize)]
+pub struct PreferencesParameters {
+    pub preference_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct PreferencesInfo {
+    preference_token: String,
+    name: String,
+    /// Names of the lists the subscriber wants to receive, unchecked lists are left and lists
+    /// not confirmed yet are confirmed by email
+    #[serde(default)]
+    lists: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct UnsubscribeInfo {
+    pub preference_token: String,
+}
+
+#[tracing::instrument(
+    name = "Show subscriber preferences",
+    skip(state, tenant, parameters),
+    fields(tenant = %tenant.slug)
+)]
+pub async fn preferences_page(
+    State(state): State<Arc<ApplicationState>>,
+    tenant: Tenant,
+    Query(parameters): Query<PreferencesParameters>,
+) -> Result<Response, PreferencesError> {
+    let subscriber_id = authorize(
+        &state.db_connection,
+        tenant.id,
+        &parameters.preference_token,
+    )
+    .await?;
+
+    let subscription = Subscriptions::find_by_id(subscriber_id)
+        .one(&state.db_connection)
+        .await
+        .context("Failed to fetch the subscriber from the database")?
+        .context("The subscriber of a preference token does not exist")?;
+
+    let all_lists = Lists::find()
+        .filter(lists::Column::TenantId.eq(tenant.id))
+        .all(&state.db_connection)
+        .await
+        .context("Failed to fetch mailing lists from the database")?;
+
+    let memberships = SubscriptionLists::find()
+        .filter(subscription_lists::Column::SubscriberId.eq(subscriber_id))
+        .filter(subscription_lists::Column::Status.ne("unsubscribed"))
+        .all(&state.db_connection)
+        .await
+        .context("Failed to fetch list memberships from the database")?;
+
+    let token = encode_minimal(&parameters.preference_token);
+
+    let list_checkboxes: String = all_lists
+        .iter()
+        .map(|list| {
+            let membership = memberships.iter().find(|m| m.list_id == list.id);
+            let checked = if membership.is_some() { " checked" } else { "" };
+            let pending = match membership {
+                Some(m) if m.status == "pending_confirmation" => " (awaiting confirmation)",
+                _ => "",
+            };
+            let name = encode_minimal(&list.name);
+
+            format!(
+                r#"<label><input type="checkbox" name="lists" value="{name}"{checked}> {name}{pending}</label><br>"#
+            )
+        })
+        .collect();
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8">
+    <title>Subscription preferences</title>
+</head>
+<body>
+    <form action="{prefix}/preferences" method="post">
+        <input type="hidden" name="preference_token" value="{token}">
+        <label>Name <input type="text" name="name" value="{name}"></label>
+        <fieldset>
+            <legend>Lists</legend>
+            {list_checkboxes}
+        </fieldset>
+        <button type="submit">Save preferences</button>
+    </form>
+    <form action="{prefix}/preferences/unsubscribe" method="post">
+        <input type="hidden" name="preference_token" value="{token}">
+        <button type="submit">Unsubscribe from all lists</button>
+    </form>
+    <p><a href="{prefix}/preferences/data?preference_token={token}">Download my personal data</a></p>
+    <form action="{prefix}/preferences/erase" method="post">
+        <input type="hidden" name="preference_token" value="{token}">
+        <button type="submit">Erase all my personal data</button>
+    </form>
+</body>
+</html>"#,
+        name = encode_minimal(&subscription.name),
+        prefix = encode_minimal(&tenant.path_prefix),
+    ))
+    .into_response())
+}
+
+#[tracing::instrument(
+    name = "Update subscriber preferences",
+    skip(state, tenant, form),
+    fields(tenant = %tenant.slug)
+)]
+pub async fn update_preferences(
+    State(state): State<Arc<ApplicationState>>,
+    tenant: Tenant,
+    PreferencesForm(form): PreferencesForm<PreferencesInfo>,
+) -> Result<Response, PreferencesError> {
+    let mut errors = FieldErrors::default();
+    let Some(name) = errors.check("name", SubscriberName::parse(form.name)) else {
+        return Err(PreferencesError::InvalidFields(errors.into_vec()));
+    };
+
+    let subscriber_id = authorize(&state.db_connection, tenant.id, &form.preference_token).await?;
+
+    let all_lists = Lists::find()
+        .filter(lists::Column::TenantId.eq(tenant.id))
+        .all(&state.db_connection)
+        .await
+        .context("Failed to fetch mailing lists from the database")?;
+
+    if let Some(unknown) = form
+        .lists
+        .iter()
+        .find(|name| !all_lists.iter().any(|list| &list.name == *name))
+    {
+        return Err(PreferencesError::ValidationError(format!(
+            "{} is not a known mailing list",
+            unknown
+        )));
+    }
+
+    let transaction = state
+        .db_connection
+        .begin()
+        .await
+        .context("Failed to begin a Postgres transaction")?;
+
+    let subscription = Subscriptions::find_by_id(subscriber_id)
+        .one(&transaction)
+        .await
+        .context("Failed to fetch the subscriber from the database")?
+        .context("The subscriber of a preference token does not exist")?;
+    let email = SubscriberEmail::parse(subscription.email.clone())
+        .map_err(anyhow::Error::msg)
+        .context("The stored subscriber email is invalid")?;
+
+    let memberships = SubscriptionLists::find()
+        .filter(subscription_lists::Column::SubscriberId.eq(subscriber_id))
+        .all(&transaction)
+        .await
+        .context("Failed to fetch list memberships from the database")?;
+
+    // Joining a list takes the same double opt-in as subscribing to it
+    let mut subscription_tokens = Vec::new();
+    for list in all_lists {
+        let current = memberships
+            .iter()
+            .find(|m| m.list_id == list.id)
+            .map(|m| m.status.as_str());
+        let (status, joined) = match (form.lists.contains(&list.name), current) {
+            (false, _) => ("unsubscribed", false),
+            (true, Some(status @ ("confirmed" | "pending_confirmation"))) => (status, false),
+            (true, _) => ("pending_confirmation", true),
+        };
+
+        set_membership_status(&transaction, subscriber_id, list.id, status)
+            .await
+            .context("Failed to update list memberships in the database")?;
+
+        if joined {
+            let subscription_token = generate_subscription_token();
+            store_token(&transaction, subscriber_id, list.id, &subscription_token)
+                .await
+                .context("Failed to store subscription token in the database")?;
+            subscription_tokens.push(subscription_token);
+        }
+    }
+
+    let status = subscriber_status(&transaction, subscriber_id)
+        .await
+        .context("Failed to fetch list memberships from the database")?;
+
+    let mut subscription = subscription.into_active_model();
+    subscription.name = Set(name.as_ref().to_string());
+    subscription.status = Set(status.to_string());
+    subscription
+        .update(&transaction)
+        .await
+        .context("Failed to update the subscriber in the database")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the Postgres transaction")?;
+
+    for subscription_token in subscription_tokens {
+        send_confirmation_email(
+            &tenant.email_client,
+            &email,
+            &tenant.base_url,
+            &subscription_token,
+            &form.preference_token,
+        )
+        .await
+        .context("Failed to send a confirmation email")?;
+    }
+
+    Ok(Redirect::to(&format!(
+        "{}/preferences?preference_token={}",
+        tenant.path_prefix, form.preference_token
+    ))
+    .into_response())
+}
+
+#[tracing::instrument(
+    name = "Unsubscribe a subscriber",
+    skip(state, tenant, form),
+    fields(tenant = %tenant.slug)
+)]
+pub async fn unsubscribe(
+    State(state): State<Arc<ApplicationState>>,
+    tenant: Tenant,
+    PreferencesForm(form): PreferencesForm<UnsubscribeInfo>,
+) -> Result<Response, PreferencesError> {
+    let subscriber_id = authorize(&state.db_connection, tenant.id, &form.preference_token).await?;
+
+    let transaction = state
+        .db_connection
+        .begin()
+        .await
+        .context("Failed to begin a Postgres transaction")?;
+
+    Subscriptions::update_many()
+        .col_expr(subscriptions::Column::Status, Expr::value("unsubscribed"))
+        .filter(subscriptions::Column::Id.eq(subscriber_id))
+        .exec(&transaction)
+        .await
+        .context("Failed to unsubscribe the subscriber")?;
+
+    SubscriptionLists::update_many()
+        .col_expr(
+            subscription_lists::Column::Status,
+            Expr::value("unsubscribed"),
+        )
+        .filter(subscription_lists::Column::SubscriberId.eq(subscriber_id))
+        .exec(&transaction)
+        .await
+        .context("Failed to unsubscribe the subscriber from their lists")?;
+
+    // Confirmation links still in a mailbox would otherwise subscribe them again
+    SubscriptionTokens::delete_many()
+        .filter(subscription_tokens::Column::SubscriberId.eq(subscriber_id))
+        .exec(&transaction)
+        .await
+        .context("Failed to delete the subscription tokens of the subscriber")?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the Postgres transaction")?;
+
+    Ok(Html("You have been unsubscribed from all lists.").into_response())
+}
+
+pub(super) async fn authorize(
+    db_connection: &DatabaseConnection,
+    tenant_id: Uuid,
+    preference_token: &str,
+) -> Result<Uuid, PreferencesError> {
+    get_subscriber_id_from_preference_token(db_connection, tenant_id, preference_token)
+        .await
+        .context("Failed to fetch subscriber ID from the database")?
+        .ok_or_else(|| {
+            PreferencesError::UnknownTokenError(format!(
+                "Unauthorized preference token detected: {}",
+                preference_token
+            ))
+        })
+}
+
+#[tracing::instrument(
+    name = "Get subscriber_id from preference token",
+    skip(db_connection, preference_token)
+)]
+pub async fn get_subscriber_id_from_preference_token(
+    db_connection: &DatabaseConnection,
+    tenant_id: Uuid,
+    preference_token: &str,
+) -> Result<Option<Uuid>, DbErr> {
+    // Tokens of other tenants are as unknown as made up ones
+    let result = PreferenceTokens::find_by_id(preference_token)
+        .inner_join(Subscriptions)
+        .filter(subscriptions::Column::TenantId.eq(tenant_id))
+        .one(db_connection)
+        .await?;
+
+    Ok(result.map(|data| data.subscriber_id))
+}
+
+#[tracing::instrument(name = "Get or create a preference token", skip(db_connection))]
+pub async fn get_or_create_preference_token(
+    db_connection: &impl ConnectionTrait,
+    subscriber_id: Uuid,
+) -> Result<String, DbErr> {
+    // Every subscriber owns a single long-lived token, created the first time it is needed
+    preference_tokens::Entity::insert(preference_tokens::ActiveModel {
+        preference_token: Set(generate_subscription_token()),
+        subscriber_id: Set(subscriber_id),
+    })
+    .on_conflict(
+        OnConflict::column(preference_tokens::Column::SubscriberId)
+            .do_nothing()
+            .to_owned(),
+    )
+    .do_nothing()
+    .exec(db_connection)
+    .await?;
+
+    let token = PreferenceTokens::find()
+        .filter(preference_tokens::Column::SubscriberId.eq(subscriber_id))
+        .one(db_connection)
+        .await?
+        .ok_or_else(|| DbErr::RecordNotFound("preference token".into()))?;
+
+    Ok(token.preference_token)
+}
+
+/// The status of a subscriber, following from the status of their list memberships.
+#[tracing::instrument(name = "Get subscriber status", skip(transaction))]
+async fn subscriber_status(
+    transaction: &DatabaseTransaction,
+    subscriber_id: Uuid,
+) -> Result<&'static str, DbErr> {
+    let statuses: Vec<String> = SubscriptionLists::find()
+        .select_only()
+        .column(subscription_lists::Column::Status)
+        .filter(subscription_lists::Column::SubscriberId.eq(subscriber_id))
+        .into_tuple()
+        .all(transaction)
+        .await?;
+
+    let status = if statuses.iter().any(|status| status == "confirmed") {
+        "confirmed"
+    } else if statuses
+        .iter()
+        .any(|status| status == "pending_confirmation")
+    {
+        "pending_confirmation"
+    } else {
+        "unsubscribed"
+    };
+
+    Ok(status)
+}
+
+#[tracing::instrument(name = "Set list membership status", skip(transaction))]
+async fn set_membership_status(
+    transaction: &DatabaseTransaction,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+    status: &str,
+) -> Result<(), DbErr> {
+    // Lists the subscriber has never joined do not need an `unsubscribed` row
+    if status == "unsubscribed" {
+        SubscriptionLists::update_many()
+            .col_expr(subscription_lists::Column::Status, Expr::value(status))
+            .filter(subscription_lists::Column::SubscriberId.eq(subscriber_id))
+            .filter(subscription_lists::Column::ListId.eq(list_id))
+            .exec(transaction)
+            .await?;
+        // Confirmation links of a left list must not join it again
+        SubscriptionTokens::delete_many()
+            .filter(subscription_tokens::Column::SubscriberId.eq(subscriber_id))
+            .filter(subscription_tokens::Column::ListId.eq(list_id))
+            .exec(transaction)
+            .await?;
+
+        return Ok(());
+    }
+
+    let membership = subscription_lists::ActiveModel {
+        subscriber_id: Set(subscriber_id),
+        list_id: Set(list_id),
+        status: Set(status.to_string()),
+        subscribed_at: Set(DateTimeWithTimeZone::from(Utc::now())),
+    };
+
+    SubscriptionLists::insert(membership)
+        .on_conflict(
+            OnConflict::columns([
+                subscription_lists::Column::SubscriberId,
+                subscription_lists::Column::ListId,
+            ])
+            .update_column(subscription_lists::Column::Status)
+            .to_owned(),
+        )
+        .exec(transaction)
+        .await?;
+
+    Ok(())
+}
@@ -1,33 +1,59 @@
 use std::iter::repeat_with;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Context;
-use axum::Form;
-use axum::extract::FromRequest;
-use axum::extract::State;
-use axum::extract::rejection::FormRejection;
-use axum::http::StatusCode;
+use axum::extract::rejection::{FormRejection, JsonRejection};
+use axum::extract::{ConnectInfo, FromRequest, Request, State};
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
 use axum::response::{IntoResponse, Response};
+use axum::{Form, Json};
 use chrono::Utc;
-use entity::{subscription_tokens, subscriptions};
+use entity::prelude::{Lists, Subscriptions};
+use entity::{lists, subscriber_tags, subscription_lists, subscription_tokens, subscriptions};
+use migration::DEFAULT_LIST_NAME;
 use rand::distributions::Alphanumeric;
 use rand::{Rng, thread_rng};
 use sea_orm::ActiveValue::Set;
 use sea_orm::prelude::DateTimeWithTimeZone;
-use sea_orm::{ActiveModelTrait, DatabaseTransaction, DbErr, TransactionTrait};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseTransaction, DbErr, EntityTrait,
+    QueryFilter, TransactionTrait,
+};
+use serde::de::DeserializeOwned;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
-use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
-use crate::email_client::EmailClient;
-use crate::routes::error_chain_fmt;
+use crate::bot_protection::{BotCheckError, FormSubmission};
+use crate::domain::{
+    EmailPolicyViolation, NewSubscriber, SubscriberEmail, SubscriberName, SubscriberTag,
+};
+use crate::email_client::{EmailClient, SendEmailError};
+use crate::problem::{FieldError, FieldErrors, PROBLEM_JSON, Problem};
+use crate::routes::{error_chain_fmt, get_or_create_preference_token};
 use crate::startup::ApplicationState;
+use crate::tenant::Tenant;
 
 #[derive(thiserror::Error)]
 pub enum SubscribeError {
     #[error(transparent)]
     FormRejection(#[from] FormRejection),
+    #[error(transparent)]
+    JsonRejection(#[from] JsonRejection),
+    #[error("Invalid fields: {0:?}")]
+    InvalidFields(Vec<FieldError>),
     #[error("{0}")]
-    ValidationError(String),
+    EmailPolicyError(EmailPolicyViolation),
+    #[error("Did you mean {0}?")]
+    LikelyTypo(String),
+    #[error(transparent)]
+    UndeliverableEmail(SendEmailError),
+    #[error("Too many subscription requests, retry after {0:?}")]
+    RateLimitError(Duration),
+    #[error("The signup looks automated")]
+    BotError(#[source] BotCheckError),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -42,46 +68,209 @@ impl IntoResponse for SubscribeError {
     fn into_response(self) -> Response {
         tracing::error!("{:?}", self);
 
-        match self {
-            SubscribeError::FormRejection(_) | SubscribeError::ValidationError(_) => {
-                StatusCode::BAD_REQUEST.into_response()
+        let status = match self {
+            SubscribeError::InvalidFields(errors) => {
+                return Problem::invalid_fields(errors).into_response();
+            }
+            SubscribeError::FormRejection(_)
+            | SubscribeError::JsonRejection(_)
+            | SubscribeError::EmailPolicyError(_)
+            | SubscribeError::UndeliverableEmail(_)
+            | SubscribeError::BotError(BotCheckError::Rejected(_)) => StatusCode::BAD_REQUEST,
+            SubscribeError::LikelyTypo(_) => {
+                return Problem::from_error(StatusCode::CONFLICT, &self)
+                    .with_type("/problems/likely-typo", "The email address looks mistyped")
+                    .into_response();
             }
-            SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            SubscribeError::RateLimitError(_) => StatusCode::TOO_MANY_REQUESTS,
+            SubscribeError::BotError(BotCheckError::UnexpectedError(_))
+            | SubscribeError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        let mut problem = Problem::from_error(status, &self);
+
+        if let SubscribeError::RateLimitError(retry_after) = self {
+            // Round up, so clients never retry before a token is available
+            let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+            problem = problem.with_header(header::RETRY_AFTER, HeaderValue::from(seconds));
         }
+
+        problem.into_response()
     }
 }
 
-#[derive(FromRequest)]
-#[from_request(via(Form), rejection(SubscribeError))]
-pub struct SubscriberForm<T>(T);
+/// Accepts a JSON body for `application/json` and `+json` media types, form data otherwise.
+pub struct SubscriberPayload<T>(pub T);
 
-#[derive(serde::Deserialize)]
+impl<S, T> FromRequest<S> for SubscriberPayload<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = SubscribeError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let essence = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .map(|essence| essence.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+
+        if essence == "application/json" || essence.ends_with("+json") {
+            let Json(data) = Json::<T>::from_request(req, state).await?;
+            Ok(Self(data))
+        } else {
+            let Form(data) = Form::<T>::from_request(req, state).await?;
+            Ok(Self(data))
+        }
+    }
+}
+
+#[derive(serde::Deserialize, ToSchema)]
 pub struct SubscriberInfo {
     name: String,
     email: String,
+    /// Name of the mailing list to subscribe to, the default list is used if omitted
+    list: Option<String>,
+    /// Comma separated tags, usually set through a hidden field of the signup form
+    tags: Option<String>,
+    /// Keeps the email as typed, after the API suggested a correction of its domain
+    #[serde(default)]
+    accept_email_as_typed: bool,
+    /// Honeypot field, hidden from people by the signup form
+    website: Option<String>,
+    /// Token from `GET /subscriptions/form-token`, fetched when the signup form was rendered
+    form_token: Option<String>,
+    /// Token of the CAPTCHA widget, when CAPTCHA verification is enabled
+    #[serde(
+        alias = "g-recaptcha-response",
+        alias = "h-captcha-response",
+        alias = "cf-turnstile-response"
+    )]
+    captcha_response: Option<String>,
 }
 
 impl TryFrom<SubscriberInfo> for NewSubscriber {
-    type Error = String;
+    type Error = Vec<FieldError>;
 
     fn try_from(value: SubscriberInfo) -> Result<Self, Self::Error> {
-        let email = SubscriberEmail::parse(value.email)?;
-        let name = SubscriberName::parse(value.name)?;
+        let mut errors = FieldErrors::default();
+        let email = errors.check("email", SubscriberEmail::parse(value.email));
+        let name = errors.check("name", SubscriberName::parse(value.name));
+        let tags = errors.check(
+            "tags",
+            SubscriberTag::parse_list(value.tags.as_deref().unwrap_or_default()),
+        );
 
-        Ok(Self { email, name })
+        match (email, name, tags) {
+            (Some(email), Some(name), Some(tags)) => Ok(Self { email, name, tags }),
+            _ => Err(errors.into_vec()),
+        }
     }
 }
 
+#[derive(serde::Serialize, ToSchema)]
+pub struct FormToken {
+    /// To send back in the `form_token` field of the signup
+    form_token: String,
+}
+
+/// Hands out the signed start time of a signup form, to be fetched when the form is rendered.
+#[utoipa::path(
+    get,
+    path = "/subscriptions/form-token",
+    tag = "subscriptions",
+    responses((status = 200, body = FormToken)),
+)]
+#[tracing::instrument(name = "Issuing a signup form token", skip(state))]
+pub async fn form_token(State(state): State<Arc<ApplicationState>>) -> Response {
+    let form_token = FormToken {
+        form_token: state.bot_protection.issue_form_token(),
+    };
+
+    // Each rendering of the form must get a token of its own
+    ([(header::CACHE_CONTROL, "no-store")], Json(form_token)).into_response()
+}
+
+/// Subscribes someone to a mailing list and sends them a confirmation email.
+#[utoipa::path(
+    post,
+    path = "/subscriptions",
+    tag = "subscriptions",
+    request_body(content(
+        (SubscriberInfo = "application/x-www-form-urlencoded"),
+        (SubscriberInfo = "application/json"),
+    )),
+    responses(
+        (status = 200, description = "The confirmation email was sent"),
+        (status = 400, description = "Invalid subscriber details, or the signup looks automated",
+            body = Problem, content_type = PROBLEM_JSON),
+        (status = 409, description = "The email domain looks mistyped, the detail suggests a \
+            correction. Resubmit with `accept_email_as_typed` to keep the address as it is",
+            body = Problem, content_type = PROBLEM_JSON),
+        (status = 429, description = "Too many signups from this client or for this address",
+            body = Problem, content_type = PROBLEM_JSON,
+            headers(("Retry-After" = u64, description = "Seconds to wait before retrying"))),
+        (status = 500, body = Problem, content_type = PROBLEM_JSON),
+    )
+)]
 #[tracing::instrument(
     name = "Adding a new subscriber",
-    skip(state, form),
-    fields(subscriber_email = %form.email, subscriber_name = %form.name)
+    skip(state, tenant, headers, form),
+    fields(
+        tenant = %tenant.slug,
+        subscriber_email = %form.email,
+        subscriber_name = %form.name
+    )
 )]
 pub async fn subscribe(
     State(state): State<Arc<ApplicationState>>,
-    SubscriberForm(form): SubscriberForm<SubscriberInfo>,
+    tenant: Tenant,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    SubscriberPayload(form): SubscriberPayload<SubscriberInfo>,
 ) -> Result<Response, SubscribeError> {
-    let new_subscriber = form.try_into().map_err(SubscribeError::ValidationError)?;
+    let rate_limiter = &state.subscription_rate_limiter;
+    let client_ip = rate_limiter.client_ip(peer.ip(), &headers);
+    rate_limiter
+        .check_client(client_ip)
+        .map_err(SubscribeError::RateLimitError)?;
+
+    let submission = FormSubmission {
+        honeypot: form.website.as_deref(),
+        form_token: form.form_token.as_deref(),
+        captcha_response: form.captcha_response.as_deref(),
+    };
+    state
+        .bot_protection
+        .check(&submission, client_ip)
+        .await
+        .map_err(SubscribeError::BotError)?;
+
+    let list_name = form
+        .list
+        .clone()
+        .unwrap_or_else(|| DEFAULT_LIST_NAME.to_string());
+    let accept_email_as_typed = form.accept_email_as_typed;
+    let new_subscriber: NewSubscriber = form.try_into().map_err(SubscribeError::InvalidFields)?;
+
+    state
+        .email_policy
+        .check(&new_subscriber.email)
+        .map_err(SubscribeError::EmailPolicyError)?;
+    if !accept_email_as_typed
+        && let Some(suggestion) = state.email_policy.suggest_correction(&new_subscriber.email)
+    {
+        return Err(SubscribeError::LikelyTypo(suggestion));
+    }
+    tenant
+        .email_client
+        .check_recipient(&new_subscriber.email)
+        .map_err(SubscribeError::UndeliverableEmail)?;
+    rate_limiter
+        .check_email(tenant.id, &new_subscriber.email)
+        .map_err(SubscribeError::RateLimitError)?;
 
     let transaction = state
         .db_connection
@@ -89,16 +278,50 @@ pub async fn subscribe(
         .await
         .context("Failed to begin a Postgres transaction")?;
 
-    let subscriber_id = insert_subscriber(&transaction, &new_subscriber)
+    let list_id = get_list_id(&transaction, tenant.id, &list_name)
+        .await
+        .context("Failed to fetch the mailing list from the database")?
+        .ok_or_else(|| {
+            SubscribeError::InvalidFields(vec![FieldError::new(
+                "list",
+                format!("{} is not a known mailing list", list_name),
+            )])
+        })?;
+
+    let existing_subscriber_id =
+        get_subscriber_id_by_email(&transaction, tenant.id, &new_subscriber.email)
+            .await
+            .context("Failed to look up the subscriber in the database")?;
+    let subscriber_id = match existing_subscriber_id {
+        Some(subscriber_id) => subscriber_id,
+        None => {
+            let subscriber_id = insert_subscriber(&transaction, tenant.id, &new_subscriber)
+                .await
+                .context("Failed to insert new subscriber in the database")?;
+            // Anyone can sign up any address, so tags only describe subscribers this request
+            // created, the segments of an existing subscriber are not the signup form's to change
+            add_subscriber_tags(&transaction, subscriber_id, &new_subscriber.tags)
+                .await
+                .context("Failed to store the subscriber tags")?;
+            subscriber_id
+        }
+    };
+
+    add_subscriber_to_list(&transaction, subscriber_id, list_id)
         .await
-        .context("Failed to insert new subscriber in the database")?;
+        .context("Failed to add the subscriber to the mailing list")?;
 
     let subscription_token = generate_subscription_token();
 
-    store_token(&transaction, subscriber_id, &subscription_token)
+    store_token(&transaction, subscriber_id, list_id, &subscription_token)
         .await
         .context("Failed to store subscription token in the database")?;
 
+    // Pending subscribers can already manage, export or erase their data
+    let preference_token = get_or_create_preference_token(&transaction, subscriber_id)
+        .await
+        .context("Failed to get the subscriber's preference token")?;
+
     transaction
         .commit()
         .await
@@ -106,31 +329,67 @@ pub async fn subscribe(
 
     // Send confirmation email to the new subscriber
     send_confirmation_email(
-        &state.email_client,
-        new_subscriber,
-        &state.base_url,
+        &tenant.email_client,
+        &new_subscriber.email,
+        &tenant.base_url,
         &subscription_token,
+        &preference_token,
     )
     .await
     .context("Failed to send a confirmation email")?;
+    state.metrics.signups.inc();
 
     Ok(StatusCode::OK.into_response())
 }
 
+#[tracing::instrument(name = "Get mailing list id by name", skip(db_connection))]
+pub async fn get_list_id(
+    db_connection: &impl ConnectionTrait,
+    tenant_id: Uuid,
+    list_name: &str,
+) -> Result<Option<Uuid>, DbErr> {
+    let list = Lists::find()
+        .filter(lists::Column::TenantId.eq(tenant_id))
+        .filter(lists::Column::Name.eq(list_name))
+        .one(db_connection)
+        .await?;
+
+    Ok(list.map(|list| list.id))
+}
+
+#[tracing::instrument(name = "Get subscriber id by email", skip(transaction, email))]
+pub async fn get_subscriber_id_by_email(
+    transaction: &DatabaseTransaction,
+    tenant_id: Uuid,
+    email: &SubscriberEmail,
+) -> Result<Option<Uuid>, DbErr> {
+    let subscription = Subscriptions::find()
+        .filter(subscriptions::Column::TenantId.eq(tenant_id))
+        .filter(subscriptions::Column::NormalizedEmail.eq(email.normalized()))
+        .one(transaction)
+        .await?;
+
+    Ok(subscription.map(|subscription| subscription.id))
+}
+
 #[tracing::instrument(
     name = "Saving new subscriber details in the database",
     skip(new_subscriber, transaction)
 )]
 pub async fn insert_subscriber(
     transaction: &DatabaseTransaction,
+    tenant_id: Uuid,
     new_subscriber: &NewSubscriber,
 ) -> Result<Uuid, DbErr> {
     let subscription = subscriptions::ActiveModel {
         id: Set(Uuid::new_v4()),
         email: Set(new_subscriber.email.as_ref().to_string()),
+        ascii_email: Set(new_subscriber.email.ascii_compatible().to_string()),
+        normalized_email: Set(new_subscriber.email.normalized().to_string()),
         name: Set(new_subscriber.name.as_ref().to_string()),
         subscribed_at: Set(DateTimeWithTimeZone::from(Utc::now())),
         status: Default::default(),
+        tenant_id: Set(tenant_id),
     }
     .insert(transaction)
     .await?;
@@ -138,6 +397,67 @@ pub async fn insert_subscriber(
     Ok(subscription.id)
 }
 
+#[tracing::instrument(name = "Add subscriber to a mailing list", skip(transaction))]
+pub async fn add_subscriber_to_list(
+    transaction: &DatabaseTransaction,
+    subscriber_id: Uuid,
+    list_id: Uuid,
+) -> Result<(), DbErr> {
+    let membership = subscription_lists::ActiveModel {
+        subscriber_id: Set(subscriber_id),
+        list_id: Set(list_id),
+        status: Default::default(),
+        subscribed_at: Set(DateTimeWithTimeZone::from(Utc::now())),
+    };
+
+    // Subscribing twice to the same list only issues a new confirmation token
+    subscription_lists::Entity::insert(membership)
+        .on_conflict(
+            OnConflict::columns([
+                subscription_lists::Column::SubscriberId,
+                subscription_lists::Column::ListId,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .do_nothing()
+        .exec(transaction)
+        .await?;
+
+    Ok(())
+}
+
+#[tracing::instrument(name = "Add tags to a subscriber", skip(transaction))]
+pub async fn add_subscriber_tags(
+    transaction: &DatabaseTransaction,
+    subscriber_id: Uuid,
+    tags: &[SubscriberTag],
+) -> Result<(), DbErr> {
+    if tags.is_empty() {
+        return Ok(());
+    }
+
+    let tags = tags.iter().map(|tag| subscriber_tags::ActiveModel {
+        subscriber_id: Set(subscriber_id),
+        tag: Set(tag.as_ref().to_string()),
+    });
+
+    subscriber_tags::Entity::insert_many(tags)
+        .on_conflict(
+            OnConflict::columns([
+                subscriber_tags::Column::SubscriberId,
+                subscriber_tags::Column::Tag,
+            ])
+            .do_nothing()
+            .to_owned(),
+        )
+        .do_nothing()
+        .exec(transaction)
+        .await?;
+
+    Ok(())
+}
+
 #[tracing::instrument(
     name = "Store subscription token in the database",
     skip(transaction, subscription_token)
@@ -145,11 +465,13 @@ pub async fn insert_subscriber(
 pub async fn store_token(
     transaction: &DatabaseTransaction,
     subscriber_id: Uuid,
+    list_id: Uuid,
     subscription_token: &str,
 ) -> Result<(), DbErr> {
     subscription_tokens::ActiveModel {
         subscriber_id: Set(subscriber_id),
         subscription_token: Set(subscription_token.to_string()),
+        list_id: Set(list_id),
     }
     .insert(transaction)
     .await?;
@@ -159,39 +481,47 @@ pub async fn store_token(
 
 #[tracing::instrument(
     name = "Send a confirmation email to the new subscriber",
-    skip(email_client, new_subscriber, base_url, subscription_token)
+    skip(
+        email_client,
+        recipient,
+        base_url,
+        subscription_token,
+        preference_token
+    )
 )]
 pub async fn send_confirmation_email(
     email_client: &EmailClient,
-    new_subscriber: NewSubscriber,
+    recipient: &SubscriberEmail,
     base_url: &str,
     subscription_token: &str,
-) -> Result<(), reqwest::Error> {
+    preference_token: &str,
+) -> Result<(), SendEmailError> {
     let confirmation_link = format!(
         "{}/subscriptions/confirm?subscription_token={}",
         base_url, subscription_token
     );
+    let preferences_link = format!(
+        "{}/preferences?preference_token={}",
+        base_url, preference_token
+    );
 
     let plain_text_body = format!(
-        "Welcome to our newsletter!\nVisit {} to confirm your subscriptions.",
-        confirmation_link
+        "Welcome to our newsletter!\nVisit {} to confirm your subscriptions.\n\
+        Manage your subscriptions or your personal data at {}",
+        confirmation_link, preferences_link
     );
     let html_body = format!(
-        "Welcome to our newsletter!<br />Click <a href=\"{}\">here</a> to confirm your subscription.",
-        confirmation_link
+        "Welcome to our newsletter!<br />Click <a href=\"{}\">here</a> to confirm your subscription.<br />\
+        <a href=\"{}\">Manage your subscriptions or your personal data</a>",
+        confirmation_link, preferences_link
     );
 
     email_client
-        .send_email(
-            &new_subscriber.email,
-            "Welcome!",
-            &html_body,
-            &plain_text_body,
-        )
+        .send_email(recipient, "Welcome!", &html_body, &plain_text_body)
         .await
 }
 
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
 
     repeat_with(|| rng.sample(Alphanumeric))
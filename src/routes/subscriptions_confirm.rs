@@ -5,14 +5,21 @@ use axum::extract::rejection::QueryRejection;
 use axum::extract::{Query, State};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
-use entity::prelude::{SubscriptionTokens, Subscriptions};
+use entity::prelude::{SubscriptionLists, SubscriptionTokens, Subscriptions};
+use entity::{subscription_lists, subscriptions};
 use sea_orm::ActiveValue::Set;
-use sea_orm::{ActiveModelTrait, DatabaseConnection, DbErr, EntityTrait, IntoActiveModel};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, IntoActiveModel,
+    QueryFilter, TransactionTrait,
+};
 use serde::Deserialize;
+use utoipa::IntoParams;
 use uuid::Uuid;
 
+use crate::problem::{PROBLEM_JSON, Problem};
 use crate::routes::error_chain_fmt;
 use crate::startup::ApplicationState;
+use crate::tenant::Tenant;
 
 #[derive(thiserror::Error)]
 pub enum ConfirmationError {
@@ -34,34 +41,56 @@ impl IntoResponse for ConfirmationError {
     fn into_response(self) -> Response {
         tracing::error!("{:?}", self);
 
-        match self {
-            ConfirmationError::QueryRejection(_) => StatusCode::BAD_REQUEST.into_response(),
-            ConfirmationError::IdNotFoundError(_) => StatusCode::UNAUTHORIZED.into_response(),
-            ConfirmationError::UnexpectedError(_) => {
-                StatusCode::INTERNAL_SERVER_ERROR.into_response()
-            }
-        }
+        let status = match self {
+            ConfirmationError::QueryRejection(_) => StatusCode::BAD_REQUEST,
+            ConfirmationError::IdNotFoundError(_) => StatusCode::UNAUTHORIZED,
+            ConfirmationError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Problem::from_error(status, &self).into_response()
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct Parameters {
+    /// Token from the link of the confirmation email
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(state, parameters))]
+/// Confirms the subscription a confirmation email was sent for.
+#[utoipa::path(
+    get,
+    path = "/subscriptions/confirm",
+    tag = "subscriptions",
+    params(Parameters),
+    responses(
+        (status = 200, description = "The subscription is confirmed"),
+        (status = 400, body = Problem, content_type = PROBLEM_JSON),
+        (status = 401, description = "Unknown subscription token",
+            body = Problem, content_type = PROBLEM_JSON),
+        (status = 500, body = Problem, content_type = PROBLEM_JSON),
+    )
+)]
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(state, tenant, parameters),
+    fields(tenant = %tenant.slug)
+)]
 pub async fn confirm(
     State(state): State<Arc<ApplicationState>>,
+    tenant: Tenant,
     Query(parameters): Query<Parameters>,
 ) -> Result<Response, ConfirmationError> {
-    if let Some(subscriber_id) =
-        get_subscriber_id_from_token(&state.db_connection, &parameters.subscription_token)
-            .await
-            .context("Failed to fetch subscriber ID from the database")?
+    if let Some((subscriber_id, list_id)) = get_subscriber_id_from_token(
+        &state.db_connection,
+        tenant.id,
+        &parameters.subscription_token,
+    )
+    .await
+    .context("Failed to fetch subscriber ID from the database")?
     {
-        confirm_subscriber(&state.db_connection, subscriber_id)
-            .await
-            .context("Failed to complete subscriber confirmation")?
+        confirm_subscriber(&state.db_connection, subscriber_id, list_id).await?;
+        state.metrics.confirmations.inc();
     } else {
         return Err(ConfirmationError::IdNotFoundError(format!(
             "Unauthorized token detected: {}",
@@ -78,32 +107,70 @@ pub async fn confirm(
 )]
 pub async fn get_subscriber_id_from_token(
     db_connection: &DatabaseConnection,
+    tenant_id: Uuid,
     subscription_token: &str,
-) -> Result<Option<Uuid>, DbErr> {
+) -> Result<Option<(Uuid, Uuid)>, DbErr> {
+    // Tokens of other tenants are as unknown as made up ones
     let result = SubscriptionTokens::find_by_id(subscription_token)
+        .inner_join(Subscriptions)
+        .filter(subscriptions::Column::TenantId.eq(tenant_id))
         .one(db_connection)
         .await?;
 
-    Ok(result.map(|data| data.subscriber_id))
+    Ok(result.map(|data| (data.subscriber_id, data.list_id)))
 }
 
 #[tracing::instrument(
     name = "Mark subscriber as confirmed",
-    skip(db_connection, subscriber_id)
+    skip(db_connection, subscriber_id, list_id)
 )]
 pub async fn confirm_subscriber(
     db_connection: &DatabaseConnection,
     subscriber_id: Uuid,
-) -> Result<(), DbErr> {
+    list_id: Uuid,
+) -> Result<(), ConfirmationError> {
+    let transaction = db_connection
+        .begin()
+        .await
+        .context("Failed to acquire a database connection")?;
+
+    // The subscriber may have left the list since the confirmation email was sent
+    let mut membership = SubscriptionLists::find_by_id((subscriber_id, list_id))
+        .filter(subscription_lists::Column::Status.ne("unsubscribed"))
+        .one(&transaction)
+        .await
+        .context("Failed to fetch the list membership")?
+        .ok_or_else(|| {
+            ConfirmationError::IdNotFoundError(
+                "The subscription of this token no longer exists".into(),
+            )
+        })?
+        .into_active_model();
+
+    membership.status = Set("confirmed".to_owned());
+    membership
+        .update(&transaction)
+        .await
+        .context("Failed to confirm the list membership")?;
+
+    // A subscriber counts as confirmed once any of their lists has been confirmed
     let mut subscription = Subscriptions::find_by_id(subscriber_id)
-        .one(db_connection)
-        .await?
-        .unwrap()
+        .one(&transaction)
+        .await
+        .context("Failed to fetch the subscriber")?
+        .context("The subscriber of a list membership does not exist")?
         .into_active_model();
 
     subscription.status = Set("confirmed".to_owned());
+    subscription
+        .update(&transaction)
+        .await
+        .context("Failed to confirm the subscriber")?;
 
-    subscription.update(db_connection).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit the confirmation")?;
 
     Ok(())
 }
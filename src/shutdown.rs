@@ -0,0 +1,83 @@
+//! Graceful shutdown: stop accepting connections, drain in-flight requests and background
+//! tasks, then let the process exit.
+
+use std::future::Future;
+
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// Shared by the server and its background tasks. Cloning it yields a handle on the same
+/// shutdown, so tests can trigger it as SIGTERM would.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    tasks: TaskTracker,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts the shutdown, as SIGTERM or SIGINT do.
+    pub fn trigger(&self) {
+        self.token.cancel();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Resolves once the shutdown has been triggered.
+    pub async fn triggered(&self) {
+        self.token.cancelled().await
+    }
+
+    /// Spawns a task the shutdown waits for, up to the drain timeout.
+    /// Long-running tasks should stop at [`Shutdown::triggered`].
+    pub fn spawn<F>(&self, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.tasks.spawn(task)
+    }
+
+    /// Resolves once the shutdown is triggered, either through [`Shutdown::trigger`] or by
+    /// a signal from the OS.
+    pub(crate) async fn requested(&self) {
+        tokio::select! {
+            _ = self.triggered() => {}
+            signal = os_signal() => {
+                tracing::info!("Received {}, shutting down", signal);
+                self.trigger();
+            }
+        }
+    }
+
+    /// Resolves once every task spawned through [`Shutdown::spawn`] has completed.
+    pub(crate) async fn tasks_completed(&self) {
+        self.tasks.close();
+        self.tasks.wait().await
+    }
+}
+
+#[cfg(unix)]
+async fn os_signal() -> &'static str {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+    tokio::select! {
+        _ = sigterm.recv() => "SIGTERM",
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+    }
+}
+
+#[cfg(not(unix))]
+async fn os_signal() -> &'static str {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to listen for Ctrl-C");
+    "Ctrl-C"
+}
@@ -1,42 +1,74 @@
+use std::future::{Future, IntoFuture};
+use std::net::SocketAddr;
+use std::pin::pin;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::Router;
 use axum::body::Body;
-use axum::extract::Request;
+use axum::extract::connect_info::IntoMakeServiceWithConnectInfo;
+use axum::extract::{ConnectInfo, Request};
+use axum::middleware::AddExtension;
 use axum::routing::{get, post};
 use axum::serve::Serve;
-use sea_orm::sqlx::postgres::PgPoolOptions;
-use sea_orm::{DatabaseConnection, SqlxPostgresConnector};
+use sea_orm::{DatabaseConnection, DbErr, SqlxPostgresConnector, SqlxSqliteConnector};
 use tokio::net::TcpListener;
+use tokio::time::Instant;
 use tower_http::trace::TraceLayer;
 use tower_request_id::{RequestId, RequestIdLayer};
+use utoipa::openapi::OpenApi;
 
-use crate::configuration::{DatabaseSettings, Settings};
+use crate::bot_protection::BotProtection;
+use crate::configuration::{
+    Backend, DatabaseSettings, EmailClientSettings, HealthSettings, Settings,
+};
+use crate::domain::EmailPolicy;
 use crate::email_client::EmailClient;
-use crate::routes::{confirm, health_check, publish_newsletters, subscribe};
+use crate::metrics::{Metrics, track_http_metrics};
+use crate::migrations::run_migrations;
+use crate::problem::problem_instance;
+use crate::rate_limit::SubscriptionRateLimiter;
+use crate::replica::ReadReplica;
+use crate::routes::{
+    api_routes, delete_subscriber, erase_personal_data, export_personal_data, export_subscribers,
+    get_metrics, get_subscriber, health_check, health_live, health_ready, import_subscribers,
+    list_audit_events, list_subscribers, openapi_json, preferences_page, unsubscribe,
+    update_preferences, update_subscriber,
+};
+use crate::shutdown::Shutdown;
+use crate::telemetry::set_remote_parent;
+use crate::tenant::strip_tenant_prefix;
+
+type AppServe = Serve<
+    TcpListener,
+    IntoMakeServiceWithConnectInfo<Router, SocketAddr>,
+    AddExtension<Router, ConnectInfo<SocketAddr>>,
+>;
 
 pub struct Application {
-    serve: Serve<TcpListener, Router, Router>,
+    serve: AppServe,
     port: u16,
+    shutdown: Shutdown,
+    drain_timeout: Duration,
 }
 
 impl Application {
     pub async fn build(configuration: Settings) -> Result<Self, std::io::Error> {
         // Database
         let db_connection = get_database_connection(&configuration.database);
+        let replica = configuration
+            .database
+            .replica()
+            .filter(|settings| settings.backend == Backend::Postgres)
+            .map(|settings| ReadReplica::new(get_replica_connection(&settings)));
+        if configuration.application.run_migrations_on_startup {
+            run_migrations(&db_connection)
+                .await
+                .map_err(std::io::Error::other)?;
+        }
 
         // Email client
-        let sender_email = configuration
-            .email_client
-            .sender()
-            .expect("Invalid sender email address");
-        let timeout = configuration.email_client.timeout();
-        let email_client = EmailClient::new(
-            configuration.email_client.base_url,
-            sender_email,
-            configuration.email_client.authorization_token,
-            timeout,
-        );
+        let email_client = get_email_client(configuration.email_client);
 
         // App
         let address = format!(
@@ -46,56 +78,214 @@ impl Application {
         let tcp_listener = TcpListener::bind(address).await?;
         let port = tcp_listener.local_addr()?.port();
 
+        let shutdown = Shutdown::new();
         let serve = run(
             tcp_listener,
             db_connection,
+            replica,
             email_client,
             configuration.application.base_url,
+            SubscriptionRateLimiter::new(&configuration.application.rate_limit),
+            BotProtection::new(&configuration.application.bot_protection),
+            EmailPolicy::new(&configuration.application.email_policy)?,
+            configuration.application.health,
+            shutdown.clone(),
         )
         .await?;
 
-        Ok(Self { serve, port })
+        Ok(Self {
+            serve,
+            port,
+            shutdown,
+            drain_timeout: configuration.application.shutdown.drain_timeout(),
+        })
     }
 
     pub fn port(&self) -> u16 {
         self.port
     }
 
+    /// A handle to stop the application, as SIGTERM or SIGINT do.
+    pub fn shutdown(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Serves requests until shutdown is requested, then drains in-flight requests and
+    /// background tasks for at most the drain timeout.
     pub async fn start_service(self) -> std::io::Result<()> {
-        self.serve.await
+        let Self {
+            serve,
+            shutdown,
+            drain_timeout,
+            ..
+        } = self;
+
+        let requested = shutdown.clone();
+        let server = serve.with_graceful_shutdown(async move { requested.requested().await });
+        let mut server = pin!(server.into_future());
+
+        let result = tokio::select! {
+            result = &mut server => return result,
+            _ = shutdown.triggered() => {
+                let deadline = Instant::now() + drain_timeout;
+                let result = match tokio::time::timeout_at(deadline, &mut server).await {
+                    Ok(result) => result,
+                    Err(_) => {
+                        tracing::warn!("Drain timeout elapsed, abandoning in-flight requests");
+                        Ok(())
+                    }
+                };
+                if tokio::time::timeout_at(deadline, shutdown.tasks_completed())
+                    .await
+                    .is_err()
+                {
+                    tracing::warn!("Drain timeout elapsed, abandoning background tasks");
+                }
+                result
+            }
+        };
+        tracing::info!("Shutdown complete");
+
+        result
     }
 }
 
 pub fn get_database_connection(settings: &DatabaseSettings) -> DatabaseConnection {
+    match settings.backend {
+        Backend::Postgres => SqlxPostgresConnector::from_sqlx_postgres_pool(
+            settings
+                .pool_options()
+                .connect_lazy_with(settings.with_db()),
+        ),
+        Backend::Sqlite => SqlxSqliteConnector::from_sqlx_sqlite_pool(
+            settings
+                .pool_options()
+                .connect_lazy_with(settings.sqlite_options()),
+        ),
+    }
+}
+
+/// Replica connections are named after the application, to tell them apart in
+/// `pg_stat_activity`.
+pub fn get_replica_connection(settings: &DatabaseSettings) -> DatabaseConnection {
     SqlxPostgresConnector::from_sqlx_postgres_pool(
-        PgPoolOptions::new().connect_lazy_with(settings.with_db()),
+        settings.pool_options().connect_lazy_with(
+            settings
+                .with_db()
+                .application_name(REPLICA_APPLICATION_NAME),
+        ),
+    )
+}
+
+pub fn get_email_client(settings: EmailClientSettings) -> EmailClient {
+    let sender_email = settings.sender().expect("Invalid sender email address");
+    let timeout = settings.timeout();
+
+    EmailClient::new(
+        settings.base_url,
+        sender_email,
+        settings.authorization_token,
+        timeout,
+        settings.supports_smtputf8,
     )
+    .with_accounts(settings.accounts)
 }
 
+pub const REPLICA_APPLICATION_NAME: &str = "zero2prod-axum-replica";
+
 pub struct ApplicationState {
     pub db_connection: DatabaseConnection,
+    pub replica: Option<ReadReplica>,
+    /// What tenants send with, unless they have a sender or token of their own
     pub email_client: EmailClient,
+    /// Base URL of the default tenant, the other tenants are served under it too
     pub base_url: String,
+    pub subscription_rate_limiter: SubscriptionRateLimiter,
+    pub bot_protection: BotProtection,
+    /// Shared by every tenant, the domains it rejects are a matter of the deployment's
+    /// sending reputation rather than of a publication
+    pub email_policy: EmailPolicy,
+    pub openapi: OpenApi,
+    pub metrics: Metrics,
+    pub health: HealthSettings,
+    /// Background tasks are spawned through it, so that shutdown waits for them
+    pub shutdown: Shutdown,
 }
 
+impl ApplicationState {
+    /// Runs a read-only query on the replica when there is one, on the primary otherwise.
+    pub async fn read_only<T, F, Fut>(&self, query: F) -> Result<T, DbErr>
+    where
+        F: Fn(DatabaseConnection) -> Fut,
+        Fut: Future<Output = Result<T, DbErr>>,
+    {
+        match &self.replica {
+            Some(replica) => replica.read(&self.db_connection, query).await,
+            None => query(self.db_connection.clone()).await,
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn run(
     tcp_listener: TcpListener,
     db_connection: DatabaseConnection,
+    replica: Option<ReadReplica>,
     email_client: EmailClient,
     base_url: String,
-) -> Result<Serve<TcpListener, Router, Router>, std::io::Error> {
+    subscription_rate_limiter: SubscriptionRateLimiter,
+    bot_protection: BotProtection,
+    email_policy: EmailPolicy,
+    health: HealthSettings,
+    shutdown: Shutdown,
+) -> Result<AppServe, std::io::Error> {
+    let (api_router, openapi) = api_routes().split_for_parts();
+    let metrics = Metrics::new(&db_connection, &email_client).map_err(std::io::Error::other)?;
     let application_state = Arc::new(ApplicationState {
         db_connection,
+        replica,
         email_client,
         base_url,
+        subscription_rate_limiter,
+        bot_protection,
+        email_policy,
+        openapi,
+        metrics,
+        health,
+        shutdown,
     });
 
     let app = Router::new()
         .route("/", get(root))
+        .merge(api_router)
+        .route("/admin/api/audit-events", get(list_audit_events))
+        .route("/admin/api/subscribers", get(list_subscribers))
+        .route("/admin/api/subscribers/export", get(export_subscribers))
+        .route("/admin/api/subscribers/import", post(import_subscribers))
+        .route(
+            "/admin/api/subscribers/{id}",
+            get(get_subscriber)
+                .patch(update_subscriber)
+                .delete(delete_subscriber),
+        )
+        .route("/api-docs/openapi.json", get(openapi_json))
+        .route("/health/live", get(health_live))
+        .route("/health/ready", get(health_ready))
         .route("/health_check", get(health_check))
-        .route("/newsletters", post(publish_newsletters))
-        .route("/subscriptions", post(subscribe))
-        .route("/subscriptions/confirm", get(confirm))
+        .route("/metrics", get(get_metrics))
+        .route(
+            "/preferences",
+            get(preferences_page).post(update_preferences),
+        )
+        .route("/preferences/data", get(export_personal_data))
+        .route("/preferences/erase", post(erase_personal_data))
+        .route("/preferences/unsubscribe", post(unsubscribe))
+        // Needs the request id, so it must sit inside the RequestId layer
+        .layer(axum::middleware::from_fn(problem_instance))
+        .layer(axum::middleware::from_fn_with_state(
+            application_state.clone(),
+            track_http_metrics,
+        ))
         .layer(
             TraceLayer::new_for_http().make_span_with(|request: &Request<Body>| {
                 // Get the request id from the extensions
@@ -106,12 +296,15 @@ pub async fn run(
                     .unwrap_or_else(|| "unknown".into());
 
                 // Put it along with other information into the `request` span
-                tracing::info_span!(
+                let span = tracing::info_span!(
                     "request",
                     id = %request_id,
                     method = %request.method(),
                     uri = %request.uri(),
-                )
+                );
+                // Continue the trace of the caller, if any
+                set_remote_parent(&span, request.headers());
+                span
             }),
         )
         // This layer creates a new id for each request and puts it into the request extensions.
@@ -119,7 +312,16 @@ pub async fn run(
         .layer(RequestIdLayer)
         .with_state(application_state);
 
-    Ok(axum::serve(tcp_listener, app))
+    // Layers of `app` only run once a route matched, the tenant prefix must go before that
+    let app = Router::new()
+        .fallback_service(app)
+        .layer(axum::middleware::from_fn(strip_tenant_prefix));
+
+    // Rate limiting needs the address of the peer
+    Ok(axum::serve(
+        tcp_listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    ))
 }
 
 pub async fn root() -> &'static str {
@@ -1,15 +1,28 @@
+use axum::http::HeaderMap;
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_http::{HeaderExtractor, HeaderInjector};
+use opentelemetry_otlp::{ExporterBuildError, Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tokio::task::JoinHandle;
 use tracing::Subscriber;
 use tracing::subscriber::set_global_default;
 use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::{EnvFilter, Registry};
 
+use crate::configuration::{OtlpProtocol, TelemetrySettings};
+
 pub fn get_subscriber<Sink>(
     name: String,
     env_filter: String,
     sink: Sink,
+    tracer_provider: &SdkTracerProvider,
 ) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
@@ -18,16 +31,131 @@ where
     // if the `RUST_LOG` environment variable has not been set
     let env_filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(env_filter));
+    // Spans get OpenTelemetry ids even when they are not exported, so that trace context
+    // is still propagated to the services we call
+    let otel_layer =
+        tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(name.clone()));
     let formatting_layer = BunyanFormattingLayer::new(name, sink);
 
     Registry::default()
         .with(env_filter)
+        .with(otel_layer)
         .with(JsonStorageLayer)
         .with(formatting_layer)
 }
 
+/// Builds the provider of OpenTelemetry tracers, which exports spans over OTLP/HTTP
+/// when `telemetry.otlp` is configured. Call `shutdown` on it before exiting to flush
+/// the spans that are still buffered.
+pub fn get_tracer_provider(
+    name: String,
+    settings: &TelemetrySettings,
+) -> Result<SdkTracerProvider, ExporterBuildError> {
+    let mut builder = SdkTracerProvider::builder()
+        .with_resource(Resource::builder().with_service_name(name).build());
+
+    if let Some(otlp) = &settings.otlp {
+        let protocol = match otlp.protocol {
+            OtlpProtocol::HttpProtobuf => Protocol::HttpBinary,
+            OtlpProtocol::HttpJson => Protocol::HttpJson,
+        };
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_protocol(protocol)
+            .with_endpoint(otlp.endpoint.clone())
+            .with_timeout(otlp.timeout())
+            .build()?;
+        builder = builder.with_batch_exporter(exporter);
+    }
+
+    Ok(builder.build())
+}
+
+/// Makes `span` a child of the W3C `traceparent` of an incoming request, if it has one.
+pub fn set_remote_parent(span: &tracing::Span, headers: &HeaderMap) {
+    let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+    // Fails only when the span is disabled, e.g. filtered out
+    let _ = span.set_parent(context);
+}
+
+/// Adds the W3C `traceparent` of the current span to the headers of an outgoing request.
+pub fn inject_trace_context(headers: &mut HeaderMap) {
+    let context = tracing::Span::current().context();
+    TraceContextPropagator::new().inject_context(&context, &mut HeaderInjector(headers));
+}
+
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     // Redirect all Log events into the subscriber
     LogTracer::init().expect("Failed to set logger");
     set_global_default(subscriber).expect("Failed to set subscriber");
 }
+
+/// Runs CPU-intensive work on the blocking thread pool without losing the current span.
+pub fn spawn_blocking_with_tracing<F, R>(f: F) -> JoinHandle<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let current_span = tracing::Span::current();
+    tokio::task::spawn_blocking(move || current_span.in_scope(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue};
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::configuration::{OtlpProtocol, OtlpSettings, TelemetrySettings};
+    use crate::telemetry::{get_subscriber, get_tracer_provider, set_remote_parent};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn spans_are_exported_to_the_collector_within_the_remote_trace() {
+        // Arrange
+        let collector = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/traces"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&collector)
+            .await;
+
+        let settings = TelemetrySettings {
+            otlp: Some(OtlpSettings {
+                endpoint: format!("{}/v1/traces", collector.uri()),
+                protocol: OtlpProtocol::HttpJson,
+                timeout_milliseconds: 1000,
+            }),
+        };
+        let tracer_provider = get_tracer_provider("test".into(), &settings).unwrap();
+        let subscriber = get_subscriber(
+            "test".into(),
+            "info".into(),
+            std::io::sink,
+            &tracer_provider,
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_str(&format!("00-{}-00f067aa0ba902b7-01", TRACE_ID)).unwrap(),
+        );
+
+        // Act
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            set_remote_parent(&span, &headers);
+            span.in_scope(|| tracing::info_span!("child").in_scope(|| {}));
+        });
+        tracer_provider.force_flush().unwrap();
+
+        // Assert
+        let requests = collector.received_requests().await.unwrap();
+        let body = requests
+            .iter()
+            .map(|request| String::from_utf8_lossy(&request.body).into_owned())
+            .collect::<String>();
+        assert!(body.contains(TRACE_ID), "{}", body);
+        assert!(body.contains("\"child\""), "{}", body);
+    }
+}
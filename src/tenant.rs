@@ -0,0 +1,226 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::extract::{FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::http::uri::{Authority, PathAndQuery};
+use axum::http::{StatusCode, Uri, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use entity::prelude::Tenants;
+use entity::tenants;
+use migration::DEFAULT_TENANT_ID;
+use sea_orm::{ColumnTrait, Condition, EntityTrait, QueryFilter};
+use uuid::Uuid;
+
+use crate::domain::SubscriberEmail;
+use crate::email_client::EmailClient;
+use crate::problem::Problem;
+use crate::routes::error_chain_fmt;
+use crate::startup::ApplicationState;
+
+/// Every route is also served under `/tenants/{slug}`, for tenants without a host of their own.
+pub const TENANT_PATH_PREFIX: &str = "/tenants";
+
+#[derive(thiserror::Error)]
+pub enum TenantError {
+    #[error("{0}")]
+    UnknownTenant(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TenantError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl IntoResponse for TenantError {
+    fn into_response(self) -> Response {
+        tracing::error!("{:?}", self);
+
+        let status = match self {
+            TenantError::UnknownTenant(_) => StatusCode::NOT_FOUND,
+            TenantError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Problem::from_error(status, &self).into_response()
+    }
+}
+
+/// The publication a request is for, with what it takes to write to its subscribers.
+///
+/// Resolved from the `/tenants/{slug}` prefix of the path, then from the `Host` header, and
+/// falls back to the default tenant.
+#[derive(Clone)]
+pub struct Tenant {
+    pub id: Uuid,
+    pub slug: String,
+    /// Prefix of the links sent by email
+    pub base_url: String,
+    /// `/tenants/{slug}` when the request went through it, prefix of the links of pages
+    pub path_prefix: String,
+    pub email_client: EmailClient,
+}
+
+/// Slug of the prefix `strip_tenant_prefix` took off the path.
+#[derive(Clone)]
+struct TenantSlug(String);
+
+impl FromRequestParts<Arc<ApplicationState>> for Tenant {
+    type Rejection = TenantError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<ApplicationState>,
+    ) -> Result<Self, Self::Rejection> {
+        // Authentication resolves the tenant too, it is only looked up once per request
+        if let Some(tenant) = parts.extensions.get::<Tenant>() {
+            return Ok(tenant.clone());
+        }
+
+        let slug = parts.extensions.get::<TenantSlug>().cloned();
+        let tenant = match &slug {
+            Some(TenantSlug(slug)) => Tenants::find()
+                .filter(tenants::Column::Slug.eq(slug))
+                .one(&state.db_connection)
+                .await
+                .context("Failed to fetch the tenant from the database")?
+                .ok_or_else(|| {
+                    TenantError::UnknownTenant(format!("{} is not a known tenant", slug))
+                })?,
+            None => {
+                // Hosts that are not a tenant's own are the deployment's
+                let mut condition = Condition::any().add(tenants::Column::Id.eq(DEFAULT_TENANT_ID));
+                if let Some(host) = request_host(parts) {
+                    condition = condition.add(tenants::Column::Host.eq(host));
+                }
+                Tenants::find()
+                    .filter(condition)
+                    .all(&state.db_connection)
+                    .await
+                    .context("Failed to fetch the tenant from the database")?
+                    .into_iter()
+                    .max_by_key(|tenant| tenant.id != DEFAULT_TENANT_ID)
+                    .context("The default tenant does not exist")?
+            }
+        };
+
+        let path_prefix = if slug.is_some() {
+            format!("{}/{}", TENANT_PATH_PREFIX, tenant.slug)
+        } else {
+            String::new()
+        };
+        let tenant = Tenant::new(tenant, path_prefix, state)?;
+        parts.extensions.insert(tenant.clone());
+
+        Ok(tenant)
+    }
+}
+
+impl Tenant {
+    /// Fills in what the tenant leaves unset with the configuration of the deployment.
+    fn new(
+        tenant: tenants::Model,
+        path_prefix: String,
+        state: &ApplicationState,
+    ) -> Result<Self, anyhow::Error> {
+        let sender = tenant
+            .sender_email
+            .map(SubscriberEmail::parse)
+            .transpose()
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("Invalid sender email for tenant {}", tenant.slug))?;
+        let email_client = state
+            .email_client
+            .with_account(sender, tenant.email_account.as_deref())
+            .map_err(anyhow::Error::msg)
+            .with_context(|| format!("Invalid email account for tenant {}", tenant.slug))?;
+
+        let base_url = match tenant.base_url {
+            Some(base_url) => base_url,
+            None if tenant.id == DEFAULT_TENANT_ID => state.base_url.clone(),
+            None => format!("{}{}/{}", state.base_url, TENANT_PATH_PREFIX, tenant.slug),
+        };
+
+        Ok(Self {
+            id: tenant.id,
+            email_client,
+            slug: tenant.slug,
+            base_url,
+            path_prefix,
+        })
+    }
+}
+
+/// Host the request was addressed to, without its port.
+fn request_host(parts: &Parts) -> Option<String> {
+    let host = match parts.headers.get(header::HOST) {
+        Some(host) => host.to_str().ok()?.parse::<Authority>().ok()?,
+        None => parts.uri.authority()?.clone(),
+    };
+
+    Some(host.host().to_ascii_lowercase())
+}
+
+/// Takes the `/tenants/{slug}` prefix off the path before routing, so that every tenant
+/// shares the same routes.
+pub async fn strip_tenant_prefix(mut request: Request, next: Next) -> Response {
+    if let Some((slug, path)) = split_tenant_prefix(request.uri().path()) {
+        let slug = TenantSlug(slug.to_string());
+        let path_and_query = match request.uri().query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path.to_string(),
+        };
+
+        let mut uri = request.uri().clone().into_parts();
+        uri.path_and_query = path_and_query.parse::<PathAndQuery>().ok();
+        if let Ok(uri) = Uri::from_parts(uri) {
+            *request.uri_mut() = uri;
+            request.extensions_mut().insert(slug);
+        }
+    }
+
+    next.run(request).await
+}
+
+/// The slug and the remaining path of a path under `/tenants/{slug}`.
+fn split_tenant_prefix(path: &str) -> Option<(&str, &str)> {
+    let rest = path.strip_prefix(TENANT_PATH_PREFIX)?.strip_prefix('/')?;
+    let (slug, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+
+    match (slug, path) {
+        ("", _) => None,
+        (slug, "") => Some((slug, "/")),
+        (slug, path) => Some((slug, path)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claims::{assert_none, assert_some_eq};
+
+    use crate::tenant::split_tenant_prefix;
+
+    #[test]
+    fn the_prefix_is_taken_off_the_path() {
+        assert_some_eq!(
+            split_tenant_prefix("/tenants/acme/subscriptions/confirm"),
+            ("acme", "/subscriptions/confirm")
+        );
+    }
+
+    #[test]
+    fn the_prefix_alone_stands_for_the_root() {
+        assert_some_eq!(split_tenant_prefix("/tenants/acme"), ("acme", "/"));
+        assert_some_eq!(split_tenant_prefix("/tenants/acme/"), ("acme", "/"));
+    }
+
+    #[test]
+    fn paths_without_a_slug_are_left_alone() {
+        assert_none!(split_tenant_prefix("/tenants"));
+        assert_none!(split_tenant_prefix("/tenants/"));
+        assert_none!(split_tenant_prefix("/tenantsacme/subscriptions"));
+        assert_none!(split_tenant_prefix("/subscriptions"));
+    }
+}
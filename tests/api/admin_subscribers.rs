@@ -0,0 +1,448 @@
+use entity::prelude::{SubscriptionLists, SubscriptionTokens, Subscriptions};
+use entity::subscriptions;
+use reqwest::Method;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{TestApp, spawn_app};
+
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let subscriber_path = format!("/admin/api/subscribers/{}", Uuid::new_v4());
+    let test_cases = vec![
+        (Method::GET, "/admin/api/subscribers".to_string()),
+        (Method::GET, subscriber_path.clone()),
+        (Method::PATCH, subscriber_path.clone()),
+        (Method::DELETE, subscriber_path),
+    ];
+
+    for (method, path) in test_cases {
+        // Act
+        let response = reqwest::Client::new()
+            .request(method.clone(), format!("{}{}", test_app.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "{} {} was not rejected",
+            method,
+            path
+        );
+        assert_eq!(
+            response.headers()["WWW-Authenticate"],
+            r#"Basic realm="admin""#
+        );
+    }
+}
+
+#[tokio::test]
+async fn requests_with_an_invalid_password_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/api/subscribers", test_app.address))
+        .basic_auth(
+            &test_app.test_user.username,
+            Some(Uuid::new_v4().to_string()),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn requests_from_an_unknown_user_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .get(format!("{}/admin/api/subscribers", test_app.address))
+        .basic_auth(
+            Uuid::new_v4().to_string(),
+            Some(&test_app.test_user.password),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn listing_filters_by_status_and_email_substring() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_subscriber(&test_app, "ada", "ada@example.com").await;
+    create_subscriber(&test_app, "grace", "Grace@Example.org").await;
+    let alan_id = create_subscriber(&test_app, "alan", "alan@example.org").await;
+    test_app
+        .admin_request(Method::PATCH, &format!("/subscribers/{}", alan_id))
+        .json(&serde_json::json!({ "status": "confirmed" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let page: serde_json::Value = test_app
+        .admin_request(Method::GET, "/subscribers")
+        .query(&[("status", "pending_confirmation"), ("email", "EXAMPLE.ORG")])
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    let subscribers = page["subscribers"].as_array().unwrap();
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(subscribers[0]["email"], "Grace@example.org");
+    assert!(page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn listing_paginates_with_a_cursor() {
+    // Arrange
+    let test_app = spawn_app().await;
+    for i in 0..5 {
+        create_subscriber(&test_app, "reader", &format!("reader{}@example.com", i)).await;
+    }
+
+    // Act
+    let mut emails = Vec::new();
+    let mut cursor: Option<String> = None;
+    let mut pages = 0;
+    loop {
+        let mut request = test_app
+            .admin_request(Method::GET, "/subscribers")
+            .query(&[("limit", "2"), ("order", "desc")]);
+        if let Some(cursor) = &cursor {
+            request = request.query(&[("cursor", cursor)]);
+        }
+
+        let page: serde_json::Value = request.send().await.unwrap().json().await.unwrap();
+        pages += 1;
+
+        for subscriber in page["subscribers"].as_array().unwrap() {
+            emails.push(subscriber["email"].as_str().unwrap().to_string());
+        }
+
+        match page["next_cursor"].as_str() {
+            Some(next_cursor) => cursor = Some(next_cursor.to_string()),
+            None => break,
+        }
+    }
+
+    // Assert
+    assert_eq!(pages, 3);
+    assert_eq!(
+        emails,
+        (0..5)
+            .rev()
+            .map(|i| format!("reader{}@example.com", i))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[tokio::test]
+async fn listing_returns_a_400_for_invalid_parameters() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let test_cases = vec![
+        ("cursor", "not-a-cursor", "invalid cursor"),
+        ("status", "lapsed", "unknown status"),
+        ("order", "sideways", "unknown order"),
+    ];
+
+    for (key, value, error_message) in test_cases {
+        // Act
+        let response = test_app
+            .admin_request(Method::GET, "/subscribers")
+            .query(&[(key, value)])
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not return a 400 Bad Request for {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn get_returns_the_subscriber_with_lists_and_tags() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let subscriber_id = create_subscriber(&test_app, "ada", "ada@example.com").await;
+
+    // Act
+    let response = test_app
+        .admin_request(Method::GET, &format!("/subscribers/{}", subscriber_id))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["id"], subscriber_id.to_string());
+    assert_eq!(subscriber["email"], "ada@example.com");
+    assert_eq!(subscriber["status"], "pending_confirmation");
+    assert_eq!(
+        subscriber["lists"],
+        serde_json::json!([{ "name": "default", "status": "pending_confirmation" }])
+    );
+    assert_eq!(subscriber["tags"], serde_json::json!(["beta"]));
+}
+
+#[tokio::test]
+async fn get_returns_a_404_for_an_unknown_subscriber() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .admin_request(Method::GET, &format!("/subscribers/{}", Uuid::new_v4()))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn patch_updates_the_subscriber() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let subscriber_id = create_subscriber(&test_app, "ada", "ada@example.com").await;
+
+    // Act
+    let response = test_app
+        .admin_request(Method::PATCH, &format!("/subscribers/{}", subscriber_id))
+        .json(&serde_json::json!({
+            "name": "Ada Lovelace",
+            "email": "lovelace@example.com",
+            "tags": ["VIP", "staff"]
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let subscriber: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(subscriber["name"], "Ada Lovelace");
+    assert_eq!(subscriber["tags"], serde_json::json!(["staff", "vip"]));
+
+    let saved = Subscriptions::find_by_id(subscriber_id)
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+    assert_eq!(saved.email, "lovelace@example.com");
+}
+
+#[tokio::test]
+async fn patch_returns_a_400_for_invalid_data() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let subscriber_id = create_subscriber(&test_app, "ada", "ada@example.com").await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "email": "not-an-email" }),
+            "email",
+            "invalid email",
+        ),
+        (serde_json::json!({ "name": "" }), "name", "empty name"),
+        (
+            serde_json::json!({ "status": "lapsed" }),
+            "status",
+            "unknown status",
+        ),
+        (serde_json::json!({ "tags": [" "] }), "tags", "invalid tag"),
+    ];
+
+    for (body, field, error_message) in test_cases {
+        // Act
+        let response = test_app
+            .admin_request(Method::PATCH, &format!("/subscribers/{}", subscriber_id))
+            .json(&body)
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not return a 400 Bad Request for {}.",
+            error_message
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(
+            problem["errors"][0]["field"], field,
+            "The problem did not point at the field for {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn patch_of_the_status_applies_to_the_list_memberships() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let subscriber_id = create_subscriber(&test_app, "ada", "ada@example.com").await;
+
+    for status in ["confirmed", "unsubscribed"] {
+        // Act
+        let response = test_app
+            .admin_request(Method::PATCH, &format!("/subscribers/{}", subscriber_id))
+            .json(&serde_json::json!({ "status": status }))
+            .send()
+            .await
+            .unwrap();
+
+        // Assert
+        assert_eq!(response.status().as_u16(), 200);
+        let memberships = SubscriptionLists::find()
+            .all(&test_app.db_connection)
+            .await
+            .expect("Failed to fetch data.");
+        assert!(!memberships.is_empty());
+        assert!(memberships.iter().all(|m| m.status == status));
+    }
+}
+
+#[tokio::test]
+async fn patch_returns_a_400_when_confirming_a_subscriber_on_no_list() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let subscriber_id = create_subscriber(&test_app, "ada", "ada@example.com").await;
+    test_app
+        .admin_request(Method::PATCH, &format!("/subscribers/{}", subscriber_id))
+        .json(&serde_json::json!({ "status": "unsubscribed" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = test_app
+        .admin_request(Method::PATCH, &format!("/subscribers/{}", subscriber_id))
+        .json(&serde_json::json!({ "status": "confirmed" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+    let saved = Subscriptions::find_by_id(subscriber_id)
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn patch_returns_a_409_when_the_email_is_taken() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_subscriber(&test_app, "ada", "ada@example.com").await;
+    let subscriber_id = create_subscriber(&test_app, "grace", "grace@example.com").await;
+
+    // Act
+    let response = test_app
+        .admin_request(Method::PATCH, &format!("/subscribers/{}", subscriber_id))
+        .json(&serde_json::json!({ "email": "Ada@Example.com" }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+}
+
+#[tokio::test]
+async fn delete_removes_the_subscriber_and_their_tokens() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let subscriber_id = create_subscriber(&test_app, "ada", "ada@example.com").await;
+
+    // Act
+    let response = test_app
+        .admin_request(Method::DELETE, &format!("/subscribers/{}", subscriber_id))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 204);
+
+    let subscribers = Subscriptions::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+    let tokens = SubscriptionTokens::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+    assert!(subscribers.is_empty());
+    assert!(tokens.is_empty());
+
+    let response = test_app
+        .admin_request(Method::DELETE, &format!("/subscribers/{}", subscriber_id))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+async fn create_subscriber(test_app: &TestApp, name: &str, email: &str) -> Uuid {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+
+    let body =
+        serde_urlencoded::to_string([("name", name), ("email", email), ("tags", "beta")]).unwrap();
+    test_app
+        .post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    Subscriptions::find()
+        .filter(subscriptions::Column::NormalizedEmail.eq(email.to_lowercase()))
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.")
+        .id
+}
@@ -0,0 +1,356 @@
+use std::time::Duration;
+
+use entity::prelude::{SubscriptionLists, Subscriptions};
+use entity::subscription_lists;
+use reqwest::Method;
+use sea_orm::{ColumnTrait, ConnectionTrait, EntityTrait, PaginatorTrait, QueryFilter};
+use wiremock::matchers::{any, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{TestApp, runs_on_postgres, spawn_app};
+
+#[tokio::test]
+async fn import_and_export_require_authentication() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let import = reqwest::Client::new()
+        .post(format!("{}/admin/api/subscribers/import", test_app.address))
+        .body("email,name\nada@example.com,ada\n")
+        .send()
+        .await
+        .unwrap();
+    let export = reqwest::get(format!("{}/admin/api/subscribers/export", test_app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(import.status().as_u16(), 401);
+    assert_eq!(export.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn import_as_confirmed_reports_row_errors_and_sends_no_email() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let csv = "name,email\n\
+               ada,ada@example.com\n\
+               grace,not-an-email\n\
+               {alan},alan@example.com\n\
+               ada again,ada@example.com\n\
+               grace,grace@example.com\n";
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = import(&test_app, "confirmed", csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+    assert_eq!(report["errors"].as_array().unwrap().len(), 2);
+    assert_eq!(report["errors"][0]["row"], 3);
+    assert_eq!(report["errors"][0]["email"], "not-an-email");
+    assert_eq!(report["errors"][1]["row"], 4);
+    assert_eq!(report["skipped"][0]["row"], 5);
+
+    let saved = Subscriptions::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+    assert_eq!(saved.len(), 2);
+    assert!(saved.iter().all(|s| s.status == "confirmed"));
+}
+
+#[tokio::test]
+async fn import_with_double_opt_in_sends_confirmation_emails() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let csv = "email,name\nada@example.com,ada\ngrace@example.com,grace\n";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = import(&test_app, "double_opt_in", csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = Subscriptions::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+    assert!(saved.iter().all(|s| s.status == "pending_confirmation"));
+
+    // The emails are sent in the background, once the batch is committed
+    let email_requests = wait_for_emails(&test_app, 2).await;
+    let confirmation_links = test_app.get_confirmation_links(&email_requests[0]);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+#[tokio::test]
+async fn import_skips_existing_subscribers() {
+    // Arrange
+    let test_app = spawn_app().await;
+    import(&test_app, "confirmed", "email,name\nada@example.com,ada\n").await;
+
+    // Act
+    let response = import(
+        &test_app,
+        "confirmed",
+        "email,name\nada@example.com,ada\ngrace@example.com,grace\n",
+    )
+    .await;
+
+    // Assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert_eq!(report["skipped"][0]["email"], "ada@example.com");
+}
+
+#[tokio::test]
+async fn import_adds_existing_subscribers_to_a_list_they_are_not_on() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let list_id = test_app.create_list("weekly").await;
+    import(&test_app, "confirmed", "email,name\nada@example.com,ada\n").await;
+
+    // Act
+    let response = test_app
+        .admin_request(Method::POST, "/subscribers/import")
+        .query(&[("mode", "confirmed"), ("list", "weekly")])
+        .header("Content-Type", "text/csv")
+        .body("email,name\nAda@Example.com,ada\n")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1);
+    assert!(report["skipped"].as_array().unwrap().is_empty());
+
+    let subscribers = Subscriptions::find()
+        .count(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+    assert_eq!(subscribers, 1);
+    let membership = SubscriptionLists::find()
+        .filter(subscription_lists::Column::ListId.eq(list_id))
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+    assert_eq!(membership.status, "confirmed");
+}
+
+#[tokio::test]
+async fn import_inserts_large_files_in_batches() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let mut csv = String::from("email,name\n");
+    for i in 0..1234 {
+        csv.push_str(&format!("reader{}@example.com,reader\n", i));
+    }
+
+    // Act
+    let response = import(&test_app, "confirmed", &csv).await;
+
+    // Assert
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 1234);
+
+    let memberships = SubscriptionLists::find()
+        .count(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+    assert_eq!(memberships, 1234);
+}
+
+#[tokio::test]
+async fn import_reports_the_batches_committed_before_a_failure() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let statements: &[&str] = if runs_on_postgres() {
+        &[
+            "CREATE FUNCTION reject_subscriber() RETURNS trigger AS $$
+            BEGIN
+                RAISE EXCEPTION 'rejected';
+            END
+            $$ LANGUAGE plpgsql",
+            "CREATE TRIGGER reject_subscriber BEFORE INSERT ON subscriptions
+            FOR EACH ROW WHEN (NEW.email = 'rejected@example.com')
+            EXECUTE FUNCTION reject_subscriber()",
+        ]
+    } else {
+        &[
+            "CREATE TRIGGER reject_subscriber BEFORE INSERT ON subscriptions
+        WHEN NEW.email = 'rejected@example.com'
+        BEGIN
+            SELECT RAISE(ABORT, 'rejected');
+        END",
+        ]
+    };
+    for statement in statements {
+        test_app
+            .db_connection
+            .execute_unprepared(statement)
+            .await
+            .expect("Failed to create the trigger.");
+    }
+    let mut csv = String::from("email,name\n");
+    for i in 0..500 {
+        csv.push_str(&format!("reader{}@example.com,reader\n", i));
+    }
+    csv.push_str("reader500@example.com,reader\nrejected@example.com,reader\n");
+
+    // Act
+    let response = import(&test_app, "confirmed", &csv).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 500);
+    assert_eq!(report["failed"]["row"], 502);
+    assert_eq!(report["failed"]["email"], "reader500@example.com");
+
+    let saved = Subscriptions::find()
+        .count(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+    assert_eq!(saved, 500);
+}
+
+#[tokio::test]
+async fn import_returns_a_400_for_invalid_input() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "confirmed",
+            "address,name\nada@example.com,ada\n",
+            "missing email column",
+        ),
+        (
+            "confirmed",
+            "email\nada@example.com\n",
+            "missing name column",
+        ),
+        ("maybe", "email,name\nada@example.com,ada\n", "unknown mode"),
+    ];
+
+    for (mode, csv, error_message) in test_cases {
+        // Act
+        let response = import(&test_app, mode, csv).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not return a 400 Bad Request for {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn export_streams_subscribers_filtered_by_status() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let mut csv = String::from("email,name\n");
+    for i in 0..600 {
+        csv.push_str(&format!("reader{}@example.com,reader {}\n", i, i));
+    }
+    import(&test_app, "confirmed", &csv).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    import(
+        &test_app,
+        "double_opt_in",
+        "email,name\npending@example.com,pending\n",
+    )
+    .await;
+
+    // Act
+    let response = test_app
+        .admin_request(Method::GET, "/subscribers/export")
+        .query(&[("status", "confirmed")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "text/csv; charset=utf-8"
+    );
+
+    let body = response.text().await.unwrap();
+    let lines: Vec<&str> = body.lines().collect();
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at");
+    assert_eq!(lines.len(), 601);
+    assert!(lines[1..].iter().all(|line| line.contains(",confirmed,")));
+    assert!(!body.contains("pending@example.com"));
+}
+
+#[tokio::test]
+async fn export_returns_a_400_for_an_unknown_status() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .admin_request(Method::GET, "/subscribers/export")
+        .query(&[("status", "lapsed")])
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+async fn import(test_app: &TestApp, mode: &str, csv: &str) -> reqwest::Response {
+    test_app
+        .admin_request(Method::POST, "/subscribers/import")
+        .query(&[("mode", mode)])
+        .header("Content-Type", "text/csv")
+        .body(csv.to_string())
+        .send()
+        .await
+        .expect("Failed to execute request.")
+}
+
+async fn wait_for_emails(test_app: &TestApp, count: usize) -> Vec<wiremock::Request> {
+    for _ in 0..100 {
+        let requests = test_app.email_server.received_requests().await.unwrap();
+        if requests.len() >= count {
+            return requests;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    panic!("Fewer than {} emails were sent.", count);
+}
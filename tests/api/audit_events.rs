@@ -0,0 +1,227 @@
+use entity::prelude::Subscriptions;
+use reqwest::Method;
+use sea_orm::{ConnectionTrait, EntityTrait};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{TestApp, spawn_app};
+
+#[tokio::test]
+async fn audit_events_require_authentication() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/admin/api/audit-events", test_app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn updating_a_subscriber_records_who_changed_what() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let subscriber_id = create_subscriber(&test_app).await;
+
+    // Act
+    test_app
+        .admin_request(Method::PATCH, &format!("/subscribers/{}", subscriber_id))
+        .json(&serde_json::json!({ "name": "Ursula" }))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let events = list_audit_events(&test_app, &[("action", "subscriber.update")]).await;
+    let event = &events["events"][0];
+
+    assert_eq!(event["actor"], test_app.test_user.username);
+    assert_eq!(event["actor_id"], test_app.test_user.user_id.to_string());
+    assert_eq!(event["target"], subscriber_id.to_string());
+    assert!(!event["request_id"].as_str().unwrap().is_empty());
+    assert_eq!(event["diff"], serde_json::json!(["name"]));
+}
+
+#[tokio::test]
+async fn deleting_a_subscriber_records_no_personal_data() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let subscriber_id = create_subscriber(&test_app).await;
+
+    // Act
+    test_app
+        .admin_request(Method::DELETE, &format!("/subscribers/{}", subscriber_id))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let events = list_audit_events(&test_app, &[("action", "subscriber.delete")]).await;
+    let event = &events["events"][0];
+
+    assert_eq!(event["target"], subscriber_id.to_string());
+    assert!(
+        event["diff"]
+            .as_array()
+            .unwrap()
+            .contains(&serde_json::json!("email"))
+    );
+    let recorded = event.to_string();
+    assert!(!recorded.contains("ursula_le_guin@gmail.com"));
+    assert!(!recorded.contains("le guin"));
+}
+
+#[tokio::test]
+async fn publishing_a_newsletter_records_the_issue() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    test_app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>"
+            }
+        }))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let events = list_audit_events(&test_app, &[("action", "newsletter.publish")]).await;
+    let event = &events["events"][0];
+
+    assert_eq!(event["actor"], test_app.test_user.username);
+    assert_eq!(event["target"], "Newsletter title");
+    assert_eq!(event["diff"]["lists"]["to"], serde_json::json!(["default"]));
+    assert_eq!(event["diff"]["recipients"]["to"], 0);
+}
+
+#[tokio::test]
+async fn audit_events_are_listed_newest_first_across_pages() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let subscriber_id = create_subscriber(&test_app).await;
+    for patch in [
+        serde_json::json!({ "name": "a" }),
+        serde_json::json!({ "tags": ["b"] }),
+        serde_json::json!({ "status": "unsubscribed" }),
+    ] {
+        test_app
+            .admin_request(Method::PATCH, &format!("/subscribers/{}", subscriber_id))
+            .json(&patch)
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Act
+    let first_page = list_audit_events(
+        &test_app,
+        &[("action", "subscriber.update"), ("limit", "2")],
+    )
+    .await;
+    let second_page = list_audit_events(
+        &test_app,
+        &[
+            ("action", "subscriber.update"),
+            ("limit", "2"),
+            ("cursor", first_page["next_cursor"].as_str().unwrap()),
+        ],
+    )
+    .await;
+
+    // Assert
+    let changes: Vec<&serde_json::Value> = first_page["events"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .chain(second_page["events"].as_array().unwrap())
+        .map(|event| &event["diff"])
+        .collect();
+
+    assert_eq!(
+        changes,
+        [
+            &serde_json::json!(["lists", "status"]),
+            &serde_json::json!(["tags"]),
+            &serde_json::json!(["name"]),
+        ]
+    );
+    assert!(second_page["next_cursor"].is_null());
+}
+
+#[tokio::test]
+async fn audit_events_cannot_be_rewritten() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_subscriber(&test_app).await;
+
+    // Act
+    let update = test_app
+        .db_connection
+        .execute_unprepared("UPDATE audit_events SET actor = 'someone else'")
+        .await;
+    let delete = test_app
+        .db_connection
+        .execute_unprepared("DELETE FROM audit_events")
+        .await;
+
+    // Assert
+    assert!(update.is_err());
+    assert!(delete.is_err());
+}
+
+/// Imports a confirmed subscriber through the admin API and returns their id.
+async fn create_subscriber(test_app: &TestApp) -> Uuid {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    test_app
+        .admin_request(Method::POST, "/subscribers/import")
+        .query(&[("mode", "confirmed")])
+        .header("Content-Type", "text/csv")
+        .body("email,name\nursula_le_guin@gmail.com,le guin\n")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    Subscriptions::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.")
+        .id
+}
+
+async fn list_audit_events(test_app: &TestApp, query: &[(&str, &str)]) -> serde_json::Value {
+    test_app
+        .admin_request(Method::GET, "/audit-events")
+        .query(query)
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
@@ -0,0 +1,253 @@
+use entity::prelude::Subscriptions;
+use migration::DEFAULT_TENANT_SLUG;
+use reqwest::Client;
+use sea_orm::{EntityTrait, PaginatorTrait};
+use secrecy::{ExposeSecret, SecretString};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod_axum::cli::{
+    NewTenant, check_config, create_tenant, create_user, generate_password, migration_status, seed,
+    send_test_email,
+};
+use zero2prod_axum::configuration::get_configuration;
+use zero2prod_axum::startup::get_email_client;
+
+use crate::helper::spawn_app;
+
+#[tokio::test]
+async fn created_users_can_publish_newsletters() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let password = generate_password();
+
+    // Act
+    create_user(
+        &test_app.db_connection,
+        DEFAULT_TENANT_SLUG,
+        "publisher",
+        password.clone(),
+    )
+    .await
+    .expect("Failed to create user");
+
+    // Assert
+    let response = Client::new()
+        .post(format!("{}/newsletters", test_app.address))
+        .basic_auth("publisher", Some(password.expose_secret()))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>"
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn create_user_rejects_a_taken_username() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let result = create_user(
+        &test_app.db_connection,
+        DEFAULT_TENANT_SLUG,
+        &test_app.test_user.username,
+        SecretString::from("another password"),
+    )
+    .await;
+
+    // Assert
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("already exists"), "{}", error);
+}
+
+#[tokio::test]
+async fn seeding_twice_inserts_the_sample_data_once() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let first = seed(&test_app.db_connection).await.unwrap();
+    let second = seed(&test_app.db_connection).await.unwrap();
+
+    // Assert
+    assert!(first > 0);
+    assert_eq!(second, 0);
+    let subscribers = Subscriptions::find()
+        .count(&test_app.db_connection)
+        .await
+        .unwrap();
+    assert_eq!(subscribers, first as u64);
+}
+
+#[tokio::test]
+async fn send_test_email_goes_through_the_email_provider() {
+    // Arrange
+    let email_server = MockServer::start().await;
+    let mut settings = get_configuration().unwrap().email_client;
+    settings.base_url = email_server.uri();
+    let email_client = get_email_client(settings);
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&email_server)
+        .await;
+
+    // Act
+    let result = send_test_email(&email_client, "ursula@example.com").await;
+
+    // Assert
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn send_test_email_rejects_an_invalid_address() {
+    // Arrange
+    let email_client = get_email_client(get_configuration().unwrap().email_client);
+
+    // Act
+    let result = send_test_email(&email_client, "not-an-email").await;
+
+    // Assert
+    assert!(result.is_err());
+}
+
+#[test]
+fn check_config_redacts_secrets() {
+    // Arrange
+    let settings = get_configuration().unwrap();
+
+    // Act
+    let dump = check_config(&settings).expect("The configuration is invalid");
+
+    // Assert
+    let dump: serde_json::Value = serde_json::from_str(&dump).unwrap();
+    assert_eq!(dump["database"]["password"], "[REDACTED]");
+    assert_eq!(dump["email_client"]["authorization_token"], "[REDACTED]");
+    for token in dump["email_client"]["accounts"]
+        .as_object()
+        .unwrap()
+        .values()
+    {
+        assert_eq!(token, "[REDACTED]");
+    }
+    assert_eq!(
+        dump["database"]["database_name"],
+        settings.database.database_name
+    );
+}
+
+#[test]
+fn check_config_reports_every_invalid_setting() {
+    // Arrange
+    let mut settings = get_configuration().unwrap();
+    settings.application.base_url = "not a url".into();
+    settings.email_client.sender_email = "not an email".into();
+    settings.application.rate_limit.per_email.refill_per_minute = 0;
+
+    // Act
+    let errors = check_config(&settings).unwrap_err();
+
+    // Assert
+    assert_eq!(errors.len(), 3);
+    assert!(errors[0].starts_with("application.base_url"));
+    assert!(errors[1].starts_with("email_client.sender_email"));
+    assert!(errors[2].starts_with("application.rate_limit.per_email.refill_per_minute"));
+}
+
+#[test]
+fn check_config_requires_a_form_token_secret_once_the_fill_time_check_is_enabled() {
+    // Arrange
+    let mut settings = get_configuration().unwrap();
+    settings.application.bot_protection.min_form_fill_seconds = 3;
+    let test_cases = vec![
+        ("", "a missing secret"),
+        (
+            "form-token-secret",
+            "the placeholder of earlier configurations",
+        ),
+    ];
+
+    for (secret, description) in test_cases {
+        settings.application.bot_protection.form_token_secret = secret.into();
+
+        // Act
+        let errors = check_config(&settings).unwrap_err();
+
+        // Assert
+        assert_eq!(
+            errors,
+            vec!["application.bot_protection.form_token_secret: must be set to a secret key"],
+            "The configuration was accepted with {}.",
+            description
+        );
+    }
+
+    settings.application.bot_protection.form_token_secret = "a-secret".into();
+    assert!(check_config(&settings).is_ok());
+}
+
+#[tokio::test]
+async fn migration_status_lists_every_migration_as_applied() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let status = migration_status(&test_app.db_connection).await.unwrap();
+
+    // Assert
+    assert!(!status.is_empty());
+    assert!(status.iter().all(|(_, applied)| *applied));
+}
+
+#[tokio::test]
+async fn create_tenant_rejects_a_taken_slug() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let result = create_tenant(
+        &test_app.db_connection,
+        NewTenant {
+            slug: DEFAULT_TENANT_SLUG.into(),
+            host: None,
+            base_url: None,
+            sender_email: None,
+            email_account: None,
+        },
+    )
+    .await;
+
+    // Assert
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("already exists"), "{}", error);
+}
+
+#[tokio::test]
+async fn create_tenant_rejects_an_invalid_sender() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let result = create_tenant(
+        &test_app.db_connection,
+        NewTenant {
+            slug: "acme".into(),
+            host: None,
+            base_url: None,
+            sender_email: Some("not an email".into()),
+            email_account: None,
+        },
+    )
+    .await;
+
+    // Assert
+    assert!(result.is_err());
+}
@@ -0,0 +1,82 @@
+use std::time::{Duration, Instant};
+
+use reqwest::Method;
+use sea_orm::{ConnectionTrait, TransactionTrait};
+use serde_json::Value;
+
+use crate::helper::{TestApp, runs_on_postgres, spawn_app_with};
+
+/// Blocks every query on `subscriptions` until the returned transaction ends.
+async fn lock_subscriptions(test_app: &TestApp) -> sea_orm::DatabaseTransaction {
+    let transaction = test_app.db_connection.begin().await.unwrap();
+    transaction
+        .execute_unprepared("LOCK TABLE subscriptions IN ACCESS EXCLUSIVE MODE")
+        .await
+        .unwrap();
+    transaction
+}
+
+#[tokio::test]
+async fn an_exhausted_pool_returns_503() {
+    // SQLite has no table locks to hold the pool up with
+    if !runs_on_postgres() {
+        return;
+    }
+
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        configuration.database.max_connections = 1;
+        configuration.database.acquire_timeout_milliseconds = 200;
+    })
+    .await;
+    let lock = lock_subscriptions(&test_app).await;
+    // Holds the only connection of the pool while waiting for the lock
+    let blocked_request = tokio::spawn(test_app.admin_request(Method::GET, "/subscribers").send());
+    tokio::time::sleep(Duration::from_millis(300)).await;
+
+    // Act
+    let response = test_app
+        .admin_request(Method::GET, "/subscribers")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    assert_eq!(response.headers()["Retry-After"], "1");
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["type"], "/problems/database-unavailable");
+
+    lock.rollback().await.unwrap();
+    let response = blocked_request.await.unwrap().unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn statements_running_past_the_statement_timeout_are_aborted() {
+    // SQLite has neither table locks nor statement timeouts
+    if !runs_on_postgres() {
+        return;
+    }
+
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        configuration.database.statement_timeout_milliseconds = Some(200);
+    })
+    .await;
+    let lock = lock_subscriptions(&test_app).await;
+
+    // Act
+    let start = Instant::now();
+    let response = test_app
+        .admin_request(Method::GET, "/subscribers")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 500);
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    lock.rollback().await.unwrap();
+}
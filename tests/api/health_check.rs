@@ -1,6 +1,11 @@
+use migration::{Migrator, MigratorTrait};
 use reqwest::Client;
+use sea_orm::sqlx::{Connection, Executor, PgConnection};
+use sea_orm::{ConnectionTrait, DatabaseBackend, Statement};
+use serde_json::{Value, json};
+use zero2prod_axum::configuration::get_configuration;
 
-use crate::helper::spawn_app;
+use crate::helper::{runs_on_postgres, spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn test_health_check_works() {
@@ -19,3 +24,139 @@ async fn test_health_check_works() {
     assert!(response.status().is_success());
     assert_eq!(Some(0), response.content_length())
 }
+
+#[tokio::test]
+async fn liveness_reports_the_process_as_up() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = Client::new()
+        .get(format!("{}/health/live", test_app.address))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body, json!({ "status": "up" }));
+}
+
+#[tokio::test]
+async fn readiness_reports_every_dependency_when_ready() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "up");
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["migrations"]["status"], "up");
+    assert!(body["checks"].get("email_provider").is_none());
+}
+
+#[tokio::test]
+async fn readiness_returns_503_with_pending_migrations() {
+    // Arrange
+    let test_app = spawn_app().await;
+    Migrator::down(&test_app.db_connection, Some(1))
+        .await
+        .expect("Failed to revert the last migration");
+
+    // Act
+    let response = test_app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["migrations"]["status"], "down");
+    assert!(
+        body["checks"]["migrations"]["detail"]
+            .as_str()
+            .unwrap()
+            .starts_with("1 pending migrations")
+    );
+}
+
+#[tokio::test]
+async fn readiness_returns_503_when_the_database_is_gone() {
+    // An in-memory SQLite database cannot be dropped from under the application
+    if !runs_on_postgres() {
+        return;
+    }
+
+    // Arrange
+    let test_app = spawn_app().await;
+    let database_name: String = test_app
+        .db_connection
+        .query_one(Statement::from_string(
+            DatabaseBackend::Postgres,
+            "SELECT current_database()",
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get_by_index(0)
+        .unwrap();
+    let settings = get_configuration().unwrap().database;
+    let mut pg_connection = PgConnection::connect_with(&settings.without_db())
+        .await
+        .unwrap();
+    pg_connection
+        .execute(format!(r#"DROP DATABASE "{}" WITH (FORCE);"#, database_name).as_str())
+        .await
+        .expect("Failed to drop database");
+
+    // Act
+    let response = test_app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "down");
+    assert_eq!(body["checks"]["database"]["status"], "down");
+    assert!(body["checks"]["database"]["detail"].is_string());
+}
+
+#[tokio::test]
+async fn readiness_checks_the_email_provider_when_enabled() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        configuration.application.health.check_email_provider = true;
+    })
+    .await;
+
+    // Act
+    let response = test_app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["email_provider"]["status"], "up");
+}
+
+#[tokio::test]
+async fn readiness_returns_503_when_the_email_provider_is_unreachable() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        configuration.application.health.check_email_provider = true;
+        configuration.email_client.base_url = "http://127.0.0.1:1".to_string();
+    })
+    .await;
+
+    // Act
+    let response = test_app.get_health_ready().await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 503);
+    let body: Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["database"]["status"], "up");
+    assert_eq!(body["checks"]["email_provider"]["status"], "down");
+}
@@ -1,47 +1,103 @@
 use std::sync::LazyLock;
 
+use chrono::Utc;
+use entity::{lists, users};
 use linkify::{LinkFinder, LinkKind};
-use migration::{Migrator, MigratorTrait};
-use reqwest::{Client, Response, Url};
-use sea_orm::DatabaseConnection;
+use migration::{DEFAULT_TENANT_ID, Migrator, MigratorTrait};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use reqwest::{Client, Method, RequestBuilder, Response, Url};
+use sea_orm::ActiveValue::Set;
+use sea_orm::prelude::DateTimeWithTimeZone;
 use sea_orm::sqlx::{Connection, Executor, PgConnection};
+use sea_orm::{ActiveModelTrait, DatabaseConnection};
+use secrecy::{ExposeSecret, SecretString};
+use tokio::task::JoinHandle;
 use uuid::Uuid;
 use wiremock::MockServer;
 
-use zero2prod_axum::configuration::{DatabaseSettings, get_configuration};
+use zero2prod_axum::authentication::compute_password_hash;
+use zero2prod_axum::configuration::{Backend, DatabaseSettings, Settings, get_configuration};
+use zero2prod_axum::shutdown::Shutdown;
 use zero2prod_axum::startup::{Application, get_database_connection};
 use zero2prod_axum::telemetry::{get_subscriber, init_subscriber};
 
 static TRACING: LazyLock<()> = LazyLock::new(|| {
     let default_filter_level = "info".to_string();
     let subscriber_name = "test".to_string();
+    // Spans are not exported, but still carry trace context
+    let tracer_provider = SdkTracerProvider::builder().build();
 
     if std::env::var("TEST_LOG").is_ok() {
         init_subscriber(get_subscriber(
             subscriber_name,
             default_filter_level,
             std::io::stdout,
+            &tracer_provider,
         ));
     } else {
         init_subscriber(get_subscriber(
             subscriber_name,
             default_filter_level,
             std::io::sink,
+            &tracer_provider,
         ));
     }
 });
 
+pub struct TestUser {
+    pub user_id: Uuid,
+    pub username: String,
+    pub password: String,
+}
+
+impl TestUser {
+    pub fn generate() -> Self {
+        Self {
+            user_id: Uuid::new_v4(),
+            username: Uuid::new_v4().to_string(),
+            password: Uuid::new_v4().to_string(),
+        }
+    }
+
+    pub async fn store(&self, db_connection: &DatabaseConnection, tenant_id: Uuid) {
+        let password_hash = compute_password_hash(SecretString::from(self.password.clone()))
+            .expect("Failed to hash password");
+
+        users::ActiveModel {
+            user_id: Set(self.user_id),
+            username: Set(self.username.clone()),
+            password_hash: Set(password_hash.expose_secret().to_string()),
+            tenant_id: Set(tenant_id),
+        }
+        .insert(db_connection)
+        .await
+        .expect("Failed to store test user.");
+    }
+}
+
 pub struct TestApp {
     pub address: String,
     pub port: u16,
     pub db_connection: DatabaseConnection,
     pub email_server: MockServer,
+    pub test_user: TestUser,
+    pub shutdown: Shutdown,
+    /// Completes once the application has shut down
+    pub service: JoinHandle<std::io::Result<()>>,
 }
 
 impl TestApp {
+    /// Builds a request to the admin API, authenticated as the test user.
+    pub fn admin_request(&self, method: Method, path: &str) -> RequestBuilder {
+        Client::new()
+            .request(method, format!("{}/admin/api{}", &self.address, path))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> Response {
         Client::new()
             .post(format!("{}/newsletters", self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(&body)
             .send()
             .await
@@ -58,25 +114,139 @@ impl TestApp {
             .expect("Failed to execute request.")
     }
 
+    /// Fetches a token the way a signup form does when it is rendered.
+    pub async fn get_form_token(&self) -> String {
+        let body: serde_json::Value = Client::new()
+            .get(format!("{}/subscriptions/form-token", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .json()
+            .await
+            .unwrap();
+
+        body["form_token"].as_str().unwrap().to_string()
+    }
+
+    pub async fn post_subscriptions_json(&self, body: &serde_json::Value) -> Response {
+        Client::new()
+            .post(format!("{}/subscriptions", &self.address))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_health_ready(&self) -> Response {
+        Client::new()
+            .get(format!("{}/health/ready", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_metrics(&self) -> String {
+        Client::new()
+            .get(format!("{}/metrics", &self.address))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+            .text()
+            .await
+            .unwrap()
+    }
+
+    pub async fn get_preferences(&self, preference_token: &str) -> Response {
+        Client::new()
+            .get(format!("{}/preferences", &self.address))
+            .query(&[("preference_token", preference_token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_preferences(&self, body: String) -> Response {
+        Client::new()
+            .post(format!("{}/preferences", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_unsubscribe(&self, body: String) -> Response {
+        Client::new()
+            .post(format!("{}/preferences/unsubscribe", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_personal_data(&self, preference_token: &str) -> Response {
+        Client::new()
+            .get(format!("{}/preferences/data", &self.address))
+            .query(&[("preference_token", preference_token)])
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_erase(&self, body: String) -> Response {
+        Client::new()
+            .post(format!("{}/preferences/erase", &self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn create_list(&self, name: &str) -> Uuid {
+        lists::ActiveModel {
+            id: Set(Uuid::new_v4()),
+            name: Set(name.to_string()),
+            created_at: Set(DateTimeWithTimeZone::from(Utc::now())),
+            tenant_id: Set(DEFAULT_TENANT_ID),
+        }
+        .insert(&self.db_connection)
+        .await
+        .expect("Failed to create mailing list.")
+        .id
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        self.get_links(email_request, "/subscriptions/confirm")
+    }
+
+    /// Links of a confirmation email or an issue to the preferences of the subscriber.
+    pub fn get_preferences_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
+        self.get_links(email_request, "/preferences")
+    }
+
+    /// The link of each body of an email whose path ends with `path`.
+    fn get_links(&self, email_request: &wiremock::Request, path: &str) -> ConfirmationLinks {
         let body = serde_json::from_slice::<serde_json::Value>(&email_request.body).unwrap();
 
         let get_link = |s: &str| {
             let links = LinkFinder::new()
                 .links(s)
                 .filter(|l| *l.kind() == LinkKind::Url)
+                .map(|l| Url::parse(l.as_str()).unwrap())
+                .filter(|link| link.path().ends_with(path))
                 .collect::<Vec<_>>();
 
             assert_eq!(links.len(), 1);
 
-            let raw_link = links[0].as_str().to_owned();
-            let mut confirmation_link = Url::parse(&raw_link).unwrap();
+            let mut link = links[0].clone();
 
-            assert_eq!(confirmation_link.host_str().unwrap(), "127.0.0.1");
+            assert_eq!(link.host_str().unwrap(), "127.0.0.1");
 
-            confirmation_link.set_port(Some(self.port)).unwrap();
+            link.set_port(Some(self.port)).unwrap();
 
-            confirmation_link
+            link
         };
 
         ConfirmationLinks {
@@ -92,6 +262,11 @@ pub struct ConfirmationLinks {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawns the application after letting the test adjust its configuration.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     // Setup logger for tests,
     // `once_cell::sync::Lazy` ensures that the initialization will be executed only once
     LazyLock::force(&TRACING);
@@ -103,7 +278,7 @@ pub async fn spawn_app() -> TestApp {
         let mut configuration = get_configuration().expect("Failed to read configuration");
 
         // Use a different database for each test case
-        configuration.database.database_name = Uuid::new_v4().to_string();
+        use_fresh_database(&mut configuration.database);
 
         // Use the mock server as email API
         configuration.email_client.base_url = email_server.uri();
@@ -111,6 +286,8 @@ pub async fn spawn_app() -> TestApp {
         // Use a random OS port
         configuration.application.port = 0;
 
+        configure(&mut configuration);
+
         configuration
     };
 
@@ -121,19 +298,63 @@ pub async fn spawn_app() -> TestApp {
         .await
         .expect("Failed to build application");
     let application_port = application.port();
+    let shutdown = application.shutdown();
 
-    tokio::spawn(application.start_service());
+    let service = tokio::spawn(application.start_service());
+
+    let test_user = TestUser::generate();
+    test_user.store(&db_connection, DEFAULT_TENANT_ID).await;
 
     TestApp {
         address: format!("http://127.0.0.1:{}", application_port),
         port: application_port,
         db_connection,
         email_server,
+        test_user,
+        shutdown,
+        service,
     }
 }
 
 async fn configure_database(settings: &DatabaseSettings) -> DatabaseConnection {
-    // Create database
+    let db_connection = create_database(settings).await;
+
+    // Migrate database
+    Migrator::up(&db_connection, None)
+        .await
+        .expect("Failed to migrate database for test.");
+
+    db_connection
+}
+
+/// Points the settings at a database of their own, in memory when running on SQLite.
+pub fn use_fresh_database(settings: &mut DatabaseSettings) {
+    settings.database_name = Uuid::new_v4().to_string();
+    if settings.backend == Backend::Sqlite {
+        settings.sqlite_path = ":memory:".to_string();
+    }
+}
+
+/// Whether the suite runs against Postgres, which tests of Postgres-specific behaviour need.
+pub fn runs_on_postgres() -> bool {
+    let configuration = get_configuration().expect("Failed to read configuration");
+
+    configuration.database.backend == Backend::Postgres
+}
+
+/// Creates an empty database, leaving migrations to the caller.
+pub async fn create_database(settings: &DatabaseSettings) -> DatabaseConnection {
+    // SQLite creates the database on first connection, and an in-memory one only lives as
+    // long as a connection to it
+    if settings.backend == Backend::Sqlite {
+        let db_connection = get_database_connection(settings);
+        db_connection
+            .ping()
+            .await
+            .expect("Failed to connect to SQLite");
+        return db_connection;
+    }
+
     let mut pg_connection = PgConnection::connect_with(&settings.without_db())
         .await
         .expect("Failed to connect to Postgres");
@@ -143,12 +364,5 @@ async fn configure_database(settings: &DatabaseSettings) -> DatabaseConnection {
         .await
         .expect("Failed to create database");
 
-    // Migrate database
-    let db_connection = get_database_connection(settings);
-
-    Migrator::up(&db_connection, None)
-        .await
-        .expect("Failed to migrate database for test.");
-
-    db_connection
+    get_database_connection(settings)
 }
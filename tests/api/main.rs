@@ -1,5 +1,17 @@
+mod admin_subscribers;
+mod admin_subscribers_csv;
+mod audit_events;
+mod cli;
+mod database_pool;
 mod health_check;
 mod helper;
+mod metrics;
+mod migrations;
 mod newsletter;
+mod openapi;
+mod preferences;
+mod replica;
+mod shutdown;
 mod subscriptions;
 mod subscriptions_confirm;
+mod tenants;
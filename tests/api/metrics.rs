@@ -0,0 +1,141 @@
+use reqwest::Client;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::spawn_app;
+
+/// The value of the sample whose name and labels match `series` exactly, if any.
+fn sample(metrics: &str, series: &str) -> Option<f64> {
+    metrics
+        .lines()
+        .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+        .map(|value| value.parse().unwrap())
+}
+
+#[tokio::test]
+async fn metrics_count_requests_by_route_template_and_status() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let client = Client::new();
+
+    // Act
+    for _ in 0..2 {
+        client
+            .get(format!("{}/health_check", &test_app.address))
+            .send()
+            .await
+            .unwrap();
+    }
+    test_app
+        .admin_request(
+            reqwest::Method::GET,
+            &format!("/subscribers/{}", uuid::Uuid::new_v4()),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    let metrics = test_app.get_metrics().await;
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"http_requests_total{method="GET",route="/health_check",status="200"}"#
+        ),
+        Some(2.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"http_requests_total{method="GET",route="/admin/api/subscribers/{id}",status="404"}"#
+        ),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"http_request_duration_seconds_count{method="GET",route="/health_check",status="200"}"#
+        ),
+        Some(2.0)
+    );
+}
+
+#[tokio::test]
+async fn metrics_count_signups_and_confirmations() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let metrics = test_app.get_metrics().await;
+    assert_eq!(sample(&metrics, "subscription_signups_total"), Some(1.0));
+    assert_eq!(
+        sample(&metrics, "subscription_confirmations_total"),
+        Some(1.0)
+    );
+    assert_eq!(
+        sample(
+            &metrics,
+            r#"email_client_request_duration_seconds_count{outcome="success"}"#
+        ),
+        Some(1.0)
+    );
+}
+
+#[tokio::test]
+async fn metrics_count_email_provider_errors() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    let metrics = test_app.get_metrics().await;
+    assert_eq!(
+        sample(&metrics, r#"email_client_errors_total{kind="status"}"#),
+        Some(1.0)
+    );
+    assert_eq!(sample(&metrics, "subscription_signups_total"), Some(0.0));
+}
+
+#[tokio::test]
+async fn metrics_report_database_pool_utilisation() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let metrics = test_app.get_metrics().await;
+
+    // Assert
+    assert!(sample(&metrics, r#"db_pool_connections{state="idle"}"#).is_some());
+    assert!(sample(&metrics, r#"db_pool_connections{state="in_use"}"#).is_some());
+    assert_eq!(sample(&metrics, "db_pool_max_connections"), Some(10.0));
+}
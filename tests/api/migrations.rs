@@ -0,0 +1,430 @@
+use chrono::Utc;
+use entity::{subscription_lists, subscriptions, tenants};
+use migration::{DEFAULT_LIST_ID, DEFAULT_TENANT_ID, Migrator, MigratorTrait};
+use sea_orm::sea_query::{Alias, Asterisk, Expr, Query, SimpleExpr};
+use sea_orm::{ConnectionTrait, DatabaseConnection};
+use uuid::Uuid;
+use zero2prod_axum::configuration::{Settings, get_configuration};
+use zero2prod_axum::startup::Application;
+
+use crate::helper::{create_database, runs_on_postgres, use_fresh_database};
+
+const NORMALIZED_EMAIL_MIGRATION: &str = "m20250608_100000_add_normalized_email";
+const ASCII_EMAIL_MIGRATION: &str = "m20250615_090000_add_ascii_email";
+const TENANTS_MIGRATION: &str = "m20250622_090000_create_tenants_table";
+
+#[tokio::test]
+async fn normalized_email_migration_backfills_existing_subscribers() {
+    // Arrange
+    let db_connection = migrate_up_to(NORMALIZED_EMAIL_MIGRATION).await;
+    insert_subscriber(&db_connection, "Ursula@Example.COM").await;
+
+    // Act
+    Migrator::up(&db_connection, None)
+        .await
+        .expect("Failed to migrate database.");
+
+    // Assert
+    let row = db_connection
+        .query_one(sea_orm::Statement::from_string(
+            db_connection.get_database_backend(),
+            "SELECT normalized_email FROM subscriptions",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let normalized_email: String = row.try_get("", "normalized_email").unwrap();
+
+    assert_eq!(normalized_email, "ursula@example.com");
+}
+
+#[tokio::test]
+async fn normalized_email_migration_reports_existing_duplicates() {
+    // Arrange
+    let db_connection = migrate_up_to(NORMALIZED_EMAIL_MIGRATION).await;
+    insert_subscriber(&db_connection, "foo@example.com").await;
+    insert_subscriber(&db_connection, "Foo@Example.com").await;
+    insert_subscriber(&db_connection, "bar@example.com").await;
+
+    // Act
+    let result = Migrator::up(&db_connection, None).await;
+
+    // Assert
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("foo@example.com"), "{}", error);
+    assert!(error.contains("Foo@Example.com"), "{}", error);
+    assert!(!error.contains("bar@example.com"), "{}", error);
+}
+
+#[tokio::test]
+async fn ascii_email_migration_keeps_both_forms_of_existing_addresses() {
+    // Arrange
+    let db_connection = migrate_up_to(ASCII_EMAIL_MIGRATION).await;
+    insert_subscriber_with(
+        &db_connection,
+        "Ursula@xn--bcher-kva.de",
+        vec![(
+            subscriptions::Column::NormalizedEmail,
+            "ursula@xn--bcher-kva.de".into(),
+        )],
+    )
+    .await;
+
+    // Act
+    Migrator::up(&db_connection, None)
+        .await
+        .expect("Failed to migrate database.");
+
+    // Assert
+    let row = db_connection
+        .query_one(sea_orm::Statement::from_string(
+            db_connection.get_database_backend(),
+            "SELECT email, ascii_email FROM subscriptions",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let email: String = row.try_get("", "email").unwrap();
+    let ascii_email: String = row.try_get("", "ascii_email").unwrap();
+
+    assert_eq!(email, "Ursula@bücher.de");
+    assert_eq!(ascii_email, "Ursula@xn--bcher-kva.de");
+}
+
+#[tokio::test]
+async fn ascii_email_migration_brings_normalized_addresses_to_nfc() {
+    // Arrange
+    let db_connection = migrate_up_to(ASCII_EMAIL_MIGRATION).await;
+    // As backfilled before, with the local part as typed
+    insert_subscriber_with(
+        &db_connection,
+        "Jose\u{301}@example.com",
+        vec![(
+            subscriptions::Column::NormalizedEmail,
+            "jose\u{301}@example.com".into(),
+        )],
+    )
+    .await;
+
+    // Act
+    Migrator::up(&db_connection, None)
+        .await
+        .expect("Failed to migrate database.");
+
+    // Assert
+    let row = db_connection
+        .query_one(sea_orm::Statement::from_string(
+            db_connection.get_database_backend(),
+            "SELECT email, normalized_email FROM subscriptions",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    let email: String = row.try_get("", "email").unwrap();
+    let normalized_email: String = row.try_get("", "normalized_email").unwrap();
+
+    assert_eq!(email, "Jos\u{e9}@example.com");
+    assert_eq!(normalized_email, "jos\u{e9}@example.com");
+}
+
+#[tokio::test]
+async fn ascii_email_migration_reports_addresses_only_differing_by_encoding() {
+    // Arrange
+    let db_connection = migrate_up_to(ASCII_EMAIL_MIGRATION).await;
+    for (email, normalized_email) in [
+        ("jose\u{301}@example.com", "jose\u{301}@example.com"),
+        ("jos\u{e9}@example.com", "jos\u{e9}@example.com"),
+    ] {
+        insert_subscriber_with(
+            &db_connection,
+            email,
+            vec![(
+                subscriptions::Column::NormalizedEmail,
+                normalized_email.into(),
+            )],
+        )
+        .await;
+    }
+
+    // Act
+    let result = Migrator::up(&db_connection, None).await;
+
+    // Assert
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("jose\u{301}@example.com"), "{}", error);
+    assert!(error.contains("jos\u{e9}@example.com"), "{}", error);
+}
+
+#[tokio::test]
+async fn tenants_migration_moves_existing_data_into_the_default_tenant() {
+    // Arrange
+    let db_connection = migrate_up_to(TENANTS_MIGRATION).await;
+    insert_subscribed_subscriber(&db_connection, "ursula@example.com").await;
+
+    // Act
+    Migrator::up(&db_connection, None)
+        .await
+        .expect("Failed to migrate database.");
+
+    // Assert
+    for table in ["lists", "subscriptions", "users", "audit_events"] {
+        let outside_default_tenant = Query::select()
+            .expr_as(Expr::col(Asterisk).count(), Alias::new("count"))
+            .from(Alias::new(table))
+            .and_where(Expr::col(Alias::new("tenant_id")).ne(DEFAULT_TENANT_ID))
+            .to_owned();
+        let row = db_connection
+            .query_one(
+                db_connection
+                    .get_database_backend()
+                    .build(&outside_default_tenant),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        let count: i64 = row.try_get("", "count").unwrap();
+        assert_eq!(count, 0, "{} has rows outside the default tenant", table);
+    }
+    // Rebuilding tables on SQLite must not cascade to the rows referencing them
+    assert_eq!(count_memberships(&db_connection).await, 1);
+}
+
+#[tokio::test]
+async fn tenants_migration_reverts_while_only_the_default_tenant_exists() {
+    // Arrange
+    let db_connection = migrate_up_to(TENANTS_MIGRATION).await;
+    insert_subscribed_subscriber(&db_connection, "ursula@example.com").await;
+    Migrator::up(&db_connection, Some(1))
+        .await
+        .expect("Failed to migrate database.");
+
+    // Act
+    Migrator::down(&db_connection, Some(1))
+        .await
+        .expect("Failed to revert migration.");
+
+    // Assert
+    assert_eq!(count_memberships(&db_connection).await, 1);
+    let pending = Migrator::get_pending_migrations(&db_connection)
+        .await
+        .unwrap();
+    assert_eq!(pending[0].name(), TENANTS_MIGRATION);
+}
+
+#[tokio::test]
+async fn tenants_migration_refuses_to_revert_with_other_tenants() {
+    // Arrange
+    let db_connection = migrate_up_to(TENANTS_MIGRATION).await;
+    Migrator::up(&db_connection, Some(1))
+        .await
+        .expect("Failed to migrate database.");
+    let insert = Query::insert()
+        .into_table(tenants::Entity)
+        .columns([tenants::Column::Id, tenants::Column::Slug])
+        .values_panic([Uuid::new_v4().into(), "acme".into()])
+        .to_owned();
+    db_connection
+        .execute(db_connection.get_database_backend().build(&insert))
+        .await
+        .unwrap();
+
+    // Act
+    let result = Migrator::down(&db_connection, Some(1)).await;
+
+    // Assert
+    let error = result.unwrap_err().to_string();
+    assert!(error.contains("acme"), "{}", error);
+}
+
+#[tokio::test]
+async fn startup_applies_pending_migrations() {
+    // Arrange
+    let configuration = startup_migrations_configuration();
+    let db_connection = create_database(&configuration.database).await;
+
+    // Act
+    Application::build(configuration)
+        .await
+        .expect("Failed to build application");
+
+    // Assert
+    let pending = Migrator::get_pending_migrations(&db_connection)
+        .await
+        .unwrap();
+    assert!(pending.is_empty());
+}
+
+#[tokio::test]
+async fn replicas_starting_together_migrate_once() {
+    // Replicas share a database server, an in-memory SQLite database cannot stand in for it
+    if !runs_on_postgres() {
+        return;
+    }
+
+    // Arrange
+    let configuration = startup_migrations_configuration();
+    let db_connection = create_database(&configuration.database).await;
+    let mut other_replica = startup_migrations_configuration();
+    other_replica.database.database_name = configuration.database.database_name.clone();
+
+    // Act
+    let (first, second) = tokio::join!(
+        Application::build(configuration),
+        Application::build(other_replica)
+    );
+
+    // Assert
+    assert!(first.is_ok());
+    assert!(second.is_ok());
+    let applied = Migrator::get_applied_migrations(&db_connection)
+        .await
+        .unwrap();
+    assert_eq!(applied.len(), Migrator::migrations().len());
+}
+
+#[tokio::test]
+async fn startup_refuses_a_schema_ahead_of_the_build() {
+    // Arrange
+    let configuration = startup_migrations_configuration();
+    let db_connection = create_database(&configuration.database).await;
+    Migrator::up(&db_connection, None)
+        .await
+        .expect("Failed to migrate database.");
+    db_connection
+        .execute_unprepared(
+            "INSERT INTO seaql_migrations (version, applied_at) \
+            VALUES ('m20991231_000000_from_the_future', 0)",
+        )
+        .await
+        .unwrap();
+
+    // Act
+    let result = Application::build(configuration).await;
+
+    // Assert
+    let error = result.err().expect("The application started").to_string();
+    assert!(
+        error.contains("m20991231_000000_from_the_future"),
+        "{}",
+        error
+    );
+}
+
+/// Settings of an application migrating its own, fresh, database on startup.
+fn startup_migrations_configuration() -> Settings {
+    let mut configuration = get_configuration().expect("Failed to read configuration");
+    use_fresh_database(&mut configuration.database);
+    configuration.application.port = 0;
+    configuration.application.run_migrations_on_startup = true;
+    configuration
+}
+
+/// Creates a database with every migration applied up to, but excluding, `name`.
+async fn migrate_up_to(name: &str) -> DatabaseConnection {
+    let mut settings = get_configuration()
+        .expect("Failed to read configuration")
+        .database;
+    use_fresh_database(&mut settings);
+
+    let db_connection = create_database(&settings).await;
+    let steps = Migrator::migrations()
+        .iter()
+        .position(|migration| migration.name() == name)
+        .expect("Unknown migration");
+
+    Migrator::up(&db_connection, Some(steps as u32))
+        .await
+        .expect("Failed to migrate database.");
+
+    db_connection
+}
+
+async fn insert_subscriber(db_connection: &DatabaseConnection, email: &str) {
+    insert_subscriber_with(db_connection, email, vec![]).await;
+}
+
+/// Inserts a subscriber with bound values, for both backends to store them their own way,
+/// along with the columns later migrations added.
+async fn insert_subscriber_with(
+    db_connection: &DatabaseConnection,
+    email: &str,
+    later_columns: Vec<(subscriptions::Column, SimpleExpr)>,
+) -> Uuid {
+    let id = Uuid::new_v4();
+    let mut columns = vec![
+        subscriptions::Column::Id,
+        subscriptions::Column::Email,
+        subscriptions::Column::Name,
+        subscriptions::Column::SubscribedAt,
+        subscriptions::Column::Status,
+    ];
+    let mut values: Vec<SimpleExpr> = vec![
+        id.into(),
+        email.into(),
+        "le guin".into(),
+        Utc::now().into(),
+        "confirmed".into(),
+    ];
+    for (column, value) in later_columns {
+        columns.push(column);
+        values.push(value);
+    }
+    let insert = Query::insert()
+        .into_table(subscriptions::Entity)
+        .columns(columns)
+        .values_panic(values)
+        .to_owned();
+
+    db_connection
+        .execute(db_connection.get_database_backend().build(&insert))
+        .await
+        .expect("Failed to insert subscriber.");
+
+    id
+}
+
+/// Inserts a subscriber in the default list, as they were stored before tenants existed.
+async fn insert_subscribed_subscriber(db_connection: &DatabaseConnection, email: &str) {
+    let subscriber_id = insert_subscriber_with(
+        db_connection,
+        email,
+        vec![
+            (subscriptions::Column::NormalizedEmail, email.into()),
+            (subscriptions::Column::AsciiEmail, email.into()),
+        ],
+    )
+    .await;
+
+    let insert = Query::insert()
+        .into_table(subscription_lists::Entity)
+        .columns([
+            subscription_lists::Column::SubscriberId,
+            subscription_lists::Column::ListId,
+            subscription_lists::Column::Status,
+            subscription_lists::Column::SubscribedAt,
+        ])
+        .values_panic([
+            subscriber_id.into(),
+            DEFAULT_LIST_ID.into(),
+            "confirmed".into(),
+            Utc::now().into(),
+        ])
+        .to_owned();
+    db_connection
+        .execute(db_connection.get_database_backend().build(&insert))
+        .await
+        .expect("Failed to insert list membership.");
+}
+
+async fn count_memberships(db_connection: &DatabaseConnection) -> i64 {
+    db_connection
+        .query_one(sea_orm::Statement::from_string(
+            db_connection.get_database_backend(),
+            "SELECT COUNT(*) AS count FROM subscription_lists",
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get("", "count")
+        .unwrap()
+}
@@ -38,6 +38,33 @@ async fn newsletters_returns_400_for_invalid_data() {
     }
 }
 
+#[tokio::test]
+async fn requests_missing_authorization_are_rejected() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/newsletters", &test_app.address))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>"
+            }
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(401, response.status().as_u16());
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
+
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     // Arrange
@@ -94,6 +121,254 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     // Mock verifies on Drop that the newsletter email has been sent
 }
 
+#[tokio::test]
+async fn newsletters_contain_a_link_to_the_preferences_page() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    test_app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[1];
+    let links = test_app.get_preferences_links(email_request);
+
+    let response = reqwest::get(links.html).await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_are_only_delivered_to_the_targeted_lists() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.create_list("weekly").await;
+    create_confirmed_subscriber(&test_app).await;
+    create_confirmed_subscriber_from(
+        &test_app,
+        "name=ada&email=ada%40example.com&list=weekly".into(),
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "lists": ["weekly"]
+    });
+
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    // Mock verifies on Drop that only the weekly subscriber has received the issue
+}
+
+#[tokio::test]
+async fn newsletters_are_delivered_once_to_subscribers_of_several_targeted_lists() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.create_list("weekly").await;
+    create_confirmed_subscriber(&test_app).await;
+    create_confirmed_subscriber_from(
+        &test_app,
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&list=weekly".into(),
+    )
+    .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "lists": ["default", "weekly"]
+    });
+
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_accept_a_list_named_twice() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "lists": ["default", "default"]
+    });
+
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_an_unknown_list() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "lists": ["does-not-exist"]
+    });
+
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn newsletters_are_only_delivered_to_the_targeted_segment() {
+    // Arrange
+    let test_app = spawn_app().await;
+    for body in [
+        "name=ada&email=ada%40example.com&tags=beta",
+        "name=grace&email=grace%40example.com&tags=beta,staff",
+        "name=alan&email=alan%40example.com",
+    ] {
+        create_confirmed_subscriber_from(&test_app, body.into()).await;
+    }
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "segment": {
+            "include_tags": ["Beta"],
+            "exclude_tags": ["staff"]
+        }
+    });
+
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body = serde_json::from_slice::<serde_json::Value>(&email_request.body).unwrap();
+    assert_eq!(body["To"], "ada@example.com");
+}
+
+#[tokio::test]
+async fn newsletters_skip_subscribers_who_joined_before_the_segment_date() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_confirmed_subscriber(&test_app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "segment": {
+            "subscribed_after": chrono::Utc::now().to_rfc3339()
+        }
+    });
+
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn newsletters_returns_400_for_an_invalid_segment() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "segment": {
+            "include_tags": [" "]
+        }
+    });
+
+    let response = test_app.post_newsletters(newsletter_request_body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
 async fn create_unconfirmed_subscriber(test_app: &TestApp) -> ConfirmationLinks {
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
 
@@ -122,6 +397,36 @@ async fn create_unconfirmed_subscriber(test_app: &TestApp) -> ConfirmationLinks
     test_app.get_confirmation_links(email_request)
 }
 
+async fn create_confirmed_subscriber_from(test_app: &TestApp, body: String) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create confirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+
+    test_app
+        .post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    reqwest::get(test_app.get_confirmation_links(email_request).html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
 async fn create_confirmed_subscriber(test_app: &TestApp) {
     let confirmation_link = create_unconfirmed_subscriber(test_app).await;
 
@@ -0,0 +1,171 @@
+use reqwest::{Client, Method};
+use serde_json::{Map, Value, json};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{TestApp, spawn_app};
+
+async fn get_openapi(test_app: &TestApp) -> Value {
+    let response = Client::new()
+        .get(format!("{}/api-docs/openapi.json", &test_app.address))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status().as_u16(), 200);
+
+    response.json().await.unwrap()
+}
+
+/// Every `(method, path)` the document describes.
+fn operations(openapi: &Value) -> Vec<(Method, String)> {
+    openapi["paths"]
+        .as_object()
+        .unwrap()
+        .iter()
+        .flat_map(|(path, item)| {
+            item.as_object()
+                .unwrap()
+                .keys()
+                .filter_map(|method| method.to_uppercase().parse().ok())
+                .map(move |method| (method, path.clone()))
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn openapi_document_describes_the_public_api() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let openapi = get_openapi(&test_app).await;
+
+    // Assert
+    assert!(openapi["openapi"].as_str().unwrap().starts_with("3.1"));
+    let mut operations = operations(&openapi);
+    operations.sort_by(|a, b| (&a.1, a.0.as_str()).cmp(&(&b.1, b.0.as_str())));
+    assert_eq!(
+        operations,
+        vec![
+            (Method::POST, "/newsletters".to_string()),
+            (Method::POST, "/subscriptions".to_string()),
+            (Method::GET, "/subscriptions/confirm".to_string()),
+            (Method::GET, "/subscriptions/form-token".to_string()),
+        ]
+    );
+    assert_eq!(
+        openapi["components"]["securitySchemes"]["basic_auth"]["scheme"],
+        "basic"
+    );
+}
+
+#[tokio::test]
+async fn every_documented_operation_is_routed() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let openapi = get_openapi(&test_app).await;
+
+    for (method, path) in operations(&openapi) {
+        // Act
+        let response = Client::new()
+            .request(method.clone(), format!("{}{}", &test_app.address, path))
+            .send()
+            .await
+            .expect("Failed to execute request.");
+
+        // Assert
+        let status = response.status().as_u16();
+        assert!(
+            status != 404 && status != 405,
+            "{} {} is documented but answered {}.",
+            method,
+            path,
+            status
+        );
+    }
+}
+
+#[tokio::test]
+async fn documented_error_responses_are_problem_details() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let openapi = get_openapi(&test_app).await;
+
+    for (method, path) in operations(&openapi) {
+        let responses = &openapi["paths"][&path][method.as_str().to_lowercase()]["responses"];
+
+        for (status, response) in responses.as_object().unwrap() {
+            if status.starts_with('2') {
+                continue;
+            }
+            // Assert
+            assert_eq!(
+                response["content"]["application/problem+json"]["schema"]["$ref"],
+                "#/components/schemas/Problem",
+                "{} {} documents a {} that is not a problem.",
+                method,
+                path,
+                status
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn subscribe_requires_exactly_the_documented_fields() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let openapi = get_openapi(&test_app).await;
+    let schema = &openapi["components"]["schemas"]["SubscriberInfo"];
+    let required: Vec<&str> = schema["required"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|field| field.as_str().unwrap())
+        .collect();
+    let valid = |field: &str| match field {
+        "email" => json!("ursula_le_guin@gmail.com"),
+        "name" => json!("le guin"),
+        other => panic!("No valid value known for the required field {}.", other),
+    };
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    for missing in &required {
+        let body: Map<String, Value> = required
+            .iter()
+            .filter(|field| *field != missing)
+            .map(|field| (field.to_string(), valid(field)))
+            .collect();
+
+        // Act
+        let response = test_app.post_subscriptions_json(&Value::Object(body)).await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API accepted a subscription without the required {}.",
+            missing
+        );
+    }
+
+    // Act
+    let body: Map<String, Value> = required
+        .iter()
+        .map(|field| (field.to_string(), valid(field)))
+        .collect();
+    let response = test_app.post_subscriptions_json(&Value::Object(body)).await;
+
+    // Assert
+    assert_eq!(
+        response.status().as_u16(),
+        200,
+        "The API rejected a subscription with only the required fields."
+    );
+}
@@ -0,0 +1,452 @@
+use entity::prelude::{PreferenceTokens, SubscriptionLists, SubscriptionTokens, Subscriptions};
+use migration::DEFAULT_LIST_ID;
+use sea_orm::{EntityTrait, PaginatorTrait};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod_axum::routes::get_or_create_preference_token;
+
+use crate::helper::{TestApp, spawn_app};
+
+#[tokio::test]
+async fn preferences_without_token_are_rejected_with_a_400() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = reqwest::get(format!("{}/preferences", test_app.address))
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn preferences_with_an_unknown_token_are_rejected_with_a_401() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app.get_preferences("not-a-real-token").await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn preferences_page_shows_the_current_settings() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.create_list("weekly").await;
+    let preference_token = create_confirmed_subscriber(&test_app).await;
+
+    // Act
+    let response = test_app.get_preferences(&preference_token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"value="le guin""#));
+    assert!(html.contains(r#"value="default" checked"#));
+    assert!(html.contains(r#"value="weekly">"#));
+}
+
+#[tokio::test]
+async fn updating_preferences_changes_name_and_lists() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let list_id = test_app.create_list("weekly").await;
+    let preference_token = create_confirmed_subscriber(&test_app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_preferences(format!(
+            "preference_token={}&name=Ursula&lists=weekly",
+            preference_token
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = Subscriptions::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+
+    assert_eq!(saved.name, "Ursula");
+
+    let memberships = SubscriptionLists::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+
+    assert_eq!(memberships.len(), 2);
+    for membership in memberships {
+        if membership.list_id == list_id {
+            assert_eq!(membership.status, "pending_confirmation");
+        } else {
+            assert_eq!(membership.list_id, DEFAULT_LIST_ID);
+            assert_eq!(membership.status, "unsubscribed");
+        }
+    }
+    assert_eq!(saved.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn lists_joined_from_the_preferences_are_confirmed_by_email() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let list_id = test_app.create_list("weekly").await;
+    let preference_token = create_confirmed_subscriber(&test_app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+    test_app
+        .post_preferences(format!(
+            "preference_token={}&name=Ursula&lists=default&lists=weekly",
+            preference_token
+        ))
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = test_app.get_confirmation_links(&email_request);
+
+    // Act
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let membership = SubscriptionLists::find_by_id((get_subscriber(&test_app).await.id, list_id))
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+    assert_eq!(membership.status, "confirmed");
+}
+
+#[tokio::test]
+async fn keeping_confirmed_lists_sends_no_confirmation_email() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let preference_token = create_confirmed_subscriber(&test_app).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_preferences(format!(
+            "preference_token={}&name=Ursula&lists=default",
+            preference_token
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = get_subscriber(&test_app).await;
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn unchecking_every_list_unsubscribes_the_subscriber() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let preference_token = create_confirmed_subscriber(&test_app).await;
+
+    // Act
+    let response = test_app
+        .post_preferences(format!("preference_token={}&name=Ursula", preference_token))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = get_subscriber(&test_app).await;
+    assert_eq!(saved.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn updating_preferences_returns_a_400_for_invalid_data() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let preference_token = create_confirmed_subscriber(&test_app).await;
+    let test_cases = vec![
+        ("name=", "empty name"),
+        ("name=%7BUrsula%7D", "invalid name"),
+        ("name=Ursula&lists=does-not-exist", "unknown list"),
+    ];
+
+    for (body, error_message) in test_cases {
+        // Act
+        let response = test_app
+            .post_preferences(format!("preference_token={}&{}", preference_token, body))
+            .await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not return a 400 Bad Request when the payload was {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn unsubscribe_removes_the_subscriber_from_all_lists() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let preference_token = create_confirmed_subscriber(&test_app).await;
+
+    // Act
+    let response = test_app
+        .post_unsubscribe(format!("preference_token={}", preference_token))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let saved = Subscriptions::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+    let membership = SubscriptionLists::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+
+    assert_eq!(saved.status, "unsubscribed");
+    assert_eq!(membership.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn an_old_confirmation_link_does_not_resubscribe_an_unsubscribed_subscriber() {
+    // Arrange
+    let test_app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    let subscriber = get_subscriber(&test_app).await;
+    let preference_token = get_or_create_preference_token(&test_app.db_connection, subscriber.id)
+        .await
+        .expect("Failed to create a preference token.");
+    test_app
+        .post_unsubscribe(format!("preference_token={}", preference_token))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+
+    let membership = SubscriptionLists::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+
+    assert_eq!(get_subscriber(&test_app).await.status, "unsubscribed");
+    assert_eq!(membership.status, "unsubscribed");
+}
+
+#[tokio::test]
+async fn personal_data_requests_with_an_unknown_token_are_rejected_with_a_401() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let export = test_app.get_personal_data("not-a-real-token").await;
+    let erase = test_app
+        .post_erase("preference_token=not-a-real-token".into())
+        .await;
+
+    // Assert
+    assert_eq!(export.status().as_u16(), 401);
+    assert_eq!(erase.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn personal_data_export_contains_everything_stored_about_the_subscriber() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let preference_token = create_confirmed_subscriber(&test_app).await;
+
+    // Act
+    let response = test_app.get_personal_data(&preference_token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["Content-Disposition"],
+        r#"attachment; filename="personal-data.json""#
+    );
+
+    let personal_data: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(
+        personal_data["subscription"]["email"],
+        "ursula_le_guin@gmail.com"
+    );
+    assert_eq!(personal_data["subscription"]["status"], "confirmed");
+    assert_eq!(personal_data["consent"][0]["list"], "default");
+    assert_eq!(personal_data["consent"][0]["status"], "confirmed");
+    assert_eq!(personal_data["subscription_tokens"][0]["list"], "default");
+    assert_eq!(personal_data["preference_token"], preference_token);
+}
+
+#[tokio::test]
+async fn pending_subscribers_can_erase_their_data_from_the_confirmation_email() {
+    // Arrange
+    let test_app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let preferences_link = test_app.get_preferences_links(email_request).html;
+    let preference_token = preferences_link
+        .query_pairs()
+        .find(|(key, _)| key == "preference_token")
+        .map(|(_, value)| value.into_owned())
+        .unwrap();
+
+    // Act
+    let page = reqwest::get(preferences_link).await.unwrap();
+    let response = test_app
+        .post_erase(format!("preference_token={}", preference_token))
+        .await;
+
+    // Assert
+    assert_eq!(page.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 200);
+    let subscribers = Subscriptions::find()
+        .count(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+    assert_eq!(subscribers, 0);
+}
+
+#[tokio::test]
+async fn erasure_deletes_the_subscriber_and_all_their_tokens() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let preference_token = create_confirmed_subscriber(&test_app).await;
+
+    // Act
+    let response = test_app
+        .post_erase(format!("preference_token={}", preference_token))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let db_connection = &test_app.db_connection;
+    assert_eq!(Subscriptions::find().count(db_connection).await.unwrap(), 0);
+    assert_eq!(
+        SubscriptionLists::find()
+            .count(db_connection)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        SubscriptionTokens::find()
+            .count(db_connection)
+            .await
+            .unwrap(),
+        0
+    );
+    assert_eq!(
+        PreferenceTokens::find().count(db_connection).await.unwrap(),
+        0
+    );
+
+    let response = test_app.get_personal_data(&preference_token).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+async fn create_confirmed_subscriber(test_app: &TestApp) -> String {
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+
+    test_app
+        .post_subscriptions(body.into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let subscriber = Subscriptions::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+
+    get_or_create_preference_token(&test_app.db_connection, subscriber.id)
+        .await
+        .expect("Failed to create a preference token.")
+}
+
+async fn get_subscriber(test_app: &TestApp) -> entity::subscriptions::Model {
+    Subscriptions::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.")
+}
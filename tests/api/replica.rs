@@ -0,0 +1,150 @@
+use std::time::{Duration, Instant};
+
+use reqwest::Method;
+use sea_orm::{ConnectionTrait, DatabaseBackend, Statement};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod_axum::configuration::ReplicaSettings;
+use zero2prod_axum::startup::REPLICA_APPLICATION_NAME;
+
+use crate::helper::{TestApp, runs_on_postgres, spawn_app_with};
+
+/// Stands in for a replica with the primary itself, reached through another pool.
+async fn spawn_app_with_replica(port: u16) -> TestApp {
+    spawn_app_with(|configuration| {
+        configuration.database.replica = Some(ReplicaSettings {
+            host: configuration.database.host.clone(),
+            port,
+            acquire_timeout_milliseconds: 200,
+        });
+    })
+    .await
+}
+
+async fn replica_connections(test_app: &TestApp) -> i64 {
+    test_app
+        .db_connection
+        .query_one(Statement::from_sql_and_values(
+            DatabaseBackend::Postgres,
+            "SELECT count(*) FROM pg_stat_activity \
+            WHERE datname = current_database() AND application_name = $1",
+            [REPLICA_APPLICATION_NAME.into()],
+        ))
+        .await
+        .unwrap()
+        .unwrap()
+        .try_get_by_index(0)
+        .unwrap()
+}
+
+#[tokio::test]
+async fn admin_listings_are_read_from_the_replica() {
+    // Replicas are only supported on Postgres
+    if !runs_on_postgres() {
+        return;
+    }
+
+    // Arrange
+    let test_app = spawn_app_with_replica(5432).await;
+
+    // Act
+    let response = test_app
+        .admin_request(Method::GET, "/subscribers")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(replica_connections(&test_app).await > 0);
+}
+
+#[tokio::test]
+async fn reads_fall_back_to_the_primary_when_the_replica_is_down() {
+    // Replicas are only supported on Postgres
+    if !runs_on_postgres() {
+        return;
+    }
+
+    // Arrange
+    let test_app = spawn_app_with_replica(1).await;
+
+    // Act
+    let listing = test_app
+        .admin_request(Method::GET, "/subscribers")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    let start = Instant::now();
+    let export = test_app
+        .admin_request(Method::GET, "/subscribers/export")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(listing.status().as_u16(), 200);
+    assert_eq!(export.status().as_u16(), 200);
+    assert!(export.text().await.unwrap().starts_with("id,email"));
+    // The replica is skipped for a while rather than waited for on every read
+    assert!(start.elapsed() < Duration::from_millis(200));
+}
+
+#[tokio::test]
+async fn writes_never_go_to_the_replica() {
+    // Replicas are only supported on Postgres
+    if !runs_on_postgres() {
+        return;
+    }
+
+    // Arrange
+    let test_app = spawn_app_with_replica(5432).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(replica_connections(&test_app).await, 0);
+}
+
+#[tokio::test]
+async fn personal_data_exports_are_read_from_the_primary() {
+    // Replicas are only supported on Postgres
+    if !runs_on_postgres() {
+        return;
+    }
+
+    // Arrange
+    let test_app = spawn_app_with_replica(5432).await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let preferences_link = test_app.get_preferences_links(email_request).html;
+    let (_, preference_token) = preferences_link
+        .query_pairs()
+        .find(|(key, _)| key == "preference_token")
+        .unwrap();
+
+    // Act
+    let response = test_app.get_personal_data(&preference_token).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(replica_connections(&test_app).await, 0);
+}
@@ -0,0 +1,131 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use reqwest::Client;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+use crate::helper::{TestApp, spawn_app, spawn_app_with};
+
+/// Mounts an email API taking `delay` to answer, so that signup requests stay in flight.
+async fn mount_slow_email_server(test_app: &TestApp, delay: Duration) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(delay))
+        .mount(&test_app.email_server)
+        .await;
+}
+
+async fn wait_for_email_request(test_app: &TestApp) {
+    while test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty()
+    {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn shutdown_stops_accepting_connections() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    test_app.shutdown.trigger();
+    let result = tokio::time::timeout(Duration::from_secs(5), test_app.service)
+        .await
+        .expect("The application did not shut down");
+
+    // Assert
+    assert!(result.unwrap().is_ok());
+    let response = Client::new()
+        .get(format!("{}/health_check", test_app.address))
+        .send()
+        .await;
+    assert!(response.is_err());
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_in_flight_requests() {
+    // Arrange
+    let test_app = spawn_app().await;
+    mount_slow_email_server(&test_app, Duration::from_millis(500)).await;
+    let request = Client::new()
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send();
+    let request = tokio::spawn(request);
+    wait_for_email_request(&test_app).await;
+
+    // Act
+    test_app.shutdown.trigger();
+
+    // Assert
+    let response = request.await.unwrap().expect("The request was cut off");
+    assert_eq!(response.status().as_u16(), 200);
+    let result = tokio::time::timeout(Duration::from_secs(5), test_app.service)
+        .await
+        .expect("The application did not shut down");
+    assert!(result.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn shutdown_gives_up_on_requests_after_the_drain_timeout() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        configuration
+            .application
+            .shutdown
+            .drain_timeout_milliseconds = 200;
+    })
+    .await;
+    mount_slow_email_server(&test_app, Duration::from_secs(5)).await;
+    let request = Client::new()
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send();
+    tokio::spawn(request);
+    wait_for_email_request(&test_app).await;
+
+    // Act
+    let start = Instant::now();
+    test_app.shutdown.trigger();
+    let result = tokio::time::timeout(Duration::from_secs(3), test_app.service)
+        .await
+        .expect("The application waited past its drain timeout");
+
+    // Assert
+    assert!(result.unwrap().is_ok());
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_background_tasks() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let completed = Arc::new(AtomicBool::new(false));
+    let shutdown = test_app.shutdown.clone();
+    let task_completed = completed.clone();
+    test_app.shutdown.spawn(async move {
+        shutdown.triggered().await;
+        // Cleanup work done once the shutdown has started
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        task_completed.store(true, Ordering::SeqCst);
+    });
+
+    // Act
+    test_app.shutdown.trigger();
+    let result = tokio::time::timeout(Duration::from_secs(5), test_app.service)
+        .await
+        .expect("The application did not shut down");
+
+    // Assert
+    assert!(result.unwrap().is_ok());
+    assert!(completed.load(Ordering::SeqCst));
+}
@@ -1,9 +1,18 @@
-use entity::prelude::Subscriptions;
-use sea_orm::{ConnectionTrait, DbBackend, EntityTrait, Statement};
-use wiremock::matchers::{method, path};
-use wiremock::{Mock, ResponseTemplate};
+use std::time::Duration;
 
-use crate::helper::spawn_app;
+use chrono::Utc;
+use entity::prelude::{SubscriberTags, SubscriptionLists, Subscriptions};
+use migration::DEFAULT_LIST_ID;
+use sea_orm::{ConnectionTrait, EntityTrait, Statement};
+use secrecy::SecretString;
+use wiremock::matchers::{
+    any, body_partial_json, body_string_contains, header_regex, method, path,
+};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use zero2prod_axum::configuration::{BotProtectionSettings, CaptchaSettings, TokenBucketSettings};
+
+use crate::helper::{spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn test_subscribe_returns_200_for_valid_form_data() {
@@ -154,8 +163,8 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     test_app
         .db_connection
         .execute(Statement::from_string(
-            DbBackend::Postgres,
-            "ALTER TABLE subscriptions DROP COLUMN email;",
+            test_app.db_connection.get_database_backend(),
+            "ALTER TABLE subscriptions DROP COLUMN name;",
         ))
         .await
         .expect("Failed to drop column");
@@ -166,3 +175,954 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     // Assert
     assert_eq!(response.status().as_u16(), 500);
 }
+
+#[tokio::test]
+async fn subscribe_without_a_list_joins_the_default_list() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    let membership = SubscriptionLists::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+
+    assert_eq!(membership.list_id, DEFAULT_LIST_ID);
+    assert_eq!(membership.status, "pending_confirmation");
+}
+
+#[tokio::test]
+async fn subscribe_joins_the_requested_list() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let list_id = test_app.create_list("weekly").await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&list=weekly";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let memberships = SubscriptionLists::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+
+    assert_eq!(memberships.len(), 1);
+    assert_eq!(memberships[0].list_id, list_id);
+}
+
+#[tokio::test]
+async fn subscribing_to_a_second_list_reuses_the_subscriber() {
+    // Arrange
+    let test_app = spawn_app().await;
+    test_app.create_list("weekly").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    for body in [
+        "name=le%20guin&email=ursula_le_guin%40gmail.com",
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&list=weekly",
+    ] {
+        let response = test_app.post_subscriptions(body.into()).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    // Assert
+    let subscribers = Subscriptions::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+    let memberships = SubscriptionLists::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+
+    assert_eq!(subscribers.len(), 1);
+    assert_eq!(memberships.len(), 2);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_an_unknown_list() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&list=does-not-exist";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_persists_the_tags_of_the_signup_form() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com&tags=Beta%2C%20early-adopter";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app.post_subscriptions(body.into()).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let mut tags: Vec<String> = SubscriberTags::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .into_iter()
+        .map(|row| row.tag)
+        .collect();
+    tags.sort();
+
+    assert_eq!(tags, vec!["beta", "early-adopter"]);
+}
+
+#[tokio::test]
+async fn subscribing_again_leaves_the_tags_of_an_existing_subscriber_alone() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&tags=beta".into())
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = test_app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&tags=no-newsletter".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+
+    let tags: Vec<String> = SubscriberTags::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .into_iter()
+        .map(|row| row.tag)
+        .collect();
+
+    assert_eq!(tags, vec!["beta"]);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_invalid_tags() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let body = format!(
+        "name=le%20guin&email=ursula_le_guin%40gmail.com&tags={}",
+        "a".repeat(65)
+    );
+
+    // Act
+    let response = test_app.post_subscriptions(body).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_429_when_a_client_exceeds_its_rate_limit() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        configuration.application.rate_limit.per_ip = TokenBucketSettings {
+            capacity: 2,
+            refill_per_minute: 1,
+        };
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let mut statuses = vec![];
+    let mut retry_after = None;
+    for email in ["a", "b", "c"] {
+        let response = test_app
+            .post_subscriptions(format!("name=le%20guin&email={}%40gmail.com", email))
+            .await;
+        statuses.push(response.status().as_u16());
+        retry_after = response.headers().get("Retry-After").cloned();
+    }
+
+    // Assert
+    assert_eq!(statuses, [200, 200, 429]);
+    assert_eq!(retry_after.unwrap(), "60");
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_429_when_an_address_is_requested_too_often() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        configuration.application.rate_limit.per_email = TokenBucketSettings {
+            capacity: 1,
+            refill_per_minute: 1,
+        };
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let first = test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let repeated = test_app
+        .post_subscriptions("name=le%20guin&email=URSULA_LE_GUIN%40gmail.com".into())
+        .await;
+    let other = test_app
+        .post_subscriptions("name=le%20guin&email=another%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(repeated.status().as_u16(), 429);
+    assert_eq!(other.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_rate_limits_by_forwarded_address_behind_a_trusted_proxy() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        let rate_limit = &mut configuration.application.rate_limit;
+        rate_limit.trusted_proxies = vec!["127.0.0.1".parse().unwrap()];
+        rate_limit.per_ip = TokenBucketSettings {
+            capacity: 1,
+            refill_per_minute: 1,
+        };
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    let subscribe_from = |client: &'static str, email: &'static str| {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions", &test_app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", client)
+            .body(format!("name=le%20guin&email={}%40gmail.com", email))
+            .send()
+    };
+
+    // Act
+    let first = subscribe_from("198.51.100.1", "a").await.unwrap();
+    let same_client = subscribe_from("198.51.100.1", "b").await.unwrap();
+    let other_client = subscribe_from("198.51.100.2", "c").await.unwrap();
+
+    // Assert
+    assert_eq!(first.status().as_u16(), 200);
+    assert_eq!(same_client.status().as_u16(), 429);
+    assert_eq!(other_client.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_when_the_honeypot_is_filled_in() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&website=http%3A%2F%2Fspam.example"
+                .into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_when_the_form_is_filled_in_too_quickly() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        let bot_protection = &mut configuration.application.bot_protection;
+        bot_protection.min_form_fill_seconds = 5;
+        bot_protection.form_token_secret = "a-secret".into();
+    })
+    .await;
+    let form_token = test_app.get_form_token().await;
+    let started_long_ago = (Utc::now().timestamp() - 60).to_string();
+    let (_, signature) = form_token.split_once('.').unwrap();
+    let test_cases = vec![
+        (String::new(), "no form token"),
+        (
+            format!("&form_token={}", form_token),
+            "an instant submission",
+        ),
+        (
+            format!("&form_token={}", started_long_ago),
+            "an unsigned start time",
+        ),
+        (
+            format!("&form_token={}.{}", started_long_ago, signature),
+            "a start time moved back",
+        ),
+    ];
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    for (fields, error_message) in test_cases {
+        // Act
+        let response = test_app
+            .post_subscriptions(format!(
+                "name=le%20guin&email=ursula_le_guin%40gmail.com{}",
+                fields
+            ))
+            .await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not return a 400 Bad Request for {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribe_accepts_a_form_filled_in_at_human_speed() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        let bot_protection = &mut configuration.application.bot_protection;
+        bot_protection.min_form_fill_seconds = 1;
+        bot_protection.form_token_secret = "a-secret".into();
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    let form_token = test_app.get_form_token().await;
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions(format!(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&form_token={}",
+            form_token
+        ))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_accepts_clients_without_a_form_token_by_default() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        configuration.application.bot_protection = production_bot_protection();
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_verifies_the_captcha_response_with_the_provider() {
+    // Arrange
+    let captcha_server = MockServer::start().await;
+    let test_app = spawn_app_with(|configuration| {
+        configuration.application.bot_protection.captcha = Some(CaptchaSettings {
+            verification_url: format!("{}/siteverify", captcha_server.uri()),
+            secret: SecretString::from("captcha-secret"),
+            timeout_milliseconds: 1000,
+        });
+    })
+    .await;
+
+    Mock::given(path("/siteverify"))
+        .and(method("POST"))
+        .and(body_string_contains("secret=captcha-secret"))
+        .and(body_string_contains("response=human"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true
+        })))
+        .expect(1)
+        .mount(&captcha_server)
+        .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmail.com&h-captcha-response=human".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_when_the_captcha_is_missing_or_rejected() {
+    // Arrange
+    let captcha_server = MockServer::start().await;
+    let test_app = spawn_app_with(|configuration| {
+        configuration.application.bot_protection.captcha = Some(CaptchaSettings {
+            verification_url: captcha_server.uri(),
+            secret: SecretString::from("captcha-secret"),
+            timeout_milliseconds: 1000,
+        });
+    })
+    .await;
+    let test_cases = vec![
+        ("", "a missing CAPTCHA response"),
+        ("&captcha_response=robot", "a rejected CAPTCHA response"),
+    ];
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": false
+        })))
+        .mount(&captcha_server)
+        .await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    for (fields, error_message) in test_cases {
+        // Act
+        let response = test_app
+            .post_subscriptions(format!(
+                "name=le%20guin&email=ursula_le_guin%40gmail.com{}",
+                fields
+            ))
+            .await;
+
+        // Assert
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not return a 400 Bad Request for {}.",
+            error_message
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribe_suggests_a_correction_for_a_mistyped_domain() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmial.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 409);
+    assert_eq!(
+        response.headers()["Content-Type"],
+        "application/problem+json"
+    );
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/likely-typo");
+    assert_eq!(problem["detail"], "Did you mean ursula_le_guin@gmail.com?");
+}
+
+#[tokio::test]
+async fn subscribe_keeps_a_mistyped_looking_address_once_confirmed() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions(
+            "name=le%20guin&email=ursula_le_guin%40gmial.com&accept_email_as_typed=true".into(),
+        )
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = Subscriptions::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch saved subscription.")
+        .expect("No subscriber was saved");
+    assert_eq!(saved.email, "ursula_le_guin@gmial.com");
+}
+
+#[tokio::test]
+async fn subscribe_returns_a_400_for_a_disposable_address() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40mailinator.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_only_accepts_allowed_domains_when_configured() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        configuration.application.email_policy.allowed_domains = vec!["example.com".into()];
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let allowed = test_app
+        .post_subscriptions("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+    let other = test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(allowed.status().as_u16(), 200);
+    assert_eq!(other.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn subscribe_treats_addresses_differing_only_by_case_as_the_same_subscriber() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    for email in [
+        "ursula_le_guin%40gmail.com",
+        "%20Ursula_Le_Guin%40GMAIL.com",
+    ] {
+        test_app
+            .post_subscriptions(format!("name=le%20guin&email={}", email))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    // Assert
+    let saved = Subscriptions::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+
+    assert_eq!(saved.len(), 1);
+    assert_eq!(saved[0].email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved[0].normalized_email, "ursula_le_guin@gmail.com");
+}
+
+#[tokio::test]
+async fn subscribe_stores_both_forms_of_an_internationalized_domain() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_partial_json(
+            serde_json::json!({ "To": "ursula@xn--bcher-kva.de" }),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=le%20guin&email=ursula%40B%C3%BCcher.de".into())
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = Subscriptions::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No subscriber was saved");
+    assert_eq!(saved.email, "ursula@bücher.de");
+    assert_eq!(saved.ascii_email, "ursula@xn--bcher-kva.de");
+    assert_eq!(saved.normalized_email, "ursula@xn--bcher-kva.de");
+}
+
+#[tokio::test]
+async fn subscribe_rejects_unicode_local_parts_when_the_transport_lacks_smtputf8() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=jos%C3%A9&email=jos%C3%A9%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    assert!(response.text().await.unwrap().contains("SMTPUTF8"));
+}
+
+#[tokio::test]
+async fn subscribe_accepts_unicode_local_parts_when_the_transport_supports_smtputf8() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        configuration.email_client.supports_smtputf8 = true;
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(body_partial_json(
+            serde_json::json!({ "To": "josé@example.com" }),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=jos%C3%A9&email=jos%C3%A9%40example.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = Subscriptions::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No subscriber was saved");
+    assert_eq!(saved.email, "josé@example.com");
+}
+
+#[tokio::test]
+async fn subscribe_accepts_json_bodies() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions_json(&serde_json::json!({
+            "name": "le guin",
+            "email": "ursula_le_guin@gmail.com",
+            "tags": "scifi,fantasy"
+        }))
+        .await;
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+    let saved = Subscriptions::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No subscriber was saved");
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+    assert_eq!(saved.name, "le guin");
+    let tags = SubscriberTags::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch tags.");
+    assert_eq!(tags.len(), 2);
+}
+
+#[tokio::test]
+async fn subscribe_returns_problem_details_to_json_clients() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let test_cases = vec![
+        (
+            serde_json::json!({ "name": "le guin", "email": "definitely-not-an-email" }),
+            "invalid email",
+        ),
+        (
+            serde_json::json!({ "name": "le guin" }),
+            "missing the email",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        // Act
+        let response = test_app.post_subscriptions_json(&body).await;
+
+        // Assert
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not fail with 400 Bad Request when the payload was {}.",
+            description
+        );
+        assert_eq!(
+            response.headers()["Content-Type"],
+            "application/problem+json",
+            "The API did not answer with problem details when the payload was {}.",
+            description
+        );
+        let problem: serde_json::Value = response.json().await.unwrap();
+        assert!(
+            problem["detail"]
+                .as_str()
+                .is_some_and(|detail| !detail.is_empty()),
+            "The problem had no detail when the payload was {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribe_rejects_malformed_json_with_problem_details() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/json; charset=utf-8")
+        .body("{\"name\": \"le guin\",")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["status"], 400);
+    assert!(problem["detail"].is_string());
+}
+
+#[tokio::test]
+async fn subscribe_reports_every_invalid_field() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=%20&email=definitely-not-an-email".into())
+        .await;
+
+    // Assert
+    assert_eq!(400, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["type"], "/problems/validation-error");
+    let fields: Vec<&str> = problem["errors"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|error| error["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, vec!["email", "name"]);
+    assert!(
+        problem["instance"]
+            .as_str()
+            .is_some_and(|instance| !instance.is_empty())
+    );
+}
+
+#[tokio::test]
+async fn subscribe_does_not_expose_internal_errors() {
+    // Arrange
+    let test_app = spawn_app().await;
+    // Sabotage the database
+    test_app
+        .db_connection
+        .execute(Statement::from_string(
+            test_app.db_connection.get_database_backend(),
+            "DROP TABLE subscription_tokens;",
+        ))
+        .await
+        .unwrap();
+
+    // Act
+    let response = test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    // Assert
+    assert_eq!(500, response.status().as_u16());
+    let problem: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(problem["title"], "Internal Server Error");
+    assert!(problem.get("detail").is_none());
+}
+
+#[tokio::test]
+async fn subscribe_propagates_the_trace_context_to_the_email_provider() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(header_regex(
+            "traceparent",
+            &format!("^00-{}-[0-9a-f]{{16}}-01$", trace_id),
+        ))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions", &test_app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", trace_id),
+        )
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    // Assert
+    assert_eq!(200, response.status().as_u16());
+}
+
+/// Bot protection as deployed, from the configuration files without the local overrides.
+fn production_bot_protection() -> BotProtectionSettings {
+    config::Config::builder()
+        .add_source(config::File::with_name("configuration/base.yaml"))
+        .add_source(config::File::with_name("configuration/production.yaml"))
+        .build()
+        .expect("Failed to read configuration")
+        .get("application.bot_protection")
+        .expect("Failed to read the bot protection settings")
+}
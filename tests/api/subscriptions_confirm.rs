@@ -1,7 +1,9 @@
-use entity::prelude::Subscriptions;
+use entity::prelude::{SubscriptionLists, Subscriptions};
+use migration::DEFAULT_LIST_ID;
 use sea_orm::EntityTrait;
 use wiremock::matchers::{method, path};
 use wiremock::{Mock, ResponseTemplate};
+use zero2prod_axum::routes::get_or_create_preference_token;
 
 use crate::helper::spawn_app;
 
@@ -78,3 +80,97 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn confirming_a_list_leaves_other_lists_pending() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let list_id = test_app.create_list("weekly").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com&list=weekly".into())
+        .await;
+
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+
+    // Act
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // Assert
+    let memberships = SubscriptionLists::find()
+        .all(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.");
+
+    for membership in memberships {
+        if membership.list_id == list_id {
+            assert_eq!(membership.status, "confirmed");
+        } else {
+            assert_eq!(membership.list_id, DEFAULT_LIST_ID);
+            assert_eq!(membership.status, "pending_confirmation");
+        }
+    }
+}
+
+#[tokio::test]
+async fn confirming_a_list_the_subscriber_has_left_returns_a_401() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    test_app
+        .post_subscriptions("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+
+    let subscriber = Subscriptions::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+    let preference_token = get_or_create_preference_token(&test_app.db_connection, subscriber.id)
+        .await
+        .expect("Failed to create a preference token.");
+    // Leaving every list, the default one included
+    test_app
+        .post_preferences(format!(
+            "preference_token={}&name=le%20guin",
+            preference_token
+        ))
+        .await
+        .error_for_status()
+        .unwrap();
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+
+    let membership = SubscriptionLists::find()
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+        .expect("No data received.");
+    assert_eq!(membership.status, "unsubscribed");
+}
@@ -0,0 +1,391 @@
+use entity::prelude::Subscriptions;
+use entity::subscriptions;
+use migration::DEFAULT_TENANT_ID;
+use reqwest::{Client, Method, Response};
+use sea_orm::ConnectionTrait;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use secrecy::SecretString;
+use uuid::Uuid;
+use wiremock::matchers::{header, method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod_axum::cli::{NewTenant, create_tenant};
+use zero2prod_axum::configuration::TokenBucketSettings;
+
+use crate::helper::{TestApp, TestUser, spawn_app_with};
+
+const ACME_HOST: &str = "news.acme.example.com";
+const ACME_SENDER: &str = "news@acme.example.com";
+const ACME_TOKEN: &str = "acme-token";
+
+#[tokio::test]
+async fn subscribing_under_a_tenant_prefix_sends_from_the_tenant() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_acme(&test_app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(header("X-Postmark-Server-Token", ACME_TOKEN))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = post_subscriptions(&test_app, "/tenants/acme", None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["From"], ACME_SENDER);
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+    assert_eq!(
+        confirmation_links.html.path(),
+        "/tenants/acme/subscriptions/confirm"
+    );
+}
+
+#[tokio::test]
+async fn confirmation_links_of_a_tenant_confirm_its_subscriber() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let acme_id = create_acme(&test_app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    post_subscriptions(&test_app, "/tenants/acme", None).await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+
+    // Act
+    let response = reqwest::get(confirmation_links.html).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    let subscriber = find_subscriber(&test_app, acme_id).await.unwrap();
+    assert_eq!(subscriber.status, "confirmed");
+}
+
+#[tokio::test]
+async fn confirmation_tokens_are_unknown_to_other_tenants() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_acme(&test_app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    post_subscriptions(&test_app, "/tenants/acme", None).await;
+    let email_request = &test_app.email_server.received_requests().await.unwrap()[0];
+    let mut confirmation_link = test_app.get_confirmation_links(email_request).html;
+
+    // Act
+    confirmation_link.set_path("/subscriptions/confirm");
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_tenant_is_resolved_from_the_host() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let acme_id = create_acme(&test_app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .and(header("X-Postmark-Server-Token", ACME_TOKEN))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = post_subscriptions(&test_app, "", Some(ACME_HOST)).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(find_subscriber(&test_app, acme_id).await.is_some());
+    assert!(
+        find_subscriber(&test_app, DEFAULT_TENANT_ID)
+            .await
+            .is_none()
+    );
+}
+
+#[tokio::test]
+async fn unknown_tenant_prefixes_are_rejected_with_a_404() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    let response = post_subscriptions(&test_app, "/tenants/nobody", None).await;
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn the_same_email_can_subscribe_to_several_tenants() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let acme_id = create_acme(&test_app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let default_response = post_subscriptions(&test_app, "", None).await;
+    let acme_response = post_subscriptions(&test_app, "/tenants/acme", None).await;
+
+    // Assert
+    assert_eq!(default_response.status().as_u16(), 200);
+    assert_eq!(acme_response.status().as_u16(), 200);
+    assert!(
+        find_subscriber(&test_app, DEFAULT_TENANT_ID)
+            .await
+            .is_some()
+    );
+    assert!(find_subscriber(&test_app, acme_id).await.is_some());
+}
+
+#[tokio::test]
+async fn addresses_are_rate_limited_within_each_tenant() {
+    // Arrange
+    let test_app = spawn_app_with(|configuration| {
+        configuration
+            .email_client
+            .accounts
+            .insert("acme".into(), SecretString::from(ACME_TOKEN));
+        configuration.application.rate_limit.per_email = TokenBucketSettings {
+            capacity: 1,
+            refill_per_minute: 1,
+        };
+    })
+    .await;
+    create_acme(&test_app).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let default_response = post_subscriptions(&test_app, "", None).await;
+    let acme_response = post_subscriptions(&test_app, "/tenants/acme", None).await;
+    let acme_retry = post_subscriptions(&test_app, "/tenants/acme", None).await;
+
+    // Assert
+    assert_eq!(default_response.status().as_u16(), 200);
+    assert_eq!(acme_response.status().as_u16(), 200);
+    assert_eq!(acme_retry.status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn users_cannot_sign_in_to_other_tenants() {
+    // Arrange
+    let test_app = spawn_app().await;
+    create_acme(&test_app).await;
+
+    // Act
+    let response = Client::new()
+        .get(format!(
+            "{}/tenants/acme/admin/api/subscribers",
+            test_app.address
+        ))
+        .basic_auth(
+            &test_app.test_user.username,
+            Some(&test_app.test_user.password),
+        )
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn admins_only_see_the_subscribers_of_their_tenant() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let acme_id = create_acme(&test_app).await;
+    let acme_user = TestUser::generate();
+    acme_user.store(&test_app.db_connection, acme_id).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&test_app.email_server)
+        .await;
+    post_subscriptions(&test_app, "", None).await;
+
+    // Act
+    let page: serde_json::Value = Client::new()
+        .request(
+            Method::GET,
+            format!("{}/tenants/acme/admin/api/subscribers", test_app.address),
+        )
+        .basic_auth(&acme_user.username, Some(&acme_user.password))
+        .send()
+        .await
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    // Assert
+    assert!(page["subscribers"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn newsletters_are_only_delivered_to_the_subscribers_of_the_tenant() {
+    // Arrange
+    let test_app = spawn_app().await;
+    let acme_id = create_acme(&test_app).await;
+    let acme_user = TestUser::generate();
+    acme_user.store(&test_app.db_connection, acme_id).await;
+    create_confirmed_subscriber(&test_app, "").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&test_app.email_server)
+        .await;
+
+    // Act
+    let response = Client::new()
+        .post(format!("{}/tenants/acme/newsletters", test_app.address))
+        .basic_auth(&acme_user.username, Some(&acme_user.password))
+        .json(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>"
+            }
+        }))
+        .send()
+        .await
+        .unwrap();
+
+    // Assert
+    assert_eq!(response.status().as_u16(), 200);
+    // Mock verifies on Drop that the subscriber of the default tenant got nothing
+}
+
+#[tokio::test]
+async fn tenant_email_tokens_are_not_stored_in_the_database() {
+    // Arrange
+    let test_app = spawn_app().await;
+
+    // Act
+    create_acme(&test_app).await;
+
+    // Assert
+    let row = test_app
+        .db_connection
+        .query_one(sea_orm::Statement::from_string(
+            test_app.db_connection.get_database_backend(),
+            "SELECT * FROM tenants WHERE slug = 'acme'",
+        ))
+        .await
+        .unwrap()
+        .unwrap();
+    for column in row.column_names() {
+        let value: Option<String> = row.try_get("", &column).unwrap_or_default();
+        assert_ne!(
+            value.as_deref(),
+            Some(ACME_TOKEN),
+            "{} holds the token",
+            column
+        );
+    }
+}
+
+/// Spawns the application with the email account of the `acme` tenant configured.
+async fn spawn_app() -> TestApp {
+    spawn_app_with(|configuration| {
+        configuration
+            .email_client
+            .accounts
+            .insert("acme".into(), SecretString::from(ACME_TOKEN));
+    })
+    .await
+}
+
+/// Creates the `acme` tenant, with a host, sender and email account of its own.
+async fn create_acme(test_app: &TestApp) -> Uuid {
+    create_tenant(
+        &test_app.db_connection,
+        NewTenant {
+            slug: "acme".into(),
+            host: Some(ACME_HOST.into()),
+            base_url: None,
+            sender_email: Some(ACME_SENDER.into()),
+            email_account: Some("acme".into()),
+        },
+    )
+    .await
+    .expect("Failed to create tenant.")
+}
+
+/// Subscribes the same person through `prefix`, addressed to `host` when given.
+async fn post_subscriptions(test_app: &TestApp, prefix: &str, host: Option<&str>) -> Response {
+    let mut request = Client::new()
+        .post(format!("{}{}/subscriptions", test_app.address, prefix))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com");
+    if let Some(host) = host {
+        request = request.header("Host", host);
+    }
+
+    request.send().await.expect("Failed to execute request.")
+}
+
+async fn create_confirmed_subscriber(test_app: &TestApp, prefix: &str) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&test_app.email_server)
+        .await;
+
+    post_subscriptions(test_app, prefix, None)
+        .await
+        .error_for_status()
+        .unwrap();
+    let email_request = &test_app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let confirmation_links = test_app.get_confirmation_links(email_request);
+
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+async fn find_subscriber(test_app: &TestApp, tenant_id: Uuid) -> Option<subscriptions::Model> {
+    Subscriptions::find()
+        .filter(subscriptions::Column::TenantId.eq(tenant_id))
+        .one(&test_app.db_connection)
+        .await
+        .expect("Failed to fetch data.")
+}